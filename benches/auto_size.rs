@@ -0,0 +1,52 @@
+// auto-sizing a render: running the program twice (once to measure it, once to draw it) against recording it once
+// and replaying the recording onto a canvas of the right size, which is what `run` does
+use penplot::canvas::{PixelCanvas, RecordingCanvas};
+use penplot::instruction::Instruction;
+use penplot::parsing;
+use penplot::program_state::{self, ProgramState};
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// how many times each way is timed, keeping the fastest
+const RUNS: usize = 5;
+
+fn fastest<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        black_box(f());
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn run_twice(commands: &[Instruction]) -> PixelCanvas {
+    let (width, height, x_offset, y_offset) = program_state::measure(commands, None).unwrap();
+    let mut program = ProgramState::new(PixelCanvas::new(width, height, x_offset, y_offset));
+    program.execute(commands).unwrap();
+    program.into_canvas()
+}
+
+fn record_and_replay(commands: &[Instruction]) -> PixelCanvas {
+    let mut program = ProgramState::new(RecordingCanvas::new());
+    program.execute(commands).unwrap();
+    let recording = program.into_canvas();
+    let ((width, height), (x_offset, y_offset)) = (recording.dimensions(), recording.offsets());
+    let mut canvas = PixelCanvas::new(width, height, x_offset, y_offset);
+    recording.replay(&mut canvas);
+    canvas
+}
+
+fn main() {
+    let spec = fs::read_to_string("examples/l_system/dragon_curve.txt").expect("benchmarks run from the crate root");
+    let (_, l_system) = parsing::parse_l_system(&spec).unwrap();
+    for iterations in [10, 14, 16] {
+        let commands = l_system.run(iterations);
+        let mut program = ProgramState::new(RecordingCanvas::new());
+        program.execute(&commands).unwrap();
+        let (calls, bytes) = program.canvas().recorded();
+        println!(
+            "dragon curve, {} iterations ({} instructions): run twice {:?}, record and replay {:?}, recording {} calls in {} KiB",
+            iterations, commands.len(), fastest(|| run_twice(&commands)), fastest(|| record_and_replay(&commands)), calls, bytes / 1024
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// expanding the koch curve L system, against the clone-per-instruction expansion it used to do
+// the depth defaults to 12 (about 490 million instructions), and a smaller one can be passed as in `cargo bench --bench koch -- 10`
+use penplot::bytecode;
+use penplot::instruction::Instruction;
+use penplot::l_system::{LSystem, RuleKey};
+use penplot::parsing;
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// how many times each way is timed, keeping the fastest
+const RUNS: usize = 5;
+
+fn fastest<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        black_box(f());
+        start.elapsed()
+    }).min().unwrap()
+}
+
+// what advance did before it copied rule bodies by slice: grow the result as it goes, cloning one instruction at a time
+fn clone_expansion(l_system: &LSystem, depth: usize) -> Vec<Instruction> {
+    let mut acc = l_system.seed.clone();
+    for _ in 0..depth {
+        let mut result = vec![];
+        for item in &acc {
+            if let Some(rule) = l_system.rules.get(&RuleKey::new(item.clone())) {
+                result.extend(rule.iter().cloned());
+            } else {
+                result.push(item.clone());
+            }
+        }
+        acc = result;
+    }
+    acc
+}
+
+fn main() {
+    // cargo passes its own flags along too, so take the first argument that's a number
+    let depth = env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(12);
+    let spec = fs::read_to_string("examples/l_system/koch_curve.txt").expect("benchmarks run from the crate root");
+    let (_, l_system) = parsing::parse_l_system(&spec).unwrap();
+    // the old expansion only knew about plain rules, so it's only a fair comparison for a system without the others
+    assert!(l_system.random_rules.is_empty() && l_system.parametric_rules.is_empty() && l_system.parametric_aliases.is_empty() && l_system.aliases.is_none());
+    assert!(l_system.rules.keys().all(|key| !key.has_context()));
+    let commands = l_system.run(depth);
+    let old = clone_expansion(&l_system, depth);
+    assert!(bytecode::encode_program(&commands) == bytecode::encode_program(&old), "expansion differs from the clone-based one");
+    drop(old);
+    println!(
+        "koch curve, depth {} ({} instructions): slices {:?}, clones {:?}",
+        depth, commands.len(), fastest(|| l_system.run(depth)), fastest(|| clone_expansion(&l_system, depth))
+    );
+}
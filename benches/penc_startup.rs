@@ -0,0 +1,34 @@
+// loading a big program: parsing its text against decoding it from .penc bytecode
+use penplot::bytecode;
+use penplot::parsing;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// how many times each way is timed, keeping the fastest
+const RUNS: usize = 5;
+
+fn fastest<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        black_box(f());
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    let spec = fs::read_to_string("examples/l_system/dragon_curve.txt").expect("benchmarks run from the crate root");
+    let (_, l_system) = parsing::parse_l_system(&spec).unwrap();
+    for iterations in [10, 14, 16] {
+        let commands = l_system.run(iterations);
+        let text = commands.iter().map(|inst| inst.to_string()).collect::<Vec<_>>().join("\n");
+        let bytes = bytecode::encode_program(&commands);
+        assert!(bytecode::decode_program(&bytes).unwrap() == commands);
+        assert!(parsing::parse_program(text.clone()).unwrap() == commands);
+        println!(
+            "dragon curve, {} iterations ({} instructions): parse {:?} from {} KiB of text, decode {:?} from {} KiB of bytecode",
+            iterations, commands.len(), fastest(|| parsing::parse_program(text.clone())), text.len() / 1024,
+            fastest(|| bytecode::decode_program(&bytes)), bytes.len() / 1024
+        );
+    }
+}
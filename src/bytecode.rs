@@ -0,0 +1,592 @@
+use crate::color::{BlendMode, Color};
+use crate::instruction::{AngleUnit, Coordinate, Expr, Instruction, Operator, Value, PALETTE_SIZE, REGISTER_COUNT};
+use std::fmt::{self, Display, Formatter};
+
+// every bytecode file starts with this, followed by the version it was written with
+const MAGIC: &[u8; 4] = b"PENC";
+const VERSION: u8 = 1;
+
+// extension of bytecode files, which are read and written instead of text when a filename ends with it
+pub const EXTENSION: &str = ".penc";
+
+// why bytecode couldn't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    NotBytecode,
+    UnsupportedVersion(u8),
+    UnknownOpcode(u8, usize), // the opcode, and the index of the instruction it starts
+    Truncated,
+    Malformed(usize)          // the index of the instruction with an operand that's out of range
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "not a penplot bytecode file"),
+            BytecodeError::UnsupportedVersion(version) => write!(f, "bytecode version {} isn't supported (expected {})", version, VERSION),
+            BytecodeError::UnknownOpcode(opcode, index) => write!(f, "unknown opcode {} in instruction {}", opcode, index),
+            BytecodeError::Truncated => write!(f, "bytecode ends partway through an instruction"),
+            BytecodeError::Malformed(index) => write!(f, "instruction {} has an operand that's out of range", index)
+        }
+    }
+}
+
+// numbers are written as LEB128 varints: 7 bits a byte, low bits first, with the top bit set on all but the last byte
+fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// signed numbers are zigzag encoded first, so small negative numbers stay short too
+fn write_signed(out: &mut Vec<u8>, value: i64) {
+    write_unsigned(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    write_unsigned(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn write_coordinate(out: &mut Vec<u8>, value: Coordinate) {
+    write_signed(out, value.to_bits());
+}
+
+// a literal is tagged 0, a register 1, and T 2
+fn write_value(out: &mut Vec<u8>, value: Value<Coordinate>) {
+    match value {
+        Value::Literal(number) => {
+            out.push(0);
+            write_coordinate(out, number);
+        }
+        Value::Register(register) => {
+            out.push(1);
+            write_unsigned(out, register as u64);
+        }
+        Value::Time => out.push(2)
+    }
+}
+
+fn write_registers(out: &mut Vec<u8>, registers: [usize; 3]) {
+    for register in registers {
+        write_unsigned(out, register as u64);
+    }
+}
+
+fn write_color(out: &mut Vec<u8>, Color(r, g, b, a): Color) {
+    out.extend_from_slice(&[r, g, b, a]);
+}
+
+fn write_expr(out: &mut Vec<u8>, expr: &Expr) {
+    match expr {
+        Expr::Literal(number) => {
+            out.push(0);
+            write_coordinate(out, *number);
+        }
+        Expr::Parameter(name) => {
+            out.push(1);
+            write_string(out, name);
+        }
+        Expr::Binary(a, op, b) => {
+            out.push(2);
+            out.push(match op {
+                Operator::Add => 0,
+                Operator::Sub => 1,
+                Operator::Mul => 2,
+                Operator::Div => 3
+            });
+            write_expr(out, a);
+            write_expr(out, b);
+        }
+    }
+}
+
+// reads operands back out of bytecode, keeping track of which instruction it's in for errors
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    index: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self.bytes.get(self.position).ok_or(BytecodeError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::Malformed(self.index))
+    }
+
+    fn signed(&mut self) -> Result<i64, BytecodeError> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        usize::try_from(self.unsigned()?).map_err(|_| BytecodeError::Malformed(self.index))
+    }
+
+    // registers and palette slots index into fixed-size arrays, so ones past the end are malformed, not just unusual
+    fn register(&mut self) -> Result<usize, BytecodeError> {
+        Some(self.usize()?).filter(|&register| register < REGISTER_COUNT).ok_or(BytecodeError::Malformed(self.index))
+    }
+
+    fn palette_index(&mut self) -> Result<usize, BytecodeError> {
+        Some(self.usize()?).filter(|&index| index < PALETTE_SIZE).ok_or(BytecodeError::Malformed(self.index))
+    }
+
+    fn isize(&mut self) -> Result<isize, BytecodeError> {
+        isize::try_from(self.signed()?).map_err(|_| BytecodeError::Malformed(self.index))
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.usize()?;
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(BytecodeError::Truncated)?;
+        let text = String::from_utf8(self.bytes[self.position..end].to_vec()).map_err(|_| BytecodeError::Malformed(self.index))?;
+        self.position = end;
+        Ok(text)
+    }
+
+    fn coordinate(&mut self) -> Result<Coordinate, BytecodeError> {
+        Ok(Coordinate::from_bits(self.signed()?))
+    }
+
+    fn value(&mut self) -> Result<Value<Coordinate>, BytecodeError> {
+        match self.byte()? {
+            0 => Ok(Value::Literal(self.coordinate()?)),
+            1 => Ok(Value::Register(self.register()?)),
+            2 => Ok(Value::Time),
+            _ => Err(BytecodeError::Malformed(self.index))
+        }
+    }
+
+    fn color(&mut self) -> Result<Color, BytecodeError> {
+        Ok(Color(self.byte()?, self.byte()?, self.byte()?, self.byte()?))
+    }
+
+    fn expr(&mut self) -> Result<Expr, BytecodeError> {
+        match self.byte()? {
+            0 => Ok(Expr::Literal(self.coordinate()?)),
+            1 => Ok(Expr::Parameter(self.string()?)),
+            2 => {
+                let op = match self.byte()? {
+                    0 => Operator::Add,
+                    1 => Operator::Sub,
+                    2 => Operator::Mul,
+                    3 => Operator::Div,
+                    _ => return Err(BytecodeError::Malformed(self.index))
+                };
+                Ok(Expr::Binary(Box::new(self.expr()?), op, Box::new(self.expr()?)))
+            }
+            _ => Err(BytecodeError::Malformed(self.index))
+        }
+    }
+
+    fn angle_unit(&mut self) -> Result<AngleUnit, BytecodeError> {
+        match self.byte()? {
+            0 => Ok(AngleUnit::Degrees),
+            1 => Ok(AngleUnit::Radians),
+            2 => Ok(AngleUnit::Gradians),
+            _ => Err(BytecodeError::Malformed(self.index))
+        }
+    }
+
+    fn blend_mode(&mut self) -> Result<BlendMode, BytecodeError> {
+        match self.byte()? {
+            0 => Ok(BlendMode::Normal),
+            1 => Ok(BlendMode::Multiply),
+            2 => Ok(BlendMode::Screen),
+            3 => Ok(BlendMode::Additive),
+            _ => Err(BytecodeError::Malformed(self.index))
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, BytecodeError> {
+        let opcode = self.byte()?;
+        let inst = match opcode {
+            0 => Instruction::Noop,
+            1 => Instruction::Move(self.value()?, self.value()?),
+            2 => Instruction::MoveRel(self.value()?, self.value()?),
+            3 => Instruction::MoveForward(self.value()?),
+            4 => Instruction::Face(self.value()?),
+            5 => Instruction::Turn(self.value()?),
+            6 => Instruction::Arc(self.value()?, self.value()?),
+            7 => Instruction::AngleMode(self.angle_unit()?),
+            8 => Instruction::Scale(self.value()?),
+            9 => Instruction::Circle(self.value()?),
+            10 => Instruction::Disc(self.value()?),
+            11 => Instruction::SetColor(self.color()?),
+            12 => Instruction::Lerp(self.color()?, self.color()?, self.usize()?, self.usize()?),
+            13 => Instruction::BlendMode(self.blend_mode()?),
+            14 => Instruction::Blot,
+            15 => Instruction::PenUp,
+            16 => Instruction::PenDown,
+            17 => Instruction::PenWidth(self.usize()?),
+            18 => Instruction::DynamicWidth(self.usize()?, self.usize()?),
+            19 => Instruction::BeginFill,
+            20 => Instruction::EndFill,
+            21 => Instruction::Hatch(self.isize()?, self.usize()?),
+            22 => Instruction::BeginStencil,
+            23 => Instruction::EndStencil,
+            24 => Instruction::ClearStencil,
+            25 => Instruction::Fill,
+            26 => Instruction::Text(self.string()?),
+            27 => Instruction::Push,
+            28 => Instruction::Pop,
+            29 => Instruction::Comment(self.string()?),
+            30 => Instruction::Parametric(self.string()?, self.expr()?),
+            31 => Instruction::Layer(self.string()?),
+            32 => Instruction::Goto(self.usize()?),
+            33 => Instruction::Jump(self.isize()?),
+            34 => Instruction::JumpIfZero(self.register()?, self.isize()?),
+            35 => Instruction::JumpIfNotZero(self.register()?, self.isize()?),
+            36 => Instruction::Call(self.usize()?),
+            37 => Instruction::Return,
+            38 => Instruction::Repeat(self.usize()?, self.usize()?),
+            39 => Instruction::Halt,
+            40 => Instruction::Load(self.register()?, self.isize()?),
+            41 => Instruction::Add(self.register()?, self.register()?, self.register()?),
+            42 => Instruction::Sub(self.register()?, self.register()?, self.register()?),
+            43 => Instruction::Mul(self.register()?, self.register()?, self.register()?),
+            44 => Instruction::Mirror,
+            45 => Instruction::PushArg(self.isize()?),
+            46 => Instruction::Arg(self.register()?, self.usize()?),
+            47 => Instruction::RepeatBlock(self.usize()?, self.usize()?),
+            48 => Instruction::EndRepeat,
+            49 => Instruction::DefinePalette(self.palette_index()?, self.color()?),
+            50 => Instruction::UsePalette(match self.byte()? {
+                0 => Value::Literal(self.palette_index()?),
+                1 => Value::Register(self.register()?),
+                2 => Value::Time,
+                _ => return Err(BytecodeError::Malformed(self.index))
+            }),
+            51 => Instruction::Random(self.register()?, self.isize()?, self.isize()?),
+            52 => Instruction::Polygon(self.usize()?, self.value()?),
+            53 => Instruction::Home,
+            54 => Instruction::LineTo(self.value()?, self.value()?),
+            55 => Instruction::AdjustColor(self.isize()?, self.isize()?, self.isize()?, self.isize()?),
+            56 => Instruction::WalkDots(self.value()?, self.value()?),
+            57 => Instruction::FaceToward(self.value()?, self.value()?),
+            _ => return Err(BytecodeError::UnknownOpcode(opcode, self.index))
+        };
+        self.index += 1;
+        Ok(inst)
+    }
+}
+
+impl Instruction {
+    // append this instruction's bytecode: an opcode, followed by its operands
+    // opcodes are numbered by hand, so reordering the enum doesn't change the format
+    pub fn write_bytes(&self, out: &mut Vec<u8>) {
+        match self {
+            Instruction::Noop => out.push(0),
+            Instruction::Move(x, y) => {
+                out.push(1);
+                write_value(out, *x);
+                write_value(out, *y);
+            }
+            Instruction::MoveRel(dx, dy) => {
+                out.push(2);
+                write_value(out, *dx);
+                write_value(out, *dy);
+            }
+            Instruction::MoveForward(n) => {
+                out.push(3);
+                write_value(out, *n);
+            }
+            Instruction::Face(theta) => {
+                out.push(4);
+                write_value(out, *theta);
+            }
+            Instruction::Turn(dt) => {
+                out.push(5);
+                write_value(out, *dt);
+            }
+            Instruction::Arc(radius, dt) => {
+                out.push(6);
+                write_value(out, *radius);
+                write_value(out, *dt);
+            }
+            Instruction::AngleMode(unit) => out.extend_from_slice(&[7, match unit {
+                AngleUnit::Degrees => 0,
+                AngleUnit::Radians => 1,
+                AngleUnit::Gradians => 2
+            }]),
+            Instruction::Scale(factor) => {
+                out.push(8);
+                write_value(out, *factor);
+            }
+            Instruction::Mirror => out.push(44),
+            Instruction::Home => out.push(53),
+            Instruction::LineTo(x, y) => {
+                out.push(54);
+                write_value(out, *x);
+                write_value(out, *y);
+            }
+            Instruction::WalkDots(dist, spacing) => {
+                out.push(56);
+                write_value(out, *dist);
+                write_value(out, *spacing);
+            }
+            Instruction::FaceToward(x, y) => {
+                out.push(57);
+                write_value(out, *x);
+                write_value(out, *y);
+            }
+            Instruction::Circle(radius) => {
+                out.push(9);
+                write_value(out, *radius);
+            }
+            Instruction::Disc(radius) => {
+                out.push(10);
+                write_value(out, *radius);
+            }
+            Instruction::Polygon(sides, radius) => {
+                out.push(52);
+                write_unsigned(out, *sides as u64);
+                write_value(out, *radius);
+            }
+            Instruction::SetColor(color) => {
+                out.push(11);
+                write_color(out, *color);
+            }
+            Instruction::Lerp(from, to, steps, step) => {
+                out.push(12);
+                write_color(out, *from);
+                write_color(out, *to);
+                write_unsigned(out, *steps as u64);
+                write_unsigned(out, *step as u64);
+            }
+            Instruction::BlendMode(mode) => out.extend_from_slice(&[13, match mode {
+                BlendMode::Normal => 0,
+                BlendMode::Multiply => 1,
+                BlendMode::Screen => 2,
+                BlendMode::Additive => 3
+            }]),
+            Instruction::DefinePalette(index, color) => {
+                out.push(49);
+                write_unsigned(out, *index as u64);
+                write_color(out, *color);
+            }
+            Instruction::UsePalette(index) => {
+                out.push(50);
+                match index {
+                    Value::Literal(index) => {
+                        out.push(0);
+                        write_unsigned(out, *index as u64);
+                    }
+                    Value::Register(register) => {
+                        out.push(1);
+                        write_unsigned(out, *register as u64);
+                    }
+                    Value::Time => out.push(2)
+                }
+            }
+            Instruction::Blot => out.push(14),
+            Instruction::PenUp => out.push(15),
+            Instruction::PenDown => out.push(16),
+            Instruction::PenWidth(width) => {
+                out.push(17);
+                write_unsigned(out, *width as u64);
+            }
+            Instruction::DynamicWidth(min, max) => {
+                out.push(18);
+                write_unsigned(out, *min as u64);
+                write_unsigned(out, *max as u64);
+            }
+            Instruction::BeginFill => out.push(19),
+            Instruction::EndFill => out.push(20),
+            Instruction::Hatch(angle, spacing) => {
+                out.push(21);
+                write_signed(out, *angle as i64);
+                write_unsigned(out, *spacing as u64);
+            }
+            Instruction::BeginStencil => out.push(22),
+            Instruction::EndStencil => out.push(23),
+            Instruction::ClearStencil => out.push(24),
+            Instruction::Fill => out.push(25),
+            Instruction::Text(text) => {
+                out.push(26);
+                write_string(out, text);
+            }
+            Instruction::Push => out.push(27),
+            Instruction::Pop => out.push(28),
+            Instruction::Comment(text) => {
+                out.push(29);
+                write_string(out, text);
+            }
+            Instruction::Parametric(name, parameter) => {
+                out.push(30);
+                write_string(out, name);
+                write_expr(out, parameter);
+            }
+            Instruction::Layer(name) => {
+                out.push(31);
+                write_string(out, name);
+            }
+            Instruction::Goto(target) => {
+                out.push(32);
+                write_unsigned(out, *target as u64);
+            }
+            Instruction::Jump(offset) => {
+                out.push(33);
+                write_signed(out, *offset as i64);
+            }
+            Instruction::JumpIfZero(r, offset) => {
+                out.push(34);
+                write_unsigned(out, *r as u64);
+                write_signed(out, *offset as i64);
+            }
+            Instruction::JumpIfNotZero(r, offset) => {
+                out.push(35);
+                write_unsigned(out, *r as u64);
+                write_signed(out, *offset as i64);
+            }
+            Instruction::Call(target) => {
+                out.push(36);
+                write_unsigned(out, *target as u64);
+            }
+            Instruction::Return => out.push(37),
+            Instruction::Repeat(target, times) => {
+                out.push(38);
+                write_unsigned(out, *target as u64);
+                write_unsigned(out, *times as u64);
+            }
+            Instruction::RepeatBlock(end, times) => {
+                out.push(47);
+                write_unsigned(out, *end as u64);
+                write_unsigned(out, *times as u64);
+            }
+            Instruction::EndRepeat => out.push(48),
+            Instruction::Halt => out.push(39),
+            Instruction::Load(r, n) => {
+                out.push(40);
+                write_unsigned(out, *r as u64);
+                write_signed(out, *n as i64);
+            }
+            Instruction::PushArg(n) => {
+                out.push(45);
+                write_signed(out, *n as i64);
+            }
+            Instruction::Arg(r, i) => {
+                out.push(46);
+                write_unsigned(out, *r as u64);
+                write_unsigned(out, *i as u64);
+            }
+            Instruction::Add(r, a, b) => {
+                out.push(41);
+                write_registers(out, [*r, *a, *b]);
+            }
+            Instruction::Sub(r, a, b) => {
+                out.push(42);
+                write_registers(out, [*r, *a, *b]);
+            }
+            Instruction::Mul(r, a, b) => {
+                out.push(43);
+                write_registers(out, [*r, *a, *b]);
+            }
+            Instruction::AdjustColor(dr, dg, db, da) => {
+                out.push(55);
+                for delta in [dr, dg, db, da] {
+                    write_signed(out, *delta as i64);
+                }
+            }
+            Instruction::Random(r, lo, hi) => {
+                out.push(51);
+                write_unsigned(out, *r as u64);
+                write_signed(out, *lo as i64);
+                write_signed(out, *hi as i64);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write_bytes(&mut out);
+        out
+    }
+
+    // read one instruction from the start of some bytecode, returning it along with the bytes after it
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Instruction, &[u8]), BytecodeError> {
+        let mut reader = Reader { bytes, position: 0, index: 0 };
+        let inst = reader.instruction()?;
+        Ok((inst, &bytes[reader.position..]))
+    }
+}
+
+/// Encode a program as bytecode, which loads much faster than parsing text. Labels aren't kept, since jumps
+/// already point at program counters.
+///
+/// ```
+/// use penplot::{bytecode, parsing};
+///
+/// let program = parsing::parse_program(String::from("RGB 255 0 0\nWALK -2.5\nLOAD R3 -7\nTEXT hi")).unwrap();
+/// let bytes = bytecode::encode_program(&program);
+/// assert_eq!(bytecode::decode_program(&bytes), Ok(program));
+/// ```
+pub fn encode_program(program: &[Instruction]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write_unsigned(&mut out, program.len() as u64);
+    for inst in program {
+        inst.write_bytes(&mut out);
+    }
+    out
+}
+
+pub fn decode_program(bytes: &[u8]) -> Result<Vec<Instruction>, BytecodeError> {
+    let body = bytes.strip_prefix(MAGIC).ok_or(BytecodeError::NotBytecode)?;
+    let mut reader = Reader { bytes: body, position: 0, index: 0 };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let len = reader.usize()?;
+    // the length comes from the file, so it isn't trusted to size the program up front
+    let mut program = Vec::with_capacity(len.min(body.len()));
+    for _ in 0..len {
+        program.push(reader.instruction()?);
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a program of one instruction, with its operand bytes written out by hand
+    fn one_instruction(bytes: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&[VERSION, 1]);
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    #[test]
+    fn out_of_range_indices_are_malformed() {
+        // LOAD R99 0
+        assert_eq!(decode_program(&one_instruction(&[40, 99, 0])), Err(BytecodeError::Malformed(0)));
+        // PALD 99 with an opaque black
+        assert_eq!(decode_program(&one_instruction(&[49, 99, 0, 0, 0, 255])), Err(BytecodeError::Malformed(0)));
+        // PALU 99, and PALU R99
+        assert_eq!(decode_program(&one_instruction(&[50, 0, 99])), Err(BytecodeError::Malformed(0)));
+        assert_eq!(decode_program(&one_instruction(&[50, 1, 99])), Err(BytecodeError::Malformed(0)));
+        // WALK R16, just past the last register
+        assert_eq!(decode_program(&one_instruction(&[3, 1, REGISTER_COUNT as u8])), Err(BytecodeError::Malformed(0)));
+        // while the last register and palette slot are fine
+        assert_eq!(decode_program(&one_instruction(&[40, 15, 0])), Ok(vec![Instruction::Load(15, 0)]));
+        assert_eq!(decode_program(&one_instruction(&[50, 0, 15])), Ok(vec![Instruction::UsePalette(Value::Literal(15))]));
+    }
+}
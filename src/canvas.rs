@@ -1,5 +1,13 @@
-use crate::color::Color;
+use crate::color::{BlendMode, Color};
+use crate::util;
 use std::cmp::{self, Ordering};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Result as IoResult};
+use std::mem;
+use std::path::Path;
+use image::{ImageFormat, ImageResult, RgbaImage};
 
 // trait for drawing canvases, allowing us to abstract over drawing SVGs and PNGs
 // the trait only exposes things the program state cares about, allowing it to stop worrying about implementation
@@ -12,16 +20,137 @@ pub trait DrawingCanvas {
 
     // set pen color
     fn set_color(&mut self, color: Color);
+
+    // set how the pen color is combined with what's underneath it
+    fn set_blend_mode(&mut self, mode: BlendMode);
+
+    // set pen width (in pixels)
+    fn set_pen_width(&mut self, width: usize);
+
+    // only draw inside a polygon from now on, or anywhere if None
+    fn set_stencil(&mut self, polygon: Option<&[(f32, f32)]>);
+
+    // flood fill the empty area around a point with the pen color
+    fn fill(&mut self, x: f32, y: f32);
+
+    // write text in the built-in font, with its top left pixel at (x, y) and each font pixel scale pixels across
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize);
+
+    // draw a circle around (x, y) with the pen, or fill it in with the pen color if filled
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool);
+}
+
+// lets drawing code borrow a canvas (including as a &mut dyn DrawingCanvas) instead of owning it
+impl<C: DrawingCanvas + ?Sized> DrawingCanvas for &mut C {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        (**self).move_pen_to(x, y);
+    }
+
+    fn blot(&mut self, x: f32, y: f32) {
+        (**self).blot(x, y);
+    }
+
+    fn set_color(&mut self, color: Color) {
+        (**self).set_color(color);
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        (**self).set_blend_mode(mode);
+    }
+
+    fn set_pen_width(&mut self, width: usize) {
+        (**self).set_pen_width(width);
+    }
+
+    fn set_stencil(&mut self, polygon: Option<&[(f32, f32)]>) {
+        (**self).set_stencil(polygon);
+    }
+
+    fn fill(&mut self, x: f32, y: f32) {
+        (**self).fill(x, y);
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        (**self).draw_text(x, y, text, scale);
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        (**self).draw_circle(x, y, radius, filled);
+    }
 }
 
 // trait for canvases that can be saved
 // this is distinct from DrawingCanvas because of SizingCanvas
 pub trait SaveableCanvas {
     // save to a file
-    fn save(&self, filename: &str);
+    fn save(&self, filename: &str) -> IoResult<()>;
+}
+
+// blend one channel of a color onto an opaque color underneath it
+fn flatten(top: u8, bottom: u8, alpha: u8) -> u8 {
+    ((top as u32 * alpha as u32 + bottom as u32 * (255 - alpha) as u32 + 127) / 255) as u8
+}
+
+// how a pixel canvas keeps its pixels
+enum Pixels {
+    // straight alpha, exactly as they're saved
+    Straight(Vec<Color>),
+    // premultiplied by alpha and kept as floats, so compositing lots of translucent strokes doesn't build up rounding
+    // error; they're only rounded when they're read back out
+    Premultiplied(Vec<[f32; 4]>)
 }
 
-// raster graphics canvas
+impl Pixels {
+    fn color(&self, index: usize) -> Color {
+        match self {
+            Pixels::Straight(buffer) => buffer[index],
+            Pixels::Premultiplied(buffer) => Color::from_premultiplied(buffer[index])
+        }
+    }
+
+    fn set(&mut self, index: usize, color: Color) {
+        match self {
+            Pixels::Straight(buffer) => buffer[index] = color,
+            Pixels::Premultiplied(buffer) => buffer[index] = color.premultiplied()
+        }
+    }
+
+    // composite a color onto a pixel
+    fn blend(&mut self, index: usize, mode: BlendMode, color: Color) {
+        match self {
+            Pixels::Straight(buffer) => buffer[index] = mode.apply(color, buffer[index]),
+            Pixels::Premultiplied(buffer) if mode == BlendMode::Normal => {
+                let top = color.premultiplied();
+                let bottom = buffer[index];
+                buffer[index] = [0, 1, 2, 3].map(|i| top[i] + bottom[i] * (1.0 - top[3]));
+            }
+            // the other modes are worked out on straight colors, so they still round once per stroke
+            Pixels::Premultiplied(buffer) => {
+                buffer[index] = mode.apply(color, Color::from_premultiplied(buffer[index])).premultiplied();
+            }
+        }
+    }
+}
+
+/// Raster graphics canvas. Thick lines are drawn by stamping a round pen along them, so the corners where they
+/// meet are always round joins, filled on both sides, and their ends are round caps.
+///
+/// ```
+/// use penplot::canvas::{DrawingCanvas, PixelCanvas};
+/// use penplot::color::Color;
+///
+/// let mut canvas = PixelCanvas::new(40, 40, 0, 0);
+/// canvas.move_pen_to(10.0, 10.0);
+/// canvas.set_color(Color(255, 255, 255, 255));
+/// canvas.set_pen_width(9);
+/// canvas.move_pen_to(30.0, 10.0);
+/// canvas.move_pen_to(30.0, 30.0);
+/// // outside and inside the corner
+/// assert_eq!(canvas.pixel(33, 7), Some(Color(255, 255, 255, 255)));
+/// assert_eq!(canvas.pixel(26, 14), Some(Color(255, 255, 255, 255)));
+/// // the outside is rounded off, rather than mitered
+/// assert_eq!(canvas.pixel(34, 6), Some(Color::transparent()));
+/// ```
 pub struct PixelCanvas {
     width: usize,
     height: usize,
@@ -30,120 +159,810 @@ pub struct PixelCanvas {
     pen_x: f32,
     pen_y: f32,
     pen_color: Color,
-    buffer: Vec<Color>
+    pen_width: usize,
+    blend_mode: BlendMode,
+    // pixel the last line ended on, if the pen hasn't changed or lifted since
+    line_end: Option<(isize, isize)>,
+    // which pixels can be drawn on, if there's a stencil
+    stencil: Option<Vec<bool>>,
+    // whether one pixel wide lines are drawn anti-aliased (with Xiaolin Wu's algorithm)
+    smooth_lines: bool,
+    // whether shrinking the canvas dithers the averaged colors, instead of rounding them all down
+    dither: bool,
+    // color the image is composited onto when it's saved (drawing always happens on a transparent buffer)
+    background: Color,
+    buffer: Pixels
 }
 
 impl PixelCanvas {
     pub fn new(width: usize, height: usize, x_offset: isize, y_offset: isize) -> Self {
+        PixelCanvas::with_pixels(width, height, x_offset, y_offset, Pixels::Straight(vec![Color::transparent(); width * height]))
+    }
+
+    /// A canvas that keeps its pixels premultiplied by alpha, at more precision than they're saved with, so stacking
+    /// many translucent strokes doesn't drift the way compositing rounded colors over and over does:
+    ///
+    /// ```
+    /// use penplot::canvas::{DrawingCanvas, PixelCanvas};
+    /// use penplot::color::Color;
+    ///
+    /// let stack = |mut canvas: PixelCanvas| {
+    ///     canvas.set_color(Color(0, 0, 0, 255));
+    ///     canvas.blot(0.0, 0.0);
+    ///     // 100 strokes of 10% white
+    ///     canvas.set_color(Color(255, 255, 255, 26));
+    ///     for _ in 0..100 {
+    ///         canvas.blot(0.0, 0.0);
+    ///     }
+    ///     canvas.pixel(0, 0).unwrap()
+    /// };
+    /// // rounding after every stroke stalls short of white, since each stroke's share rounds away to nothing
+    /// assert!(stack(PixelCanvas::new(1, 1, 0, 0)).red() < 252);
+    /// assert_eq!(stack(PixelCanvas::new_premultiplied(1, 1, 0, 0)), Color(255, 255, 255, 255));
+    /// ```
+    pub fn new_premultiplied(width: usize, height: usize, x_offset: isize, y_offset: isize) -> Self {
+        PixelCanvas::with_pixels(width, height, x_offset, y_offset, Pixels::Premultiplied(vec![[0.0; 4]; width * height]))
+    }
+
+    fn with_pixels(width: usize, height: usize, x_offset: isize, y_offset: isize, buffer: Pixels) -> Self {
         PixelCanvas {
             width, height, x_offset, y_offset,
             pen_x: 0.0,
             pen_y: 0.0,
             pen_color: Color::transparent(),
-            buffer: vec![Color::transparent(); width * height]
+            pen_width: 1,
+            blend_mode: BlendMode::Normal,
+            line_end: None,
+            stencil: None,
+            smooth_lines: false,
+            dither: false,
+            background: Color::transparent(),
+            buffer
+        }
+    }
+
+    /// Draw one pixel wide lines anti-aliased, instead of with hard edges. A smooth diagonal line spreads about
+    /// the same amount of ink as a hard one over more pixels, partly covering the ones either side of it:
+    ///
+    /// ```
+    /// use penplot::canvas::{DrawingCanvas, PixelCanvas};
+    /// use penplot::color::Color;
+    ///
+    /// // how much of each pixel a line from (2, 2) to (18, 10) covers, from 0 to 1
+    /// let coverage = |smooth: bool| {
+    ///     let mut canvas = PixelCanvas::new(20, 20, 0, 0).with_smooth_lines(smooth);
+    ///     canvas.move_pen_to(2.0, 2.0);
+    ///     canvas.set_color(Color(255, 255, 255, 255));
+    ///     canvas.move_pen_to(18.0, 10.0);
+    ///     (0..400).map(|i| canvas.pixel(i % 20, i / 20).unwrap().alpha() as f32 / 255.0).collect::<Vec<_>>()
+    /// };
+    /// let (hard, smooth) = (coverage(false), coverage(true));
+    /// let touched = |pixels: &[f32]| pixels.iter().filter(|&&alpha| alpha > 0.0).count();
+    /// // the hard line is one fully opaque pixel per column
+    /// assert_eq!(touched(&hard), 17);
+    /// assert!(hard.iter().all(|&alpha| alpha == 0.0 || alpha == 1.0));
+    /// // the smooth one touches more pixels, most of them only partly
+    /// assert!(touched(&smooth) > touched(&hard));
+    /// assert!(smooth.iter().filter(|&&alpha| alpha > 0.0 && alpha < 1.0).count() > touched(&smooth) / 2);
+    /// // but the total ink is within a pixel of the hard line's (the end pixels are only half covered)
+    /// let total = |pixels: &[f32]| pixels.iter().sum::<f32>();
+    /// assert!((total(&smooth) - total(&hard)).abs() <= 1.1);
+    /// ```
+    pub fn with_smooth_lines(mut self, smooth_lines: bool) -> Self {
+        self.smooth_lines = smooth_lines;
+        self
+    }
+
+    /// Dither the colors that shrinking the canvas averages together (for `--aa`), so soft edges and gradients
+    /// spread over several pixels don't band. Each 4x4 tile of the result keeps the average of what it came from:
+    ///
+    /// ```
+    /// use penplot::canvas::{DrawingCanvas, PixelCanvas};
+    /// use penplot::color::Color;
+    ///
+    /// // one pixel in every 2x2 block is drawn, so each shrunk pixel should be a quarter opaque: 63.75
+    /// let alphas = |dither: bool| {
+    ///     let mut canvas = PixelCanvas::new(8, 8, 0, 0).with_dither(dither);
+    ///     canvas.set_color(Color(255, 255, 255, 255));
+    ///     for y in (0..8).step_by(2) {
+    ///         for x in (0..8).step_by(2) {
+    ///             canvas.blot(x as f32, y as f32);
+    ///         }
+    ///     }
+    ///     let canvas = canvas.downsample(2);
+    ///     (0..16).map(|i| canvas.pixel(i % 4, i / 4).unwrap().alpha() as usize).collect::<Vec<_>>()
+    /// };
+    /// // without dithering, every pixel rounds down the same way
+    /// assert!(alphas(false).iter().all(|&alpha| alpha == 63));
+    /// // with it, neighbors differ, and the total is what it should be (16 pixels at 63.75)
+    /// let dithered = alphas(true);
+    /// assert!(dithered.windows(2).any(|pair| pair[0] != pair[1]));
+    /// assert_eq!(dithered.iter().sum::<usize>(), 1020);
+    /// ```
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    // save the image on top of a background color, instead of leaving it transparent
+    pub fn with_background(mut self, background: Color) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn offsets(&self) -> (isize, isize) {
+        (self.x_offset, self.y_offset)
+    }
+
+    // load a saved image to keep drawing on, placed with the given offsets
+    pub fn load(filename: &str, x_offset: isize, y_offset: isize) -> ImageResult<Self> {
+        let image = image::open(filename)?.into_rgba8();
+        let mut canvas = PixelCanvas::new(image.width() as usize, image.height() as usize, x_offset, y_offset);
+        for (index, &image::Rgba([r, g, b, a])) in image.pixels().enumerate() {
+            canvas.buffer.set(index, Color(r, g, b, a));
         }
+        Ok(canvas)
     }
 
-    fn draw_pixel_f(&mut self, x: f32, y: f32) {
-        self.draw_pixel_i(x.round() as isize, y.round() as isize);
+    // color of a pixel in the saved image (so offsets don't apply), or None if it's outside the canvas
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x < self.width && y < self.height {
+            Some(self.buffer.color(x + y * self.width))
+        } else {
+            None
+        }
+    }
+
+    // shrink the canvas to fit within the given size (keeping its aspect ratio), averaging the pixels that get merged
+    // canvases that already fit are copied as-is
+    pub fn thumbnail(&self, max_width: usize, max_height: usize) -> PixelCanvas {
+        let scale = (max_width as f32 / self.width as f32)
+            .min(max_height as f32 / self.height as f32)
+            .min(1.0);
+        let width = cmp::max((self.width as f32 * scale).round() as usize, 1);
+        let height = cmp::max((self.height as f32 * scale).round() as usize, 1);
+        self.resample(width, height)
+    }
+
+    // shrink a canvas drawn through a ScaledCanvas back down by the same factor
+    // each pixel averages the factor x factor block it came from, which smooths out jagged edges
+    pub fn downsample(&self, factor: usize) -> PixelCanvas {
+        let factor = cmp::max(factor, 1);
+        let mut canvas = self.resample(cmp::max(self.width / factor, 1), cmp::max(self.height / factor, 1));
+        canvas.x_offset = self.x_offset / factor as isize;
+        canvas.y_offset = self.y_offset / factor as isize;
+        canvas.background = self.background;
+        canvas
+    }
+
+    // shrink the canvas to exactly the given size, averaging the pixels that get merged
+    fn resample(&self, width: usize, height: usize) -> PixelCanvas {
+        let mut thumbnail = PixelCanvas::new(width, height, 0, 0);
+        for y in 0..height {
+            for x in 0..width {
+                // the box of source pixels that land on this thumbnail pixel
+                let (x0, x1) = (x * self.width / width, cmp::max((x + 1) * self.width / width, x * self.width / width + 1));
+                let (y0, y1) = (y * self.height / height, cmp::max((y + 1) * self.height / height, y * self.height / height + 1));
+                let mut colors = vec![];
+                for source_y in y0..y1 {
+                    colors.extend((x0..x1).map(|source_x| self.buffer.color(source_x + source_y * self.width)));
+                }
+                let color = if self.dither {
+                    Color::average_dithered(&colors, util::bayer_threshold(x, y))
+                } else {
+                    Color::average(&colors)
+                };
+                thumbnail.buffer.set(x + y * width, color);
+            }
+        }
+        thumbnail
+    }
+
+    // draw the canvas as text, one character per cell, for previewing in a terminal
+    // terminal cells are about twice as tall as they are wide, so each cell covers twice as many rows as columns
+    pub fn to_ascii(&self, columns: usize) -> String {
+        const RAMP: &[u8] = b" .:-=+*#%@";
+        let columns = cmp::max(cmp::min(columns, self.width), 1);
+        let rows = cmp::max((self.height * columns).div_ceil(self.width * 2), 1);
+        let preview = self.resample(columns, rows);
+        let mut text = String::new();
+        for y in 0..rows {
+            for color in (0..columns).filter_map(|x| preview.pixel(x, y)) {
+                // brightness of the color over a black background, so transparent pixels are blank
+                let luminance = 0.2126 * color.red() as f32 + 0.7152 * color.green() as f32 + 0.0722 * color.blue() as f32;
+                let brightness = luminance / 255.0 * color.alpha() as f32 / 255.0;
+                text.push(RAMP[(brightness * (RAMP.len() - 1) as f32).round() as usize] as char);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Copy out a rectangle of the canvas (in saved image pixels), clamped to the canvas bounds. The offsets move
+    /// with it, so drawing on the cropped canvas lands in the same place as on the original:
+    ///
+    /// ```
+    /// use penplot::canvas::{DrawingCanvas, PixelCanvas};
+    /// use penplot::color::Color;
+    ///
+    /// let white = Color(255, 255, 255, 255);
+    /// let mut canvas = PixelCanvas::new(10, 10, 2, 0);
+    /// canvas.set_color(white);
+    /// canvas.blot(3.0, 5.0);
+    /// let mut cropped = canvas.crop(4, 4, 3, 3);
+    /// assert_eq!((cropped.width(), cropped.height(), cropped.offsets()), (3, 3, (-2, -4)));
+    /// assert_eq!(cropped.pixel(1, 1), Some(white));
+    /// assert_eq!(cropped.pixel(0, 0), Some(Color::transparent()));
+    /// cropped.set_color(Color(255, 0, 0, 255));
+    /// cropped.blot(4.0, 6.0);
+    /// assert_eq!(cropped.pixel(2, 2), Some(Color(255, 0, 0, 255)));
+    /// // a rectangle hanging off the edge only keeps the part on the canvas
+    /// let corner = canvas.crop(8, 7, 10, 10);
+    /// assert_eq!((corner.width(), corner.height()), (2, 3));
+    /// ```
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> PixelCanvas {
+        let x = cmp::min(x, self.width);
+        let y = cmp::min(y, self.height);
+        let width = cmp::min(width, self.width - x);
+        let height = cmp::min(height, self.height - y);
+        let mut cropped = PixelCanvas::new(width, height, self.x_offset - x as isize, self.y_offset - y as isize);
+        for row in 0..height {
+            for column in 0..width {
+                cropped.buffer.set(column + row * width, self.buffer.color(x + column + (y + row) * self.width));
+            }
+        }
+        cropped
+    }
+
+    // composite another canvas on top of this one, with its top left corner at (x, y)
+    pub fn blit(&mut self, source: &PixelCanvas, x: usize, y: usize) {
+        for source_y in 0..source.height {
+            for source_x in 0..source.width {
+                let (target_x, target_y) = (x + source_x, y + source_y);
+                if let (Some(color), Some(_)) = (source.pixel(source_x, source_y), self.pixel(target_x, target_y)) {
+                    self.buffer.blend(target_x + target_y * self.width, BlendMode::Normal, color);
+                }
+            }
+        }
+    }
+
+    // buffer index of a pixel, or None if it's off the page
+    // coordinates can be anything, so applying the offset is checked, and the index is only computed once the pixel
+    // is known to be on the page (so it's less than the buffer length and can't overflow)
+    fn pixel_index(&self, x: isize, y: isize) -> Option<usize> {
+        let x = usize::try_from(x.checked_add(self.x_offset)?).ok()?;
+        let y = usize::try_from(y.checked_add(self.y_offset)?).ok()?;
+        if x < self.width && y < self.height {
+            Some(x + y * self.width)
+        } else {
+            None
+        }
     }
 
     fn draw_pixel_i(&mut self, x: isize, y: isize) {
-        let w = self.width as isize;
-        let h = self.height as isize;
-        let x = x + self.x_offset;
-        let y = y + self.y_offset;
-        if x < 0 || y < 0 || x >= w || y >= h {
-            // do nothing, since we're off the page
+        // do nothing if we're off the page or outside the stencil
+        if let Some(index) = self.pixel_index(x, y) {
+            if self.stencil.as_ref().is_none_or(|stencil| stencil[index]) {
+                self.buffer.blend(index, self.blend_mode, self.pen_color);
+            }
+        }
+    }
+
+    // offsets of every pixel covered by a pen of the current width
+    fn stamp_offsets(&self) -> Vec<(isize, isize)> {
+        let radius = self.pen_width as f32 / 2.0;
+        let reach = radius.floor() as isize;
+        let mut offsets = vec![];
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                if ((dx * dx + dy * dy) as f32) <= radius * radius {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets
+    }
+
+    // draw a set of pixels covered by stamping the pen at each point
+    // each pixel is only composited once, so overlapping stamps don't darken translucent strokes
+    fn stamp_points(&mut self, points: &[(isize, isize)]) {
+        if self.pen_width <= 1 {
+            for &(x, y) in points {
+                self.draw_pixel_i(x, y);
+            }
         } else {
-            let index = (x + y * w) as usize;
-            self.buffer[index] = Color::overlay(self.pen_color, self.buffer[index]);
+            let offsets = self.stamp_offsets();
+            let mut covered = HashSet::new();
+            for &(x, y) in points {
+                for &(dx, dy) in &offsets {
+                    // saturating keeps stamps at the far edges of isize from overflowing; they're off the page anyway
+                    let (x, y) = (x.saturating_add(dx), y.saturating_add(dy));
+                    if covered.insert((x, y)) {
+                        self.draw_pixel_i(x, y);
+                    }
+                }
+            }
         }
     }
 
-    // bresenham's line algorithm
-    fn plot_line(&mut self, mut x0: isize, mut y0: isize, x1: isize, y1: isize) {
-        let dx = (x1 - x0).abs();
-        let sx = match x0.cmp(&x1) {
-            Ordering::Less => 1,
-            _ => -1,
-        };
-        let dy = -(y1 - y0).abs();
-        let sy = match y0.cmp(&y1) {
-            Ordering::Less => 1,
-            _ => -1,
-        };
-        let mut err = dx + dy;
-        loop {
-            self.draw_pixel_i(x0, y0);
-            if x0 == x1 && y0 == y1 {
-                break;
+    // clip a line to the page (plus room for the pen), so huge lines don't rasterize billions of off-page pixels
+    // lines that start and end on the page are left exactly as they are
+    // returns None if the line misses the page entirely
+    fn clip_line(&self, x0: f32, y0: f32, x1: f32, y1: f32) -> Option<(f32, f32, f32, f32)> {
+        let margin = self.pen_width as f64 + 1.0;
+        let min_x = -(self.x_offset as f64) - margin;
+        let min_y = -(self.y_offset as f64) - margin;
+        let max_x = self.width as f64 - self.x_offset as f64 + margin;
+        let max_y = self.height as f64 - self.y_offset as f64 + margin;
+        let inside = |x: f32, y: f32| (min_x..=max_x).contains(&(x as f64)) && (min_y..=max_y).contains(&(y as f64));
+        if inside(x0, y0) && inside(x1, y1) {
+            return Some((x0, y0, x1, y1));
+        }
+        // liang-barsky: narrow down the range of t (along the line) that's within every edge
+        let (x0, y0, dx, dy) = (x0 as f64, y0 as f64, x1 as f64 - x0 as f64, y1 as f64 - y0 as f64);
+        let (mut t0, mut t1) = (0.0, 1.0);
+        for (p, q) in [(-dx, x0 - min_x), (dx, max_x - x0), (-dy, y0 - min_y), (dy, max_y - y0)] {
+            if p == 0.0 {
+                // parallel to this edge, so it's either entirely inside or entirely outside
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                t0 = f64::max(t0, q / p);
+            } else {
+                t1 = f64::min(t1, q / p);
             }
-            let e2 = 2 * err;
-            if e2 >= dy {
-                err += dy;
-                x0 += sx;
+        }
+        if t0 > t1 {
+            None
+        } else {
+            Some(((x0 + t0 * dx) as f32, (y0 + t0 * dy) as f32, (x0 + t1 * dx) as f32, (y0 + t1 * dy) as f32))
+        }
+    }
+
+    // fill a disc one row at a time, using the same edge as the midpoint circle so outline and fill line up
+    fn fill_disc(&mut self, x: isize, y: isize, radius: isize) {
+        // the page edges saturate, since a page offset by nearly isize::MAX has edges that don't fit in an isize
+        let (top, bottom) = (self.y_offset.saturating_neg(), (self.height as isize).saturating_sub(self.y_offset));
+        let (left_edge, right_edge) = (self.x_offset.saturating_neg(), (self.width as isize).saturating_sub(self.x_offset));
+        let rows = cmp::max(-radius, top.saturating_sub(y))..=cmp::min(radius, bottom.saturating_sub(y).saturating_sub(1));
+        for dy in rows {
+            let reach = ((radius * radius + radius - dy * dy) as f64).sqrt() as isize;
+            let left = cmp::max(x.saturating_sub(reach), left_edge);
+            let right = cmp::min(x.saturating_add(reach), right_edge.saturating_sub(1));
+            for column in left..=right {
+                self.draw_pixel_i(column, y + dy);
             }
-            if e2 <= dx {
-                err += dx;
-                y0 += sy;
+        }
+    }
+
+    // plot a line with bresenham's algorithm
+    fn plot_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize) {
+        let points = line_points(x0, y0, x1, y1);
+        // a line carrying on from the last one starts on the pixel that one ended on, which is already drawn
+        // skipping it keeps translucent joins from being composited twice
+        let skip = usize::from(self.line_end == Some((x0, y0)));
+        self.stamp_points(&points[skip..]);
+        self.line_end = Some((x1, y1));
+    }
+
+    // composite the pen color onto a pixel, with its alpha scaled by how much of the pixel is covered
+    fn draw_pixel_coverage(&mut self, x: isize, y: isize, coverage: f32) {
+        if let Some(index) = self.pixel_index(x, y) {
+            if coverage > 0.0 && self.stencil.as_ref().is_none_or(|stencil| stencil[index]) {
+                let Color(r, g, b, a) = self.pen_color;
+                let color = Color(r, g, b, (a as f32 * coverage.min(1.0)).round() as u8);
+                self.buffer.blend(index, self.blend_mode, color);
             }
         }
     }
+
+    // xiaolin wu's line algorithm, which shares each point of the line between the two pixels nearest to it
+    fn plot_smooth_line(&mut self, mut x0: f32, mut y0: f32, mut x1: f32, mut y1: f32) {
+        // step along whichever axis the line is longer in, left to right
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            (x0, y0, x1, y1) = (y0, x0, y1, x1);
+        }
+        if x0 > x1 {
+            (x0, y0, x1, y1) = (x1, y1, x0, y0);
+        }
+        let plot = |canvas: &mut PixelCanvas, x: isize, y: f32, coverage: f32| {
+            // split between the pixel the point is in and the one below it
+            let (y, below) = (y.floor() as isize, fractional_part(y));
+            let (x0, y0, x1, y1) = if steep { (y, x, y + 1, x) } else { (x, y, x, y + 1) };
+            canvas.draw_pixel_coverage(x0, y0, (1.0 - below) * coverage);
+            canvas.draw_pixel_coverage(x1, y1, below * coverage);
+        };
+        let gradient = if x1 == x0 { 1.0 } else { (y1 - y0) / (x1 - x0) };
+        // the end pixels are only partly covered, depending on where inside them the line stops
+        let (start_x, end_x) = (x0.round(), x1.round());
+        let start_y = y0 + gradient * (start_x - x0);
+        plot(self, start_x as isize, start_y, 0.5 - (x0 - start_x));
+        plot(self, end_x as isize, y1 + gradient * (end_x - x1), 0.5 + (x1 - end_x));
+        let mut y = start_y + gradient;
+        for x in start_x as isize + 1..end_x as isize {
+            plot(self, x, y, 1.0);
+            y += gradient;
+        }
+    }
+}
+
+// fractional part of a number, measured up from its floor (so it's never negative)
+fn fractional_part(x: f32) -> f32 {
+    x - x.floor()
+}
+
+// bresenham's line algorithm
+fn line_points(mut x0: isize, mut y0: isize, x1: isize, y1: isize) -> Vec<(isize, isize)> {
+    let dx = (x1 - x0).abs();
+    let sx = match x0.cmp(&x1) {
+        Ordering::Less => 1,
+        _ => -1,
+    };
+    let dy = -(y1 - y0).abs();
+    let sy = match y0.cmp(&y1) {
+        Ordering::Less => 1,
+        _ => -1,
+    };
+    let mut err = dx + dy;
+    let mut points = vec![];
+    loop {
+        points.push((x0, y0));
+        if x0 == x1 && y0 == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x0 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y0 += sy;
+        }
+    }
+    points
+}
+
+// offsets of the pixels on a circle of the given radius, with the midpoint circle algorithm
+// one octant is traced and mirrored into the other seven, so points where octants meet show up more than once
+fn circle_points(radius: isize) -> Vec<(isize, isize)> {
+    let (mut x, mut y) = (radius, 0);
+    let mut err = 1 - radius;
+    let mut points = vec![];
+    while x >= y {
+        for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+            points.push((dx, dy));
+        }
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+    points
 }
 
 impl DrawingCanvas for PixelCanvas {
     fn move_pen_to(&mut self, new_x: f32, new_y: f32) {
-        if self.pen_color != Color::transparent() {
-            self.plot_line(
-                self.pen_x.round() as isize,
-                self.pen_y.round() as isize,
-                new_x.round() as isize,
-                new_y.round() as isize,
-            );
+        match self.clip_line(self.pen_x, self.pen_y, new_x, new_y) {
+            Some((x0, y0, x1, y1)) if self.pen_color != Color::transparent() && self.smooth_lines && self.pen_width == 1 => {
+                // smooth lines blend into each other at the joins anyway, so there's no join to skip
+                self.plot_smooth_line(x0, y0, x1, y1);
+                self.line_end = None;
+            }
+            Some((x0, y0, x1, y1)) if self.pen_color != Color::transparent() => {
+                self.plot_line(x0.round() as isize, y0.round() as isize, x1.round() as isize, y1.round() as isize);
+            }
+            _ => self.line_end = None
         }
         self.pen_x = new_x;
         self.pen_y = new_y;
     }
 
     fn blot(&mut self, x: f32, y: f32) {
-        // TODO: pen width?
-        self.draw_pixel_f(x, y);
+        self.stamp_points(&[(x.round() as isize, y.round() as isize)]);
     }
 
     fn set_color(&mut self, color: Color) {
+        // a new color should cover the join with the last line
+        if color != self.pen_color {
+            self.line_end = None;
+        }
         self.pen_color = color;
     }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        // like a new color, a new mode should cover the join with the last line
+        if mode != self.blend_mode {
+            self.line_end = None;
+        }
+        self.blend_mode = mode;
+    }
+
+    fn set_pen_width(&mut self, width: usize) {
+        self.pen_width = width;
+    }
+
+    fn set_stencil(&mut self, polygon: Option<&[(f32, f32)]>) {
+        self.stencil = polygon.map(|polygon| {
+            // scanline fill the polygon into a mask, row by row through pixel centers
+            let mut stencil = vec![false; self.width * self.height];
+            for row in 0..self.height {
+                let y = (row as f64 - self.y_offset as f64) as f32;
+                for span in util::scanline_crossings(polygon, y).chunks_exact(2) {
+                    let start = (span[0].ceil() as isize).saturating_add(self.x_offset).clamp(0, self.width as isize) as usize;
+                    let end = (span[1].floor() as isize).saturating_add(self.x_offset).saturating_add(1).clamp(0, self.width as isize) as usize;
+                    if start < end {
+                        stencil[start + row * self.width..end + row * self.width].fill(true);
+                    }
+                }
+            }
+            stencil
+        });
+    }
+
+    // scanline flood fill, using a stack of spans to check instead of recursing
+    fn fill(&mut self, x: f32, y: f32) {
+        let start = match self.pixel_index(x.round() as isize, y.round() as isize) {
+            Some(index) => index,
+            None => return
+        };
+        // only empty pixels are filled, so starting on something already drawn does nothing
+        let fillable = |canvas: &PixelCanvas, index: usize| {
+            canvas.buffer.color(index).alpha() == 0 && canvas.stencil.as_ref().is_none_or(|stencil| stencil[index])
+        };
+        if self.pen_color.alpha() == 0 || !fillable(self, start) {
+            return;
+        }
+        let mut stack = vec![(start % self.width, start / self.width)];
+        while let Some((x, y)) = stack.pop() {
+            let row = y * self.width;
+            if !fillable(self, x + row) {
+                continue;
+            }
+            // widen to the whole empty span on this row, then fill it
+            let mut left = x;
+            while left > 0 && fillable(self, left - 1 + row) {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < self.width && fillable(self, right + 1 + row) {
+                right += 1;
+            }
+            for index in left + row..=right + row {
+                self.buffer.set(index, self.pen_color);
+            }
+            // check the rows above and below, starting one span for each run of empty pixels
+            for next_y in [y.checked_sub(1), Some(y + 1).filter(|&y| y < self.height)].into_iter().flatten() {
+                let next_row = next_y * self.width;
+                let mut in_run = false;
+                for next_x in left..=right {
+                    let empty = fillable(self, next_x + next_row);
+                    if empty && !in_run {
+                        stack.push((next_x, next_y));
+                    }
+                    in_run = empty;
+                }
+            }
+        }
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        if self.pen_color == Color::transparent() {
+            return;
+        }
+        let (x, y, scale) = (x.round() as isize, y.round() as isize, cmp::max(scale, 1));
+        for (column, row) in text_pixels(text) {
+            // each font pixel is a scale x scale square
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    self.draw_pixel_i(x.saturating_add((column * scale + dx) as isize), y.saturating_add((row * scale + dy) as isize));
+                }
+            }
+        }
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        let (x, y, radius) = (x.round() as isize, y.round() as isize, radius.round() as isize);
+        // circles that miss the page entirely don't need to be rasterized at all
+        let margin = radius + self.pen_width as isize;
+        let misses_page = x.saturating_add(margin) < self.x_offset.saturating_neg()
+            || x.saturating_sub(margin) >= (self.width as isize).saturating_sub(self.x_offset)
+            || y.saturating_add(margin) < self.y_offset.saturating_neg()
+            || y.saturating_sub(margin) >= (self.height as isize).saturating_sub(self.y_offset);
+        if self.pen_color == Color::transparent() || misses_page {
+            return;
+        }
+        if filled {
+            self.fill_disc(x, y, radius);
+        } else {
+            let mut points: Vec<(isize, isize)> = circle_points(radius).into_iter().map(|(dx, dy)| (x.saturating_add(dx), y.saturating_add(dy))).collect();
+            // the octants meet at shared points, which should only be drawn once
+            points.sort_unstable();
+            points.dedup();
+            self.stamp_points(&points);
+        }
+        self.line_end = None;
+    }
 }
 
-impl SaveableCanvas for PixelCanvas {
-    fn save(&self, filename: &str) {
+impl PixelCanvas {
+    // a pixel as it's saved, on top of the background
+    fn composited(&self, color: Color) -> Color {
+        match self.background.alpha() {
+            0 => color,
+            _ => Color::overlay(color, self.background)
+        }
+    }
+
+    fn rgba_bytes(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![0; self.width * self.height * 4];
         for index in 0..self.width * self.height {
-            let Color(r, g, b, a) = self.buffer[index];
+            let Color(r, g, b, a) = self.composited(self.buffer.color(index));
             bytes[index * 4] = r;
             bytes[index * 4 + 1] = g;
             bytes[index * 4 + 2] = b;
             bytes[index * 4 + 3] = a;
         }
-        // TODO: return this error
-        image::save_buffer(
-            filename,
-            &bytes,
-            self.width as u32,
-            self.height as u32,
-            image::ColorType::Rgba8,
-        ).unwrap();
+        bytes
+    }
+
+    // pixels for formats without an alpha channel
+    // whatever is still transparent is flattened onto the background's color, or the format's usual matte without one
+    fn rgb_bytes(&self, default_matte: Color) -> Vec<u8> {
+        let Color(matte_r, matte_g, matte_b, _) = if self.background.alpha() > 0 { self.background } else { default_matte };
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.width * self.height * 3);
+        for index in 0..self.width * self.height {
+            let Color(r, g, b, a) = self.composited(self.buffer.color(index));
+            bytes.extend_from_slice(&[flatten(r, matte_r, a), flatten(g, matte_g, a), flatten(b, matte_b, a)]);
+        }
+        bytes
+    }
+
+    /// Encode the image in the given format, writing the bytes to w (a file, stdout, ...). The image crate's
+    /// encoders need to seek, so the image is encoded in memory first. PPM and JPEG have no alpha channel, so
+    /// anything transparent is flattened onto the background, or onto black (PPM) or white (JPEG) without one.
+    ///
+    /// ```
+    /// use penplot::canvas::{DrawingCanvas, PixelCanvas, SaveableCanvas};
+    /// use penplot::color::Color;
+    /// use std::fs;
+    ///
+    /// let mut canvas = PixelCanvas::new(3, 2, 0, 0);
+    /// canvas.set_color(Color(255, 255, 255, 255));
+    /// canvas.blot(1.0, 1.0);
+    /// let filename = std::env::temp_dir().join("penplot_doctest_round_trip.ppm");
+    /// let filename = filename.to_str().unwrap();
+    /// canvas.save(filename).unwrap();
+    /// let bytes = fs::read(filename).unwrap();
+    /// let header = b"P6\n3 2\n255\n";
+    /// assert_eq!(&bytes[..header.len()], header);
+    /// // pixels are in rows, so (1, 1) is the fifth of six
+    /// let pixels = &bytes[header.len()..];
+    /// assert_eq!(pixels.len(), 3 * 2 * 3);
+    /// assert_eq!(pixels.chunks(3).position(|pixel| pixel == [255, 255, 255]), Some(4));
+    /// assert!(pixels.chunks(3).filter(|pixel| *pixel != [255, 255, 255]).all(|pixel| pixel == [0, 0, 0]));
+    ///
+    /// // with a background, the empty pixels are the background's color
+    /// let canvas = canvas.with_background(Color(0, 0, 255, 128));
+    /// canvas.save(filename).unwrap();
+    /// let bytes = fs::read(filename).unwrap();
+    /// assert_eq!(&bytes[header.len()..header.len() + 3], [0, 0, 255]);
+    /// fs::remove_file(filename).unwrap();
+    /// ```
+    pub fn encode_to_writer(&self, mut w: impl io::Write, format: RasterFormat) -> IoResult<()> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let mut encoded = Cursor::new(vec![]);
+        let result = match format {
+            // binary ppm needs no image codecs to write or read
+            RasterFormat::Ppm => {
+                encoded.get_mut().extend_from_slice(format!("P6\n{} {}\n255\n", self.width, self.height).as_bytes());
+                encoded.get_mut().extend_from_slice(&self.rgb_bytes(Color(0, 0, 0, 255)));
+                Ok(())
+            }
+            // jpeg's usual matte is white, since that's what most photos and pages sit on
+            RasterFormat::Jpeg => image::write_buffer_with_format(
+                &mut encoded, &self.rgb_bytes(Color(255, 255, 255, 255)), width, height, image::ColorType::Rgb8, ImageFormat::Jpeg
+            ),
+            // formats that can keep the alpha channel are handed straight to the image crate
+            RasterFormat::Png | RasterFormat::Bmp | RasterFormat::Gif | RasterFormat::Tiff => {
+                let image_format = match format {
+                    RasterFormat::Bmp => ImageFormat::Bmp,
+                    RasterFormat::Gif => ImageFormat::Gif,
+                    RasterFormat::Tiff => ImageFormat::Tiff,
+                    _ => ImageFormat::Png
+                };
+                image::write_buffer_with_format(
+                    &mut encoded, &self.rgba_bytes(), width, height, image::ColorType::Rgba8, image_format
+                )
+            }
+        };
+        result.map_err(io::Error::other)?;
+        w.write_all(encoded.get_ref())?;
+        w.flush()
+    }
+
+    // copy of the canvas as it is right now, e.g. to use as a frame of an animation
+    pub fn to_frame(&self) -> RgbaImage {
+        RgbaImage::from_raw(self.width as u32, self.height as u32, self.rgba_bytes())
+            .expect("buffer should match canvas dimensions")
+    }
+}
+
+// the formats a pixel canvas can be saved in, picked by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Gif,
+    Tiff,
+    Ppm
+}
+
+const RASTER_EXTENSIONS: &str = ".png, .jpg, .jpeg, .bmp, .gif, .tif, .tiff or .ppm";
+
+impl RasterFormat {
+    pub fn from_filename(filename: &str) -> Option<RasterFormat> {
+        let extension = Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(RasterFormat::Png),
+            "jpg" | "jpeg" => Some(RasterFormat::Jpeg),
+            "bmp" => Some(RasterFormat::Bmp),
+            "gif" => Some(RasterFormat::Gif),
+            "tif" | "tiff" => Some(RasterFormat::Tiff),
+            "ppm" => Some(RasterFormat::Ppm),
+            _ => None
+        }
+    }
+}
+
+impl SaveableCanvas for PixelCanvas {
+    fn save(&self, filename: &str) -> IoResult<()> {
+        let format = RasterFormat::from_filename(filename).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image format, expected {}", RASTER_EXTENSIONS)
+        ))?;
+        self.encode_to_writer(BufWriter::new(File::create(filename)?), format)
     }
 }
 
-// "canvas" that merely keeps track of the bounding box of the drawing
-// this can be used to compute offsets / necessary width
+/// "Canvas" that merely keeps track of the bounding box of the drawing.
+/// This can be used to compute offsets / necessary width. Only what's actually drawn counts, so travelling
+/// somewhere with the pen up (a transparent pen) doesn't make the canvas any bigger.
+///
+/// ```
+/// use penplot::canvas::{DrawingCanvas, SizingCanvas};
+/// use penplot::color::Color;
+///
+/// let mut canvas = SizingCanvas::new();
+/// canvas.set_color(Color(255, 255, 255, 255));
+/// canvas.move_pen_to(10.0, 10.0);
+/// canvas.set_color(Color::transparent());
+/// canvas.move_pen_to(100000.0, -100000.0);
+/// assert_eq!(canvas.dimensions(), (11, 11));
+/// ```
 pub struct SizingCanvas {
     min_x: isize,
     min_y: isize,
     max_x: isize,
-    max_y: isize
+    max_y: isize,
+    pen_x: f32,
+    pen_y: f32,
+    // whether the pen puts anything down, since only what's drawn counts towards the bounds
+    drawing: bool
 }
 
 impl SizingCanvas {
@@ -152,7 +971,10 @@ impl SizingCanvas {
             min_x: 0,
             min_y: 0,
             max_x: 0,
-            max_y: 0
+            max_y: 0,
+            pen_x: 0.0,
+            pen_y: 0.0,
+            drawing: false
         }
     }
 
@@ -178,17 +1000,1157 @@ impl SizingCanvas {
     }
 }
 
+impl Default for SizingCanvas {
+    fn default() -> Self {
+        SizingCanvas::new()
+    }
+}
+
 impl DrawingCanvas for SizingCanvas {
+    // a line covers both its ends, and the start might have been reached with the pen up
     fn move_pen_to(&mut self, x: f32, y: f32) {
-        self.update_values(x.round() as isize, y.round() as isize);
+        if self.drawing {
+            self.update_values(self.pen_x.round() as isize, self.pen_y.round() as isize);
+            self.update_values(x.round() as isize, y.round() as isize);
+        }
+        self.pen_x = x;
+        self.pen_y = y;
     }
 
-    // the below methods are no-ops since color / blotting doesn't matter
-    fn blot(&mut self, _x: f32, _y: f32) {
+    fn blot(&mut self, x: f32, y: f32) {
+        if self.drawing {
+            self.update_values(x.round() as isize, y.round() as isize);
+        }
+    }
+
+    // the pen is lifted by making it transparent
+    fn set_color(&mut self, color: Color) {
+        self.drawing = color != Color::transparent();
+    }
+
+    // the below methods are no-ops since blend modes / pen width / stencils don't matter
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    fn set_pen_width(&mut self, _width: usize) {
+
+    }
+
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // a fill can't spread past the edges of the canvas, so it doesn't change the bounds
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        let (width, height) = text_size(text);
+        if self.drawing && width > 0 {
+            let (x, y, scale) = (x.round() as isize, y.round() as isize, cmp::max(scale, 1));
+            self.update_values(x, y);
+            self.update_values(x + (width * scale) as isize - 1, y + (height * scale) as isize - 1);
+        }
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, _filled: bool) {
+        if self.drawing {
+            self.update_values((x - radius).round() as isize, (y - radius).round() as isize);
+            self.update_values((x + radius).round() as isize, (y + radius).round() as isize);
+        }
+    }
+}
+
+// a single call made on a drawing canvas
+#[derive(Clone, Debug)]
+enum DrawCommand {
+    MovePenTo(f32, f32),
+    Blot(f32, f32),
+    SetColor(Color),
+    SetBlendMode(BlendMode),
+    SetPenWidth(usize),
+    SetStencil(Option<Vec<(f32, f32)>>),
+    Fill(f32, f32),
+    Text(f32, f32, String, usize),
+    Circle(f32, f32, f32, bool)
+}
+
+// canvas that records every drawing call while keeping track of the bounding box
+// this lets a program execute once, and then be replayed onto a canvas of the right size
+// each call takes 40 bytes (plus the text of TEXT), so a 16 iteration dragon curve's 65,537 calls come to 5 MiB with
+// the vec's spare room: more than its image, but it only grows as far as the step limit lets the program run, and it
+// saves running the program a second time (benches/auto_size.rs times both ways)
+pub struct RecordingCanvas {
+    sizing: SizingCanvas,
+    commands: Vec<DrawCommand>
+}
+
+impl RecordingCanvas {
+    pub fn new() -> Self {
+        RecordingCanvas {
+            sizing: SizingCanvas::new(),
+            commands: vec![]
+        }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        self.sizing.dimensions()
+    }
+
+    pub fn offsets(&self) -> (isize, isize) {
+        self.sizing.offsets()
+    }
+
+    // how many calls have been recorded, and about how many bytes the recording takes up (not counting text)
+    pub fn recorded(&self) -> (usize, usize) {
+        (self.commands.len(), self.commands.capacity() * mem::size_of::<DrawCommand>())
+    }
+
+    // perform every recorded call on another canvas
+    pub fn replay<T: DrawingCanvas>(&self, canvas: &mut T) {
+        for command in &self.commands {
+            match *command {
+                DrawCommand::MovePenTo(x, y) => canvas.move_pen_to(x, y),
+                DrawCommand::Blot(x, y) => canvas.blot(x, y),
+                DrawCommand::SetColor(color) => canvas.set_color(color),
+                DrawCommand::SetBlendMode(mode) => canvas.set_blend_mode(mode),
+                DrawCommand::SetPenWidth(width) => canvas.set_pen_width(width),
+                DrawCommand::SetStencil(ref polygon) => canvas.set_stencil(polygon.as_deref()),
+                DrawCommand::Fill(x, y) => canvas.fill(x, y),
+                DrawCommand::Text(x, y, ref text, scale) => canvas.draw_text(x, y, text, scale),
+                DrawCommand::Circle(x, y, radius, filled) => canvas.draw_circle(x, y, radius, filled)
+            }
+        }
+    }
+}
 
+impl Default for RecordingCanvas {
+    fn default() -> Self {
+        RecordingCanvas::new()
+    }
+}
+
+impl DrawingCanvas for RecordingCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        self.sizing.move_pen_to(x, y);
+        self.commands.push(DrawCommand::MovePenTo(x, y));
     }
 
-    fn set_color(&mut self, _color: Color) {
+    fn blot(&mut self, x: f32, y: f32) {
+        self.sizing.blot(x, y);
+        self.commands.push(DrawCommand::Blot(x, y));
+    }
 
+    fn set_color(&mut self, color: Color) {
+        self.sizing.set_color(color);
+        self.commands.push(DrawCommand::SetColor(color));
     }
-}
\ No newline at end of file
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.sizing.set_blend_mode(mode);
+        self.commands.push(DrawCommand::SetBlendMode(mode));
+    }
+
+    fn set_pen_width(&mut self, width: usize) {
+        self.sizing.set_pen_width(width);
+        self.commands.push(DrawCommand::SetPenWidth(width));
+    }
+
+    fn set_stencil(&mut self, polygon: Option<&[(f32, f32)]>) {
+        self.sizing.set_stencil(polygon);
+        self.commands.push(DrawCommand::SetStencil(polygon.map(|polygon| polygon.to_vec())));
+    }
+
+    fn fill(&mut self, x: f32, y: f32) {
+        self.sizing.fill(x, y);
+        self.commands.push(DrawCommand::Fill(x, y));
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        self.sizing.draw_text(x, y, text, scale);
+        self.commands.push(DrawCommand::Text(x, y, text.to_string(), scale));
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        self.sizing.draw_circle(x, y, radius, filled);
+        self.commands.push(DrawCommand::Circle(x, y, radius, filled));
+    }
+}
+
+// canvas adapter that scales everything drawn by a whole number factor, for supersampling
+// the canvas underneath should be factor times the size (with offsets scaled too), and downsampled afterwards
+// it can also turn the program's units into pixels first, e.g. for programs measured in millimeters
+pub struct ScaledCanvas<C: DrawingCanvas> {
+    canvas: C,
+    factor: usize,
+    // pixels per unit of the program's coordinates
+    unit_scale: f32
+}
+
+impl<C: DrawingCanvas> ScaledCanvas<C> {
+    pub fn new(mut canvas: C, factor: usize) -> Self {
+        let factor = cmp::max(factor, 1);
+        // canvases start with a one pixel pen, which has to be scaled up like any other width
+        canvas.set_pen_width(factor);
+        ScaledCanvas { canvas, factor, unit_scale: 1.0 }
+    }
+
+    // positions and radii are converted from units to pixels, but pen widths and text sizes are already in pixels
+    pub fn with_unit_scale(mut self, unit_scale: f32) -> Self {
+        self.unit_scale = unit_scale;
+        self
+    }
+
+    pub fn into_inner(self) -> C {
+        self.canvas
+    }
+
+    // a point maps to the center of its block of pixels, so it lands on the same pixel once downsampled
+    fn scale(&self, x: f32, y: f32) -> (f32, f32) {
+        let factor = self.factor as f32;
+        let center = (factor - 1.0) / 2.0;
+        (x * self.unit_scale * factor + center, y * self.unit_scale * factor + center)
+    }
+}
+
+impl<C: DrawingCanvas> DrawingCanvas for ScaledCanvas<C> {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.scale(x, y);
+        self.canvas.move_pen_to(x, y);
+    }
+
+    fn blot(&mut self, x: f32, y: f32) {
+        let (x, y) = self.scale(x, y);
+        self.canvas.blot(x, y);
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.canvas.set_color(color);
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.canvas.set_blend_mode(mode);
+    }
+
+    fn set_pen_width(&mut self, width: usize) {
+        self.canvas.set_pen_width(width * self.factor);
+    }
+
+    fn set_stencil(&mut self, polygon: Option<&[(f32, f32)]>) {
+        let polygon: Option<Vec<(f32, f32)>> = polygon.map(|polygon| polygon.iter().map(|&(x, y)| self.scale(x, y)).collect());
+        self.canvas.set_stencil(polygon.as_deref());
+    }
+
+    fn fill(&mut self, x: f32, y: f32) {
+        let (x, y) = self.scale(x, y);
+        self.canvas.fill(x, y);
+    }
+
+    // text is placed by its top left pixel, so it goes at the corner of that pixel's block instead of the center
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        let factor = self.factor as f32;
+        self.canvas.draw_text((x * self.unit_scale).round() * factor, (y * self.unit_scale).round() * factor, text, scale * self.factor);
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        let (x, y) = self.scale(x, y);
+        self.canvas.draw_circle(x, y, radius * self.unit_scale * self.factor as f32, filled);
+    }
+}
+
+// "canvas" that measures how far the pen travels, for estimating how long a plotter would take
+pub struct StatsCanvas {
+    pen_x: f32,
+    pen_y: f32,
+    pen_color: Color,
+    pen_down: bool,
+    draw_distance: f32,
+    travel_distance: f32,
+    pen_lifts: usize
+}
+
+impl StatsCanvas {
+    pub fn new() -> Self {
+        StatsCanvas {
+            pen_x: 0.0,
+            pen_y: 0.0,
+            pen_color: Color::transparent(),
+            pen_down: false,
+            draw_distance: 0.0,
+            travel_distance: 0.0,
+            pen_lifts: 0
+        }
+    }
+
+    // distance travelled with the pen down
+    pub fn draw_distance(&self) -> f32 {
+        self.draw_distance
+    }
+
+    // distance travelled with the pen up
+    pub fn travel_distance(&self) -> f32 {
+        self.travel_distance
+    }
+
+    // number of times the pen has to be put down and lifted again
+    pub fn pen_lifts(&self) -> usize {
+        self.pen_lifts
+    }
+}
+
+impl Default for StatsCanvas {
+    fn default() -> Self {
+        StatsCanvas::new()
+    }
+}
+
+impl DrawingCanvas for StatsCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        let distance = (x - self.pen_x).hypot(y - self.pen_y);
+        if self.pen_color == Color::transparent() {
+            self.travel_distance += distance;
+            self.pen_down = false;
+        } else {
+            if !self.pen_down {
+                self.pen_lifts += 1;
+                self.pen_down = true;
+            }
+            self.draw_distance += distance;
+        }
+        self.pen_x = x;
+        self.pen_y = y;
+    }
+
+    fn blot(&mut self, _x: f32, _y: f32) {
+        // a blot is the pen touching down and coming straight back up
+        if self.pen_color != Color::transparent() {
+            self.pen_lifts += 1;
+            self.pen_down = false;
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.pen_color = color;
+    }
+
+    // blend modes, pen width and stencils don't change how far the pen moves
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    fn set_pen_width(&mut self, _width: usize) {
+
+    }
+
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // fills don't move the pen
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    // text is stamped in place, not drawn by moving the pen
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _scale: usize) {
+
+    }
+
+    // an outline is the pen going once around the circle, and filled circles are skipped like other fills
+    fn draw_circle(&mut self, _x: f32, _y: f32, radius: f32, filled: bool) {
+        if self.pen_color != Color::transparent() && !filled {
+            self.pen_lifts += 1;
+            self.pen_down = false;
+            self.draw_distance += std::f32::consts::TAU * radius;
+        }
+    }
+}
+
+// how long each side of the polygon standing in for a circle's outline is (at most)
+const CIRCLE_SEGMENT_LENGTH: f32 = 2.0;
+
+// "canvas" that records every pen-down stroke as a polyline
+// this is meant for handing the drawing off to other vector tools (plotters, three.js, etc.)
+pub struct PolylineCanvas {
+    pen_x: f32,
+    pen_y: f32,
+    pen_color: Color,
+    // whether the last polyline can still be extended by the next move
+    extending: bool,
+    polylines: Vec<(Color, Vec<(f32, f32)>)>
+}
+
+impl PolylineCanvas {
+    pub fn new() -> Self {
+        PolylineCanvas {
+            pen_x: 0.0,
+            pen_y: 0.0,
+            pen_color: Color::transparent(),
+            extending: false,
+            polylines: vec![]
+        }
+    }
+}
+
+impl Default for PolylineCanvas {
+    fn default() -> Self {
+        PolylineCanvas::new()
+    }
+}
+
+impl DrawingCanvas for PolylineCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        if self.pen_color == Color::transparent() {
+            // pen up, so the next stroke starts a new polyline
+            self.extending = false;
+        } else {
+            if !self.extending {
+                self.polylines.push((self.pen_color, vec![(self.pen_x, self.pen_y)]));
+                self.extending = true;
+            }
+            if let Some((_, points)) = self.polylines.last_mut() {
+                points.push((x, y));
+            }
+        }
+        self.pen_x = x;
+        self.pen_y = y;
+    }
+
+    fn blot(&mut self, x: f32, y: f32) {
+        // a blot is recorded as a single-point polyline
+        if self.pen_color != Color::transparent() {
+            self.polylines.push((self.pen_color, vec![(x, y)]));
+            self.extending = false;
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        if color != self.pen_color {
+            self.extending = false;
+        }
+        self.pen_color = color;
+    }
+
+    // polylines are just paths, so how they'd be blended doesn't matter
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    // polylines have no width, so this is a no-op
+    fn set_pen_width(&mut self, _width: usize) {
+
+    }
+
+    // strokes are recorded whole, so stencils are ignored
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // fills aren't strokes
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    // neither is text
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _scale: usize) {
+
+    }
+
+    // outlines are recorded as closed polylines around the circle, and filled circles are skipped like other fills
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        if self.pen_color != Color::transparent() && !filled {
+            let segments = cmp::max((std::f32::consts::TAU * radius / CIRCLE_SEGMENT_LENGTH).ceil() as usize, 8);
+            let points = (0..=segments).map(|index| {
+                let angle = std::f32::consts::TAU * index as f32 / segments as f32;
+                (x + radius * angle.cos(), y + radius * angle.sin())
+            }).collect();
+            self.polylines.push((self.pen_color, points));
+            self.extending = false;
+        }
+    }
+}
+
+impl SaveableCanvas for PolylineCanvas {
+    fn save(&self, filename: &str) -> IoResult<()> {
+        // the output is simple enough that we write the JSON by hand
+        let mut json = String::from("[\n");
+        for (index, (color, points)) in self.polylines.iter().enumerate() {
+            let points: Vec<String> = points.iter().map(|(x, y)| format!("[{}, {}]", x, y)).collect();
+            let separator = if index + 1 < self.polylines.len() { "," } else { "" };
+            writeln!(json, "  {{\"color\": \"{}\", \"points\": [{}]}}{}", color, points.join(", "), separator).unwrap();
+        }
+        json.push_str("]\n");
+        fs::write(filename, json)
+    }
+}
+
+// a line drawn on a path canvas, as its segment id, start, end, and color (blots start and end at the same point)
+type PathLine = (usize, (f32, f32), (f32, f32), Color);
+
+// "canvas" that records every pen-down line as its own row of a CSV, for analysis or other plotters
+// unbroken runs of lines share a segment id, and a new one starts whenever the pen lifts or changes color
+pub struct PathCanvas {
+    pen_x: f32,
+    pen_y: f32,
+    pen_color: Color,
+    // whether the next line carries on the current segment
+    extending: bool,
+    segments: usize,
+    lines: Vec<PathLine>
+}
+
+impl PathCanvas {
+    pub fn new() -> Self {
+        PathCanvas {
+            pen_x: 0.0,
+            pen_y: 0.0,
+            pen_color: Color::transparent(),
+            extending: false,
+            segments: 0,
+            lines: vec![]
+        }
+    }
+
+    // the id for a line, starting a new segment if this one can't be extended
+    fn segment_id(&mut self, extend: bool) -> usize {
+        if !(extend && self.extending) {
+            self.segments += 1;
+        }
+        self.extending = extend;
+        self.segments - 1
+    }
+}
+
+impl Default for PathCanvas {
+    fn default() -> Self {
+        PathCanvas::new()
+    }
+}
+
+impl DrawingCanvas for PathCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        if self.pen_color == Color::transparent() {
+            self.extending = false;
+        } else {
+            let id = self.segment_id(true);
+            self.lines.push((id, (self.pen_x, self.pen_y), (x, y), self.pen_color));
+        }
+        self.pen_x = x;
+        self.pen_y = y;
+    }
+
+    // a blot is a segment of its own, with no length
+    fn blot(&mut self, x: f32, y: f32) {
+        if self.pen_color != Color::transparent() {
+            let id = self.segment_id(false);
+            self.lines.push((id, (x, y), (x, y), self.pen_color));
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        if color != self.pen_color {
+            self.extending = false;
+        }
+        self.pen_color = color;
+    }
+
+    // the path is the same however it's blended
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    // lines have no width, so this is a no-op
+    fn set_pen_width(&mut self, _width: usize) {
+
+    }
+
+    // lines are recorded whole, so stencils are ignored
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // fills aren't paths
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    // neither is text
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _scale: usize) {
+
+    }
+
+    // outlines are recorded as a segment of short lines around the circle, and filled circles are skipped like other fills
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        if self.pen_color != Color::transparent() && !filled {
+            let segments = cmp::max((std::f32::consts::TAU * radius / CIRCLE_SEGMENT_LENGTH).ceil() as usize, 8);
+            let point = |index: usize| {
+                let angle = std::f32::consts::TAU * index as f32 / segments as f32;
+                (x + radius * angle.cos(), y + radius * angle.sin())
+            };
+            let id = self.segment_id(false);
+            for index in 0..segments {
+                self.lines.push((id, point(index), point(index + 1), self.pen_color));
+            }
+        }
+    }
+}
+
+impl SaveableCanvas for PathCanvas {
+    fn save(&self, filename: &str) -> IoResult<()> {
+        let mut csv = String::from("segment_id,x0,y0,x1,y1,color\n");
+        for (id, (x0, y0), (x1, y1), color) in &self.lines {
+            writeln!(csv, "{},{},{},{},{},{}", id, x0, y0, x1, y1, color).unwrap();
+        }
+        fs::write(filename, csv)
+    }
+}
+
+// a stroke or dot on an svg canvas, along with the pen it was drawn with
+enum SvgShape {
+    Polyline(Color, usize, Vec<(f32, f32)>),
+    Dot(Color, usize, f32, f32),
+    // squares of the given size, with their top left pixels at each point
+    Squares(Color, usize, Vec<(f32, f32)>),
+    // center and radius, and whether it's filled in
+    Circle(Color, usize, f32, f32, f32, bool)
+}
+
+// vector graphics canvas, for plotters and laser cutters
+pub struct SvgCanvas {
+    pen_x: f32,
+    pen_y: f32,
+    pen_color: Color,
+    pen_width: usize,
+    // whether the last polyline can still be extended by the next move
+    extending: bool,
+    // the size of the image, or None to fit it to the drawing
+    size: Option<(usize, usize)>,
+    sizing: SizingCanvas,
+    shapes: Vec<SvgShape>
+}
+
+impl SvgCanvas {
+    pub fn new(size: Option<(usize, usize)>) -> Self {
+        SvgCanvas {
+            pen_x: 0.0,
+            pen_y: 0.0,
+            pen_color: Color::transparent(),
+            pen_width: 1,
+            extending: false,
+            size,
+            sizing: SizingCanvas::new(),
+            shapes: vec![]
+        }
+    }
+}
+
+// svg wants colors as #rrggbb with a separate opacity
+fn svg_color(color: Color) -> (String, f32) {
+    let Color(r, g, b, a) = color;
+    (format!("#{:02x}{:02x}{:02x}", r, g, b), a as f32 / 255.0)
+}
+
+impl DrawingCanvas for SvgCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        self.sizing.move_pen_to(x, y);
+        if self.pen_color == Color::transparent() {
+            // pen up, so the next stroke starts a new polyline
+            self.extending = false;
+        } else {
+            if !self.extending {
+                self.shapes.push(SvgShape::Polyline(self.pen_color, self.pen_width, vec![(self.pen_x, self.pen_y)]));
+                self.extending = true;
+            }
+            if let Some(SvgShape::Polyline(_, _, points)) = self.shapes.last_mut() {
+                points.push((x, y));
+            }
+        }
+        self.pen_x = x;
+        self.pen_y = y;
+    }
+
+    fn blot(&mut self, x: f32, y: f32) {
+        self.sizing.blot(x, y);
+        if self.pen_color != Color::transparent() {
+            self.shapes.push(SvgShape::Dot(self.pen_color, self.pen_width, x, y));
+            self.extending = false;
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.sizing.set_color(color);
+        if color != self.pen_color {
+            self.extending = false;
+        }
+        self.pen_color = color;
+    }
+
+    // svg viewers composite shapes normally, so blend modes only apply to pixels
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    fn set_pen_width(&mut self, width: usize) {
+        if width != self.pen_width {
+            self.extending = false;
+        }
+        self.pen_width = width;
+    }
+
+    // strokes are kept whole, so stencils are ignored
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // flood fills need pixels, so they're ignored
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    // text is drawn as a square for each font pixel, so it looks the same as it does in pixel output
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, scale: usize) {
+        if self.pen_color != Color::transparent() && !text.is_empty() {
+            let (x, y, scale) = (x.round(), y.round(), cmp::max(scale, 1));
+            self.sizing.draw_text(x, y, text, scale);
+            let corners = text_pixels(text).into_iter()
+                .map(|(column, row)| (x + (column * scale) as f32, y + (row * scale) as f32))
+                .collect();
+            self.shapes.push(SvgShape::Squares(self.pen_color, scale, corners));
+            self.extending = false;
+        }
+    }
+
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        if self.pen_color != Color::transparent() {
+            self.sizing.draw_circle(x, y, radius, filled);
+            self.shapes.push(SvgShape::Circle(self.pen_color, self.pen_width, x, y, radius, filled));
+            self.extending = false;
+        }
+    }
+}
+
+impl SaveableCanvas for SvgCanvas {
+    fn save(&self, filename: &str) -> IoResult<()> {
+        let ((width, height), (x_offset, y_offset)) = match self.size {
+            Some(size) => (size, (0, 0)),
+            None => (self.sizing.dimensions(), self.sizing.offsets())
+        };
+        // pixels are centered on whole coordinates, so the view starts half a pixel before the first one
+        let mut svg = String::new();
+        writeln!(svg, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">",
+            width, height, -x_offset as f32 - 0.5, -y_offset as f32 - 0.5, width, height).unwrap();
+        for shape in &self.shapes {
+            match shape {
+                SvgShape::Polyline(color, width, points) => {
+                    let (stroke, opacity) = svg_color(*color);
+                    let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                    writeln!(svg, "  <polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+                        points.join(" "), stroke, opacity, width).unwrap();
+                }
+                SvgShape::Dot(color, width, x, y) => {
+                    let (fill, opacity) = svg_color(*color);
+                    writeln!(svg, "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>",
+                        x, y, *width as f32 / 2.0, fill, opacity).unwrap();
+                }
+                SvgShape::Squares(color, size, corners) => {
+                    let (fill, opacity) = svg_color(*color);
+                    // pixels are centered on whole coordinates, so each square starts half a pixel up and left
+                    let path: Vec<String> = corners.iter()
+                        .map(|(x, y)| format!("M{} {}h{}v{}h-{}z", x - 0.5, y - 0.5, size, size, size))
+                        .collect();
+                    writeln!(svg, "  <path d=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>", path.join(""), fill, opacity).unwrap();
+                }
+                SvgShape::Circle(color, _, x, y, radius, true) => {
+                    let (fill, opacity) = svg_color(*color);
+                    writeln!(svg, "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>",
+                        x, y, radius, fill, opacity).unwrap();
+                }
+                SvgShape::Circle(color, width, x, y, radius, false) => {
+                    let (stroke, opacity) = svg_color(*color);
+                    writeln!(svg, "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"/>",
+                        x, y, radius, stroke, opacity, width).unwrap();
+                }
+            }
+        }
+        svg.push_str("</svg>\n");
+        fs::write(filename, svg)
+    }
+}
+
+// the pens in a plotter's carousel, by the colors they usually hold (pen 1 is the first)
+const HPGL_PENS: [Color; 7] = [
+    Color(0, 0, 0, 255), // black
+    Color(255, 0, 0, 255), // red
+    Color(0, 255, 0, 255), // green
+    Color(255, 255, 0, 255), // yellow
+    Color(0, 0, 255, 255), // blue
+    Color(255, 0, 255, 255), // magenta
+    Color(0, 255, 255, 255) // cyan
+];
+
+// the pen whose color is closest to the given color
+fn hpgl_pen(color: Color) -> usize {
+    let distance = |pen: &Color| {
+        let channels = [(pen.red(), color.red()), (pen.green(), color.green()), (pen.blue(), color.blue())];
+        channels.iter().map(|&(a, b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+    };
+    let (index, _) = HPGL_PENS.iter().enumerate().min_by_key(|(_, pen)| distance(pen)).unwrap();
+    index + 1
+}
+
+enum HpglCommand {
+    SelectPen(usize),
+    PenUp(isize, isize),
+    PenDown(isize, isize),
+    // an outline around the pen's position
+    Circle(isize)
+}
+
+// HP-GL canvas, for pen plotters
+// coordinates are rounded to whole plotter units the same way pixels are, and flipped so y goes up like on a plotter
+pub struct HpglCanvas {
+    pen_x: f32,
+    pen_y: f32,
+    pen_color: Color,
+    // the pen selected on the plotter, if any
+    pen: Option<usize>,
+    // whether the plotter's pen is down at (pen_x, pen_y), so the next stroke can carry on without lifting it
+    lowered: bool,
+    // the size of the drawing, or None to fit it to what's drawn
+    size: Option<(usize, usize)>,
+    sizing: SizingCanvas,
+    commands: Vec<HpglCommand>
+}
+
+impl HpglCanvas {
+    pub fn new(size: Option<(usize, usize)>) -> Self {
+        HpglCanvas {
+            pen_x: 0.0,
+            pen_y: 0.0,
+            pen_color: Color::transparent(),
+            pen: None,
+            lowered: false,
+            size,
+            sizing: SizingCanvas::new(),
+            commands: vec![]
+        }
+    }
+
+    // lift the pen and move it to where the next stroke starts, unless it's already there
+    fn start_stroke(&mut self, x: f32, y: f32) {
+        if !self.lowered || (x, y) != (self.pen_x, self.pen_y) {
+            self.commands.push(HpglCommand::PenUp(x.round() as isize, y.round() as isize));
+        }
+    }
+}
+
+impl DrawingCanvas for HpglCanvas {
+    fn move_pen_to(&mut self, x: f32, y: f32) {
+        self.sizing.move_pen_to(x, y);
+        if self.pen_color == Color::transparent() {
+            // the pen only actually lifts once the next stroke starts, so travel moves in a row become one
+            self.lowered = false;
+        } else {
+            self.start_stroke(self.pen_x, self.pen_y);
+            self.commands.push(HpglCommand::PenDown(x.round() as isize, y.round() as isize));
+            self.lowered = true;
+        }
+        self.pen_x = x;
+        self.pen_y = y;
+    }
+
+    // a blot is the pen touching down and coming straight back up
+    fn blot(&mut self, x: f32, y: f32) {
+        self.sizing.blot(x, y);
+        if self.pen_color != Color::transparent() {
+            self.start_stroke(x, y);
+            self.commands.push(HpglCommand::PenDown(x.round() as isize, y.round() as isize));
+            self.lowered = false;
+        }
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.sizing.set_color(color);
+        self.pen_color = color;
+        if color != Color::transparent() {
+            let pen = hpgl_pen(color);
+            if self.pen != Some(pen) {
+                // changing pens puts the old one away, lifting it
+                self.commands.push(HpglCommand::SelectPen(pen));
+                self.pen = Some(pen);
+                self.lowered = false;
+            }
+        }
+    }
+
+    // pens draw what they draw, so there's nothing to blend
+    fn set_blend_mode(&mut self, _mode: BlendMode) {
+
+    }
+
+    // and they're as wide as they are
+    fn set_pen_width(&mut self, _width: usize) {
+
+    }
+
+    // strokes are kept whole, so stencils are ignored
+    fn set_stencil(&mut self, _polygon: Option<&[(f32, f32)]>) {
+
+    }
+
+    // flood fills need pixels, so they're ignored
+    fn fill(&mut self, _x: f32, _y: f32) {
+
+    }
+
+    // the pixel font can't be drawn with strokes
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _scale: usize) {
+
+    }
+
+    // plotters draw circles themselves, and filled circles are skipped like other fills
+    fn draw_circle(&mut self, x: f32, y: f32, radius: f32, filled: bool) {
+        if self.pen_color != Color::transparent() && !filled {
+            self.sizing.draw_circle(x, y, radius, filled);
+            self.commands.push(HpglCommand::PenUp(x.round() as isize, y.round() as isize));
+            self.commands.push(HpglCommand::Circle(radius.round() as isize));
+            // the pen comes back up in the middle of the circle
+            self.lowered = false;
+        }
+    }
+}
+
+impl SaveableCanvas for HpglCanvas {
+    fn save(&self, filename: &str) -> IoResult<()> {
+        let ((_, height), (x_offset, y_offset)) = match self.size {
+            Some(size) => (size, (0, 0)),
+            None => (self.sizing.dimensions(), self.sizing.offsets())
+        };
+        // y goes down in the program but up on the plotter, so the bottom row of the drawing goes at y = 0
+        let top = height as isize - 1 - y_offset;
+        let mut hpgl = String::from("IN;\n");
+        for command in &self.commands {
+            match command {
+                HpglCommand::SelectPen(pen) => writeln!(hpgl, "SP{};", pen),
+                HpglCommand::PenUp(x, y) => writeln!(hpgl, "PU{},{};", x + x_offset, top - y),
+                HpglCommand::PenDown(x, y) => writeln!(hpgl, "PD{},{};", x + x_offset, top - y),
+                HpglCommand::Circle(radius) => writeln!(hpgl, "CI{};", radius)
+            }.unwrap();
+        }
+        // park the pen, so it doesn't dry out on the paper
+        hpgl.push_str("PU;\nSP0;\n");
+        fs::write(filename, hpgl)
+    }
+}
+
+// the built-in font: 5x7 pixel glyphs for printable ascii, starting at ' '
+// each glyph is five columns, left to right, with the top pixel in the lowest bit
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x01, 0x01], // 'F'
+    [0x3E, 0x41, 0x41, 0x51, 0x32], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x04, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x7F, 0x20, 0x18, 0x20, 0x7F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x03, 0x04, 0x78, 0x04, 0x03], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x08, 0x54, 0x54, 0x54, 0x3C], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// glyphs are spaced one pixel apart
+const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+// width and height of some text in font pixels
+pub fn text_size(text: &str) -> (usize, usize) {
+    let length = text.chars().count();
+    ((length * GLYPH_ADVANCE).saturating_sub(1), if length == 0 { 0 } else { GLYPH_HEIGHT })
+}
+
+// every lit pixel of some text, measured in font pixels from its top left corner
+// characters outside printable ascii are drawn as '?'
+fn text_pixels(text: &str) -> Vec<(usize, usize)> {
+    let mut pixels = vec![];
+    for (index, c) in text.chars().enumerate() {
+        let glyph = match c {
+            ' '..='~' => FONT[c as usize - ' ' as usize],
+            _ => FONT['?' as usize - ' ' as usize]
+        };
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) != 0 {
+                    pixels.push((index * GLYPH_ADVANCE + column, row));
+                }
+            }
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every pixel of a canvas, row by row
+    fn pixels(canvas: &PixelCanvas) -> Vec<Color> {
+        (0..canvas.height()).flat_map(|y| (0..canvas.width()).map(move |x| (x, y)))
+            .map(|(x, y)| canvas.pixel(x, y).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn translucent_joins_are_drawn_once() {
+        // a line carrying on from where the last one ended doesn't draw the pixel they share again
+        let mut canvas = PixelCanvas::new(10, 10, 0, 0);
+        canvas.move_pen_to(1.0, 5.0);
+        canvas.set_color(Color(255, 255, 255, 128));
+        canvas.move_pen_to(5.0, 5.0);
+        canvas.move_pen_to(5.0, 1.0);
+        let alpha = |x, y| canvas.pixel(x, y).unwrap().alpha();
+        assert_eq!(alpha(3, 5), 128);
+        assert_eq!(alpha(5, 5), alpha(3, 5));
+        assert_eq!(alpha(5, 3), alpha(3, 5));
+    }
+
+    #[test]
+    fn fills_only_color_under_the_stencil() {
+        let mut canvas = PixelCanvas::new(20, 20, 0, 0);
+        canvas.set_stencil(Some(&[(0.0, 0.0), (19.0, 0.0), (0.0, 19.0)]));
+        canvas.set_color(Color(255, 255, 255, 255));
+        canvas.fill(2.0, 2.0);
+        // the fill would cover the whole canvas, but only the triangle under the stencil is colored
+        // (pixels right on the slanted edge are left out of this, since they're only half inside)
+        for y in 0..20 {
+            for x in 0..20 {
+                let colored = canvas.pixel(x, y) != Some(Color::transparent());
+                assert!(if x + y < 19 { colored } else { x + y == 19 || !colored });
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_index_misses_at_the_edges_of_isize() {
+        let canvas = PixelCanvas::new(8, 8, isize::MAX, isize::MAX);
+        // applying the offset overflows, or lands just before the page
+        assert_eq!(canvas.pixel_index(isize::MAX, 0), None);
+        assert_eq!(canvas.pixel_index(isize::MIN, isize::MIN), None);
+        // while the page itself is still there
+        assert_eq!(canvas.pixel_index(-isize::MAX, -isize::MAX), Some(0));
+        assert_eq!(canvas.pixel_index(7 - isize::MAX, 7 - isize::MAX), Some(63));
+    }
+
+    #[test]
+    fn drawing_near_the_edges_of_isize_just_misses() {
+        let far = isize::MAX as f32;
+        for (x_offset, y_offset) in [(0, 0), (isize::MAX, isize::MAX), (isize::MIN, isize::MIN)] {
+            let mut canvas = PixelCanvas::new(8, 8, x_offset, y_offset);
+            canvas.set_color(Color(255, 255, 255, 255));
+            canvas.set_pen_width(3);
+            for (x, y) in [(far, far), (-far, -far), (far, -far), (f32::MAX, f32::MIN), (f32::INFINITY, 4.0)] {
+                canvas.blot(x, y);
+                canvas.move_pen_to(x, y);
+                canvas.move_pen_to(4.0, 4.0);
+                canvas.draw_circle(x, y, 5.0, true);
+                canvas.draw_circle(x, y, 5.0, false);
+                canvas.draw_text(x, y, "hi", 2);
+                canvas.fill(x, y);
+                canvas.set_stencil(Some(&[(-far, -far), (far, -far), (x, y)]));
+                canvas.set_stencil(None);
+            }
+        }
+        let mut canvas = PixelCanvas::new(8, 8, 0, 0);
+        canvas.set_color(Color(255, 255, 255, 255));
+        canvas.blot(far, far);
+        canvas.draw_circle(-far, far, 5.0, true);
+        assert!(pixels(&canvas).iter().all(|&color| color == Color::transparent()));
+    }
+}
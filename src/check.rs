@@ -0,0 +1,202 @@
+use crate::instruction::Instruction;
+use crate::parsing::{self, ParseError};
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+// whether a problem stops a program from being accepted, or is just worth a look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error")
+        }
+    }
+}
+
+// something a program does that's probably a mistake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    UnusedLabel(String),
+    ReturnOutsideCall,
+    TargetOutOfRange(isize),    // the program counter that's jumped to
+    ColorOverwritten(usize)     // the (1-based) line the color is replaced on
+}
+
+// a problem found by check_program, on a particular line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize, // 1-based
+    pub kind: DiagnosticKind
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            DiagnosticKind::ReturnOutsideCall | DiagnosticKind::TargetOutOfRange(_) => Severity::Error,
+            DiagnosticKind::UnusedLabel(_) | DiagnosticKind::ColorOverwritten(_) => Severity::Warning
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            DiagnosticKind::UnusedLabel(label) => write!(f, "label '{}' is never used", label),
+            DiagnosticKind::ReturnOutsideCall => write!(f, "RTRN can be reached without a CALL or LOOP to return from"),
+            DiagnosticKind::TargetOutOfRange(target) if *target < 0 => write!(f, "jumps to before the start of the program"),
+            DiagnosticKind::TargetOutOfRange(target) => write!(f, "jumps to line {}, past the end of the program", target.saturating_add(1)),
+            DiagnosticKind::ColorOverwritten(line) => write!(f, "color is replaced on line {} before anything is drawn with it", line)
+        }
+    }
+}
+
+// where an instruction can send the program counter instead of the next line, if anywhere
+// relative jumps are measured from pc, and aren't clamped to the start of the program here
+fn jump_target(pc: usize, inst: &Instruction) -> Option<isize> {
+    match inst {
+        Instruction::Goto(target) | Instruction::Call(target) | Instruction::Repeat(target, _) => Some(*target as isize),
+        Instruction::Jump(offset) | Instruction::JumpIfZero(_, offset) | Instruction::JumpIfNotZero(_, offset) =>
+            Some((pc as isize).saturating_add(*offset).saturating_add(1)),
+        _ => None
+    }
+}
+
+// labels used as operands anywhere in the program
+// labels have already been turned into numbers by parsing, so this looks for them in the source instead
+fn used_labels<'a>(lines: &[(usize, &'a str)]) -> HashSet<&'a str> {
+    lines.iter()
+        .filter_map(|(_, line)| line.split(['@', ';']).next())
+        .flat_map(|code| code.split_whitespace().skip(1))
+        .collect()
+}
+
+// follow every path the program can take from the start, finding returns that aren't inside a call
+// each line is visited at most twice: once inside a call, and once outside
+fn unmatched_returns(program: &[Instruction]) -> Vec<usize> {
+    let mut visited = HashSet::new();
+    let mut unmatched = vec![];
+    let mut stack = vec![(0, false)];
+    while let Some((pc, in_call)) = stack.pop() {
+        if pc >= program.len() || !visited.insert((pc, in_call)) {
+            continue;
+        }
+        let clamp = |target: isize| target.max(0) as usize;
+        match &program[pc] {
+            Instruction::Halt => (),
+            Instruction::Goto(target) => stack.push((*target, in_call)),
+            Instruction::Jump(offset) => stack.push((clamp((pc as isize).saturating_add(*offset).saturating_add(1)), in_call)),
+            Instruction::JumpIfZero(_, offset) | Instruction::JumpIfNotZero(_, offset) => {
+                stack.push((pc + 1, in_call));
+                stack.push((clamp((pc as isize).saturating_add(*offset).saturating_add(1)), in_call));
+            }
+            // looping zero times skips the body
+            Instruction::Repeat(_, 0) => stack.push((pc + 1, in_call)),
+            // the body runs inside the call, and then the program carries on after it
+            Instruction::Call(target) | Instruction::Repeat(target, _) => {
+                stack.push((*target, true));
+                stack.push((pc + 1, in_call));
+            }
+            Instruction::RepeatBlock(end, 0) => stack.push((end + 1, in_call)),
+            Instruction::RepeatBlock(end, _) => {
+                stack.push((pc + 1, true));
+                stack.push((end + 1, in_call));
+            }
+            // the caller carries on from after its call, which is already being followed
+            Instruction::Return | Instruction::EndRepeat if in_call => (),
+            // an END outside its block does nothing, but isn't something to warn about like RTRN
+            Instruction::EndRepeat => stack.push((pc + 1, in_call)),
+            // with nothing to return to, RTRN does nothing
+            Instruction::Return => {
+                unmatched.push(pc);
+                stack.push((pc + 1, in_call));
+            }
+            _ => stack.push((pc + 1, in_call))
+        }
+    }
+    unmatched
+}
+
+// instructions that can't draw anything, or change where the program goes
+fn is_quiet(inst: &Instruction) -> bool {
+    matches!(inst,
+        Instruction::Noop | Instruction::Comment(_) | Instruction::Parametric(_, _) | Instruction::Layer(_) | Instruction::Home |
+        Instruction::Face(_) | Instruction::FaceToward(_, _) | Instruction::Turn(_) | Instruction::AngleMode(_) | Instruction::Scale(_) | Instruction::Mirror | Instruction::BlendMode(_) | Instruction::DefinePalette(_, _) | Instruction::AdjustColor(_, _, _, _) |
+        Instruction::PenUp | Instruction::PenDown | Instruction::PenWidth(_) |
+        Instruction::DynamicWidth(_, _) | Instruction::Hatch(_, _) | Instruction::Push |
+        Instruction::Load(_, _) | Instruction::PushArg(_) | Instruction::Arg(_, _) | Instruction::Add(_, _, _) | Instruction::Sub(_, _, _) | Instruction::Mul(_, _, _) | Instruction::Random(_, _, _)
+    )
+}
+
+// colors that are replaced by another color before anything is drawn
+// returns the line of each wasted color, along with the line that replaces it
+fn overwritten_colors(program: &[Instruction], targets: &HashSet<usize>) -> Vec<(usize, usize)> {
+    let is_color = |inst: &Instruction| matches!(inst, Instruction::SetColor(_) | Instruction::Lerp(_, _, _, _));
+    let mut overwritten = vec![];
+    for pc in (0..program.len()).filter(|&pc| is_color(&program[pc])) {
+        // a line that's jumped to can be reached with some other color, so that's where the search stops
+        let next = program.iter().enumerate().skip(pc + 1)
+            .find(|(next, inst)| targets.contains(next) || !is_quiet(inst));
+        if let Some((next, inst)) = next {
+            if is_color(inst) && !targets.contains(&next) {
+                overwritten.push((pc, next));
+            }
+        }
+    }
+    overwritten
+}
+
+/// Look for likely mistakes in a program, without running it. Like `parse_program`, this returns every
+/// parse error instead if the program can't be parsed.
+///
+/// ```
+/// use penplot::check::{self, DiagnosticKind};
+///
+/// let diagnostics = check::check_program(String::from("RGB 255 0 0\nRGB 0 0 255\nWALK 10\nRTRN")).unwrap();
+/// assert_eq!(diagnostics[0].kind, DiagnosticKind::ColorOverwritten(2));
+/// assert_eq!(diagnostics[1].kind, DiagnosticKind::ReturnOutsideCall);
+///
+/// // a jump too far to add up is still just past the end
+/// let diagnostics = check::check_program(format!("WALK 10\nJUMP {}", isize::MAX)).unwrap();
+/// assert_eq!(diagnostics[0].kind, DiagnosticKind::TargetOutOfRange(isize::MAX));
+/// ```
+pub fn check_program(text: String) -> Result<Vec<Diagnostic>, Vec<ParseError>> {
+    let program = parsing::parse_program(text.clone())?;
+    let symbol_table = parsing::parse_symbol_table(&text);
+    // the program parsed, so its comments are all closed
+    let text = parsing::strip_block_comments(&text).unwrap_or(text);
+    let lines = parsing::program_lines(&text);
+    let used = used_labels(&lines);
+    // blank lines aren't instructions, so program counters and line numbers can differ
+    let line = |pc: usize| lines[pc].0;
+    let mut diagnostics: Vec<Diagnostic> = symbol_table.iter()
+        .filter(|(label, _)| !used.contains(label.as_str()))
+        .map(|(label, &pc)| Diagnostic { line: line(pc), kind: DiagnosticKind::UnusedLabel(label.clone()) })
+        .collect();
+    // jumping to just past the end is fine, since that's how the program ends anyway
+    let mut targets = HashSet::new();
+    for (pc, inst) in program.iter().enumerate() {
+        match jump_target(pc, inst) {
+            Some(target) if target < 0 || target as usize > program.len() =>
+                diagnostics.push(Diagnostic { line: line(pc), kind: DiagnosticKind::TargetOutOfRange(target) }),
+            Some(target) => {
+                targets.insert(target as usize);
+            }
+            None => ()
+        }
+    }
+    for pc in unmatched_returns(&program) {
+        diagnostics.push(Diagnostic { line: line(pc), kind: DiagnosticKind::ReturnOutsideCall });
+    }
+    for (pc, next) in overwritten_colors(&program, &targets) {
+        diagnostics.push(Diagnostic { line: line(pc), kind: DiagnosticKind::ColorOverwritten(line(next)) });
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    Ok(diagnostics)
+}
@@ -1,7 +1,50 @@
-use fixed::{types::extra::U8, FixedU16};
 use std::convert::TryInto;
 use std::fmt::{self, Display, Formatter};
 
+// how a pen's color is combined with what's already been drawn underneath it
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Normal,     // ordinary alpha compositing (Color::overlay)
+    Multiply,   // darkens, like layering inks
+    Screen,     // lightens, like layering light
+    Additive    // adds channels together, saturating at 255
+}
+
+impl BlendMode {
+    // look up a mode by the name BLND uses for it (case-insensitive)
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(BlendMode::Normal),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "additive" | "add" => Some(BlendMode::Additive),
+            _ => None
+        }
+    }
+
+    // composite top onto bottom with this mode
+    pub fn apply(self, top: Color, bottom: Color) -> Color {
+        match self {
+            BlendMode::Normal => Color::overlay(top, bottom),
+            BlendMode::Multiply => Color::multiply(top, bottom),
+            BlendMode::Screen => Color::screen(top, bottom),
+            BlendMode::Additive => Color::additive(top, bottom)
+        }
+    }
+}
+
+impl Display for BlendMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BlendMode::Normal => write!(f, "normal"),
+            BlendMode::Multiply => write!(f, "multiply"),
+            BlendMode::Screen => write!(f, "screen"),
+            BlendMode::Additive => write!(f, "additive")
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct Color(pub u8, pub u8, pub u8, pub u8); // RGBA in [0, 255]
 
@@ -12,14 +55,158 @@ impl Display for Color {
     }
 }
 
-#[inline]
-fn fixed_to_byte(x: FixedU16<U8>) -> u8 {
-    if x > 255 {
-        255
-    } else {
-        x.to_bits() as u8
-    }
-}
+// named colors, for the COLR instruction (these are the CSS color keywords)
+const NAMED_COLORS: &[(&str, Color)] = &[
+    ("aliceblue", Color(240, 248, 255, 255)),
+    ("antiquewhite", Color(250, 235, 215, 255)),
+    ("aqua", Color(0, 255, 255, 255)),
+    ("aquamarine", Color(127, 255, 212, 255)),
+    ("azure", Color(240, 255, 255, 255)),
+    ("beige", Color(245, 245, 220, 255)),
+    ("bisque", Color(255, 228, 196, 255)),
+    ("black", Color(0, 0, 0, 255)),
+    ("blanchedalmond", Color(255, 235, 205, 255)),
+    ("blue", Color(0, 0, 255, 255)),
+    ("blueviolet", Color(138, 43, 226, 255)),
+    ("brown", Color(165, 42, 42, 255)),
+    ("burlywood", Color(222, 184, 135, 255)),
+    ("cadetblue", Color(95, 158, 160, 255)),
+    ("chartreuse", Color(127, 255, 0, 255)),
+    ("chocolate", Color(210, 105, 30, 255)),
+    ("coral", Color(255, 127, 80, 255)),
+    ("cornflowerblue", Color(100, 149, 237, 255)),
+    ("cornsilk", Color(255, 248, 220, 255)),
+    ("crimson", Color(220, 20, 60, 255)),
+    ("cyan", Color(0, 255, 255, 255)),
+    ("darkblue", Color(0, 0, 139, 255)),
+    ("darkcyan", Color(0, 139, 139, 255)),
+    ("darkgoldenrod", Color(184, 134, 11, 255)),
+    ("darkgray", Color(169, 169, 169, 255)),
+    ("darkgreen", Color(0, 100, 0, 255)),
+    ("darkgrey", Color(169, 169, 169, 255)),
+    ("darkkhaki", Color(189, 183, 107, 255)),
+    ("darkmagenta", Color(139, 0, 139, 255)),
+    ("darkolivegreen", Color(85, 107, 47, 255)),
+    ("darkorange", Color(255, 140, 0, 255)),
+    ("darkorchid", Color(153, 50, 204, 255)),
+    ("darkred", Color(139, 0, 0, 255)),
+    ("darksalmon", Color(233, 150, 122, 255)),
+    ("darkseagreen", Color(143, 188, 143, 255)),
+    ("darkslateblue", Color(72, 61, 139, 255)),
+    ("darkslategray", Color(47, 79, 79, 255)),
+    ("darkslategrey", Color(47, 79, 79, 255)),
+    ("darkturquoise", Color(0, 206, 209, 255)),
+    ("darkviolet", Color(148, 0, 211, 255)),
+    ("deeppink", Color(255, 20, 147, 255)),
+    ("deepskyblue", Color(0, 191, 255, 255)),
+    ("dimgray", Color(105, 105, 105, 255)),
+    ("dimgrey", Color(105, 105, 105, 255)),
+    ("dodgerblue", Color(30, 144, 255, 255)),
+    ("firebrick", Color(178, 34, 34, 255)),
+    ("floralwhite", Color(255, 250, 240, 255)),
+    ("forestgreen", Color(34, 139, 34, 255)),
+    ("fuchsia", Color(255, 0, 255, 255)),
+    ("gainsboro", Color(220, 220, 220, 255)),
+    ("ghostwhite", Color(248, 248, 255, 255)),
+    ("gold", Color(255, 215, 0, 255)),
+    ("goldenrod", Color(218, 165, 32, 255)),
+    ("gray", Color(128, 128, 128, 255)),
+    ("green", Color(0, 128, 0, 255)),
+    ("greenyellow", Color(173, 255, 47, 255)),
+    ("grey", Color(128, 128, 128, 255)),
+    ("honeydew", Color(240, 255, 240, 255)),
+    ("hotpink", Color(255, 105, 180, 255)),
+    ("indianred", Color(205, 92, 92, 255)),
+    ("indigo", Color(75, 0, 130, 255)),
+    ("ivory", Color(255, 255, 240, 255)),
+    ("khaki", Color(240, 230, 140, 255)),
+    ("lavender", Color(230, 230, 250, 255)),
+    ("lavenderblush", Color(255, 240, 245, 255)),
+    ("lawngreen", Color(124, 252, 0, 255)),
+    ("lemonchiffon", Color(255, 250, 205, 255)),
+    ("lightblue", Color(173, 216, 230, 255)),
+    ("lightcoral", Color(240, 128, 128, 255)),
+    ("lightcyan", Color(224, 255, 255, 255)),
+    ("lightgoldenrodyellow", Color(250, 250, 210, 255)),
+    ("lightgray", Color(211, 211, 211, 255)),
+    ("lightgreen", Color(144, 238, 144, 255)),
+    ("lightgrey", Color(211, 211, 211, 255)),
+    ("lightpink", Color(255, 182, 193, 255)),
+    ("lightsalmon", Color(255, 160, 122, 255)),
+    ("lightseagreen", Color(32, 178, 170, 255)),
+    ("lightskyblue", Color(135, 206, 250, 255)),
+    ("lightslategray", Color(119, 136, 153, 255)),
+    ("lightslategrey", Color(119, 136, 153, 255)),
+    ("lightsteelblue", Color(176, 196, 222, 255)),
+    ("lightyellow", Color(255, 255, 224, 255)),
+    ("lime", Color(0, 255, 0, 255)),
+    ("limegreen", Color(50, 205, 50, 255)),
+    ("linen", Color(250, 240, 230, 255)),
+    ("magenta", Color(255, 0, 255, 255)),
+    ("maroon", Color(128, 0, 0, 255)),
+    ("mediumaquamarine", Color(102, 205, 170, 255)),
+    ("mediumblue", Color(0, 0, 205, 255)),
+    ("mediumorchid", Color(186, 85, 211, 255)),
+    ("mediumpurple", Color(147, 112, 219, 255)),
+    ("mediumseagreen", Color(60, 179, 113, 255)),
+    ("mediumslateblue", Color(123, 104, 238, 255)),
+    ("mediumspringgreen", Color(0, 250, 154, 255)),
+    ("mediumturquoise", Color(72, 209, 204, 255)),
+    ("mediumvioletred", Color(199, 21, 133, 255)),
+    ("midnightblue", Color(25, 25, 112, 255)),
+    ("mintcream", Color(245, 255, 250, 255)),
+    ("mistyrose", Color(255, 228, 225, 255)),
+    ("moccasin", Color(255, 228, 181, 255)),
+    ("navajowhite", Color(255, 222, 173, 255)),
+    ("navy", Color(0, 0, 128, 255)),
+    ("oldlace", Color(253, 245, 230, 255)),
+    ("olive", Color(128, 128, 0, 255)),
+    ("olivedrab", Color(107, 142, 35, 255)),
+    ("orange", Color(255, 165, 0, 255)),
+    ("orangered", Color(255, 69, 0, 255)),
+    ("orchid", Color(218, 112, 214, 255)),
+    ("palegoldenrod", Color(238, 232, 170, 255)),
+    ("palegreen", Color(152, 251, 152, 255)),
+    ("paleturquoise", Color(175, 238, 238, 255)),
+    ("palevioletred", Color(219, 112, 147, 255)),
+    ("papayawhip", Color(255, 239, 213, 255)),
+    ("peachpuff", Color(255, 218, 185, 255)),
+    ("peru", Color(205, 133, 63, 255)),
+    ("pink", Color(255, 192, 203, 255)),
+    ("plum", Color(221, 160, 221, 255)),
+    ("powderblue", Color(176, 224, 230, 255)),
+    ("purple", Color(128, 0, 128, 255)),
+    ("rebeccapurple", Color(102, 51, 153, 255)),
+    ("red", Color(255, 0, 0, 255)),
+    ("rosybrown", Color(188, 143, 143, 255)),
+    ("royalblue", Color(65, 105, 225, 255)),
+    ("saddlebrown", Color(139, 69, 19, 255)),
+    ("salmon", Color(250, 128, 114, 255)),
+    ("sandybrown", Color(244, 164, 96, 255)),
+    ("seagreen", Color(46, 139, 87, 255)),
+    ("seashell", Color(255, 245, 238, 255)),
+    ("sienna", Color(160, 82, 45, 255)),
+    ("silver", Color(192, 192, 192, 255)),
+    ("skyblue", Color(135, 206, 235, 255)),
+    ("slateblue", Color(106, 90, 205, 255)),
+    ("slategray", Color(112, 128, 144, 255)),
+    ("slategrey", Color(112, 128, 144, 255)),
+    ("snow", Color(255, 250, 250, 255)),
+    ("springgreen", Color(0, 255, 127, 255)),
+    ("steelblue", Color(70, 130, 180, 255)),
+    ("tan", Color(210, 180, 140, 255)),
+    ("teal", Color(0, 128, 128, 255)),
+    ("thistle", Color(216, 191, 216, 255)),
+    ("tomato", Color(255, 99, 71, 255)),
+    ("turquoise", Color(64, 224, 208, 255)),
+    ("violet", Color(238, 130, 238, 255)),
+    ("wheat", Color(245, 222, 179, 255)),
+    ("white", Color(255, 255, 255, 255)),
+    ("whitesmoke", Color(245, 245, 245, 255)),
+    ("yellow", Color(255, 255, 0, 255)),
+    ("yellowgreen", Color(154, 205, 50, 255)),
+    ("transparent", Color(0, 0, 0, 0)),
+];
 
 impl Color {
     pub fn transparent() -> Color {
@@ -30,65 +217,286 @@ impl Color {
         Some(Color(r.try_into().ok()?, g.try_into().ok()?, b.try_into().ok()?, a.try_into().ok()?))
     }
 
-    #[inline]
-    fn from_fixed(r: FixedU16<U8>, g: FixedU16<U8>, b: FixedU16<U8>, a: FixedU16<U8>) -> Color {
-        Color(fixed_to_byte(r), fixed_to_byte(g), fixed_to_byte(b), fixed_to_byte(a))
+    // parse a hex color like #rrggbb or #rrggbbaa, or the #rgb / #rgba shorthand (the # is optional)
+    pub fn from_hex(text: &str) -> Option<Color> {
+        let digits = text.strip_prefix('#').unwrap_or(text);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |index: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[index * width..(index + 1) * width], 16).ok()?;
+            // shorthand digits are doubled, so f is ff
+            Some(if width == 1 { value * 17 } else { value })
+        };
+        match digits.len() {
+            3 => Some(Color(channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 255)),
+            4 => Some(Color(channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, channel(3, 1)?)),
+            6 => Some(Color(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255)),
+            8 => Some(Color(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, channel(3, 2)?)),
+            _ => None
+        }
+    }
+
+    // look up a named color (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Color> {
+        let name = name.to_ascii_lowercase();
+        NAMED_COLORS.iter().find(|(color_name, _)| *color_name == name).map(|(_, color)| *color)
+    }
+
+    // hue in degrees [0, 360), saturation and lightness in [0, 1]
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (r, g, b) = (self.red() as f32 / 255.0, self.green() as f32 / 255.0, self.blue() as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        if max == min {
+            // grays have no hue or saturation
+            return (0.0, 0.0, lightness);
+        }
+        let delta = max - min;
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (hue, saturation, lightness)
+    }
+
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: u8) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x)
+        };
+        let m = lightness - chroma / 2.0;
+        let to_byte = |channel: f32| ((channel + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color(to_byte(r), to_byte(g), to_byte(b), alpha)
+    }
+
+    // raise lightness by amount (in [0, 1]), keeping hue and saturation
+    pub fn lighten(&self, amount: f32) -> Color {
+        let (hue, saturation, lightness) = self.to_hsl();
+        Color::from_hsl(hue, saturation, (lightness + amount).clamp(0.0, 1.0), self.alpha())
+    }
+
+    // lower lightness by amount (in [0, 1]), keeping hue and saturation
+    pub fn darken(&self, amount: f32) -> Color {
+        self.lighten(-amount)
+    }
+
+    /// Add a (possibly negative) amount to each channel, clamping them to [0, 255] rather than wrapping around.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// let mut color = Color(0, 128, 0, 255);
+    /// for _ in 0..30 {
+    ///     color = color.adjust(10, -10, 0, 0);
+    /// }
+    /// assert_eq!(color, Color(255, 0, 0, 255));
+    /// ```
+    pub fn adjust(&self, dr: isize, dg: isize, db: isize, da: isize) -> Color {
+        let channel = |value: u8, delta: isize| (value as isize).saturating_add(delta).clamp(0, 255) as u8;
+        Color(channel(self.red(), dr), channel(self.green(), dg), channel(self.blue(), db), channel(self.alpha(), da))
+    }
+
+    /// Interpolate between two colors, at step out of steps (clamped to [0, steps]), as `LERP` does.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// let (from, to) = (Color(255, 0, 100, 255), Color(55, 200, 0, 55));
+    /// assert_eq!(Color::lerp(from, to, 0, 10), from);
+    /// assert_eq!(Color::lerp(from, to, 10, 10), to);
+    /// assert_eq!(Color::lerp(from, to, 5, 10), Color(155, 100, 50, 155));
+    /// // steps past the end stay on the last color
+    /// assert_eq!(Color::lerp(from, to, 15, 10), to);
+    /// ```
+    pub fn lerp(from: Color, to: Color, step: usize, steps: usize) -> Color {
+        if steps == 0 {
+            return from;
+        }
+        let (step, steps) = (step.min(steps) as u128, steps as u128);
+        // rounds to the nearest value, so the midpoint comes out the same in either direction
+        let channel = |a: u8, b: u8| ((a as u128 * (steps - step) + b as u128 * step + steps / 2) / steps) as u8;
+        Color(
+            channel(from.red(), to.red()),
+            channel(from.green(), to.green()),
+            channel(from.blue(), to.blue()),
+            channel(from.alpha(), to.alpha())
+        )
     }
 
+    // the color with each channel multiplied by its alpha, all in [0, 1]
+    pub fn premultiplied(&self) -> [f32; 4] {
+        let alpha = self.alpha() as f32 / 255.0;
+        let channel = |value: u8| value as f32 / 255.0 * alpha;
+        [channel(self.red()), channel(self.green()), channel(self.blue()), alpha]
+    }
+
+    // undo premultiplied, rounding each channel to the nearest value
+    pub fn from_premultiplied([r, g, b, a]: [f32; 4]) -> Color {
+        if a <= 0.0 {
+            return Color::transparent();
+        }
+        let channel = |value: f32| (value / a * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color(channel(r), channel(g), channel(b), (a * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+
+    // average a group of colors, weighting each by its alpha so transparent pixels don't darken the result
+    pub fn average(colors: &[Color]) -> Color {
+        let total_alpha: u64 = colors.iter().map(|c| c.alpha() as u64).sum();
+        if total_alpha == 0 {
+            return Color::transparent();
+        }
+        let weighted = |channel: fn(&Color) -> u8| {
+            (colors.iter().map(|c| channel(c) as u64 * c.alpha() as u64).sum::<u64>() / total_alpha) as u8
+        };
+        Color(
+            weighted(Color::red),
+            weighted(Color::green),
+            weighted(Color::blue),
+            (total_alpha / colors.len() as u64) as u8
+        )
+    }
+
+    // average colors like average, but round each channel up if its fraction is past threshold (in [0, 1)) instead
+    // of always down, so a region of fractional values dithered with varying thresholds keeps their average
+    pub fn average_dithered(colors: &[Color], threshold: f32) -> Color {
+        let total_alpha: u64 = colors.iter().map(|c| c.alpha() as u64).sum();
+        if total_alpha == 0 {
+            return Color::transparent();
+        }
+        let quantize = |value: f64| (value + threshold as f64).floor().min(255.0) as u8;
+        let weighted = |channel: fn(&Color) -> u8| {
+            quantize(colors.iter().map(|c| channel(c) as u64 * c.alpha() as u64).sum::<u64>() as f64 / total_alpha as f64)
+        };
+        Color(
+            weighted(Color::red),
+            weighted(Color::green),
+            weighted(Color::blue),
+            quantize(total_alpha as f64 / colors.len() as f64)
+        )
+    }
+
+    /// Composite one color on top of another ("over"), rounding each channel to the nearest value.
+    /// The math is done in integers scaled by 255², so opaque colors come through exactly.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// // opaque over anything is just the top color
+    /// assert_eq!(Color::overlay(Color(255, 0, 0, 255), Color(0, 0, 255, 255)), Color(255, 0, 0, 255));
+    /// assert_eq!(Color::overlay(Color(255, 0, 0, 255), Color::transparent()), Color(255, 0, 0, 255));
+    /// // anything over transparent is unchanged
+    /// assert_eq!(Color::overlay(Color(12, 34, 56, 78), Color::transparent()), Color(12, 34, 56, 78));
+    /// // half transparent over opaque is an even mix, rounded
+    /// assert_eq!(Color::overlay(Color(255, 255, 255, 128), Color(0, 0, 0, 255)), Color(128, 128, 128, 255));
+    /// ```
     pub fn overlay(top: Color, bottom: Color) -> Color {
         if top.alpha() == 0 && bottom.alpha() == 0 {
             // avoid division by zero errors
-            Color::transparent()
-        } else {
-            let inv_alpha = FixedU16::<U8>::from_bits((255 - top.alpha()).into());
-            let new_alpha = top.alpha_fixed() + bottom.alpha_fixed() * inv_alpha;
-            let scale = FixedU16::<U8>::from_bits(255) / new_alpha;
-            Color::from_fixed(
-                (top.red_fixed() * top.alpha_fixed() + bottom.red_fixed() * bottom.alpha_fixed() * inv_alpha) * scale,
-                (top.green_fixed() * top.alpha_fixed() + bottom.green_fixed() * bottom.alpha_fixed() * inv_alpha) * scale,
-                (top.blue_fixed() * top.alpha_fixed() + bottom.blue_fixed() * bottom.alpha_fixed() * inv_alpha) * scale,
-                new_alpha,
-            )
+            return Color::transparent();
         }
+        let top_alpha = top.alpha() as u32;
+        // each color's share of the result, both out of 255²
+        let top_weight = top_alpha * 255;
+        let bottom_weight = bottom.alpha() as u32 * (255 - top_alpha);
+        let total_weight = top_weight + bottom_weight;
+        let channel = |top: u8, bottom: u8| {
+            ((top as u32 * top_weight + bottom as u32 * bottom_weight + total_weight / 2) / total_weight) as u8
+        };
+        Color(
+            channel(top.red(), bottom.red()),
+            channel(top.green(), bottom.green()),
+            channel(top.blue(), bottom.blue()),
+            ((total_weight + 127) / 255) as u8
+        )
     }
 
-    #[inline]
-    pub fn red(&self) -> u8 {
-        self.0
+    // mix each channel of top with what blend makes of it and bottom, by how opaque bottom is, and then overlay that
+    // so blending onto a transparent pixel just draws the top color, and onto an opaque one draws the blend
+    fn blend(top: Color, bottom: Color, blend: impl Fn(u32, u32) -> u32) -> Color {
+        let bottom_alpha = bottom.alpha() as u32;
+        let channel = |top: u8, bottom: u8| {
+            ((top as u32 * (255 - bottom_alpha) + blend(top as u32, bottom as u32) * bottom_alpha + 127) / 255) as u8
+        };
+        let mixed = Color(
+            channel(top.red(), bottom.red()),
+            channel(top.green(), bottom.green()),
+            channel(top.blue(), bottom.blue()),
+            top.alpha()
+        );
+        Color::overlay(mixed, bottom)
     }
 
-    #[inline]
-    pub fn green(&self) -> u8 {
-        self.1
+    /// Composite one color on top of another, multiplying their channels. This only ever darkens, and white
+    /// leaves the color underneath unchanged.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// assert_eq!(Color::multiply(Color(255, 128, 0, 255), Color(200, 200, 200, 255)), Color(200, 100, 0, 255));
+    /// assert_eq!(Color::multiply(Color(255, 255, 255, 255), Color(12, 34, 56, 255)), Color(12, 34, 56, 255));
+    /// ```
+    pub fn multiply(top: Color, bottom: Color) -> Color {
+        Color::blend(top, bottom, |top, bottom| (top * bottom + 127) / 255)
     }
 
-    #[inline]
-    pub fn blue(&self) -> u8 {
-        self.2
+    /// Composite one color on top of another, multiplying the inverse of their channels. This is the opposite of
+    /// multiply: it only ever lightens, and black leaves the color underneath unchanged.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// assert_eq!(Color::screen(Color(0, 128, 255, 255), Color(55, 55, 55, 255)), Color(55, 155, 255, 255));
+    /// assert_eq!(Color::screen(Color(0, 0, 0, 255), Color(12, 34, 56, 255)), Color(12, 34, 56, 255));
+    /// ```
+    pub fn screen(top: Color, bottom: Color) -> Color {
+        Color::blend(top, bottom, |top, bottom| 255 - ((255 - top) * (255 - bottom) + 127) / 255)
     }
 
-    #[inline]
-    pub fn alpha(&self) -> u8 {
-        self.3
+    /// Composite one color on top of another, adding their channels, so overlapping strokes build up towards white.
+    ///
+    /// ```
+    /// use penplot::color::Color;
+    ///
+    /// // each channel saturates at 255
+    /// assert_eq!(Color::additive(Color(200, 0, 0, 255), Color(100, 50, 0, 255)), Color(255, 50, 0, 255));
+    /// assert_eq!(Color::additive(Color(128, 128, 128, 255), Color(200, 200, 200, 255)), Color(255, 255, 255, 255));
+    /// // a half transparent color adds half as much
+    /// assert_eq!(Color::additive(Color(100, 100, 100, 128), Color(50, 50, 50, 255)), Color(100, 100, 100, 255));
+    /// ```
+    pub fn additive(top: Color, bottom: Color) -> Color {
+        Color::blend(top, bottom, |top, bottom| (top + bottom).min(255))
     }
 
     #[inline]
-    fn red_fixed(&self) -> FixedU16<U8> {
-        FixedU16::from_bits(self.red().into())
+    pub fn red(&self) -> u8 {
+        self.0
     }
 
     #[inline]
-    fn green_fixed(&self) -> FixedU16<U8> {
-        FixedU16::from_bits(self.green().into())
+    pub fn green(&self) -> u8 {
+        self.1
     }
 
     #[inline]
-    fn blue_fixed(&self) -> FixedU16<U8> {
-        FixedU16::from_bits(self.blue().into())
+    pub fn blue(&self) -> u8 {
+        self.2
     }
 
     #[inline]
-    fn alpha_fixed(&self) -> FixedU16<U8> {
-        FixedU16::from_bits(self.alpha().into())
+    pub fn alpha(&self) -> u8 {
+        self.3
     }
 }
\ No newline at end of file
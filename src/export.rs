@@ -0,0 +1,156 @@
+use crate::color::Color;
+use crate::instruction::{Coordinate, Instruction, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// an operand of an instruction, as it's written in JSON
+enum Operand {
+    Integer(i128),
+    Number(Coordinate),
+    Register(usize),
+    Time,
+    Text(String),
+    Color(Color)
+}
+
+impl From<Value<Coordinate>> for Operand {
+    fn from(value: Value<Coordinate>) -> Self {
+        match value {
+            Value::Literal(number) => Operand::Number(number),
+            Value::Register(register) => Operand::Register(register),
+            Value::Time => Operand::Time
+        }
+    }
+}
+
+// strings are the only thing that need escaping, since everything else is a number
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl Operand {
+    fn to_json(&self) -> String {
+        match self {
+            Operand::Integer(n) => n.to_string(),
+            Operand::Number(n) => n.to_string(),
+            Operand::Register(register) => format!("{{\"register\": {}}}", register),
+            Operand::Time => String::from("{\"register\": \"T\"}"),
+            Operand::Text(text) => json_string(text),
+            Operand::Color(color) => json_string(&color.to_string())
+        }
+    }
+}
+
+// the name an instruction is tagged with, and its operands
+// names are the mnemonics programs are written with, so they stay the same however the enum changes
+fn operands(inst: &Instruction) -> (&'static str, Vec<Operand>) {
+    use Operand::{Color as C, Integer as I, Register as R, Text as T};
+    let int = |n: usize| I(n as i128);
+    match inst {
+        Instruction::Noop => ("NOOP", vec![]),
+        Instruction::Move(x, y) => ("MOVE", vec![(*x).into(), (*y).into()]),
+        Instruction::MoveRel(dx, dy) => ("SHFT", vec![(*dx).into(), (*dy).into()]),
+        Instruction::MoveForward(n) => ("WALK", vec![(*n).into()]),
+        Instruction::Face(theta) => ("FACE", vec![(*theta).into()]),
+        Instruction::FaceToward(x, y) => ("FACP", vec![(*x).into(), (*y).into()]),
+        Instruction::Turn(dt) => ("TURN", vec![(*dt).into()]),
+        Instruction::Arc(radius, dt) => ("ARC", vec![(*radius).into(), (*dt).into()]),
+        Instruction::AngleMode(unit) => ("ANGLEMODE", vec![T(unit.to_string())]),
+        Instruction::Scale(factor) => ("SCAL", vec![(*factor).into()]),
+        Instruction::Mirror => ("MIRR", vec![]),
+        Instruction::Home => ("HOME", vec![]),
+        Instruction::LineTo(x, y) => ("LINE", vec![(*x).into(), (*y).into()]),
+        Instruction::WalkDots(dist, spacing) => ("WLKD", vec![(*dist).into(), (*spacing).into()]),
+        Instruction::Circle(radius) => ("CIRC", vec![(*radius).into()]),
+        Instruction::Disc(radius) => ("DISC", vec![(*radius).into()]),
+        Instruction::Polygon(sides, radius) => ("POLY", vec![int(*sides), (*radius).into()]),
+        Instruction::SetColor(color) => ("RGBA", vec![C(*color)]),
+        Instruction::Lerp(from, to, steps, step) => ("LERP", vec![C(*from), C(*to), int(*steps), int(*step)]),
+        Instruction::AdjustColor(dr, dg, db, da) => ("ADJC", vec![I(*dr as i128), I(*dg as i128), I(*db as i128), I(*da as i128)]),
+        Instruction::BlendMode(mode) => ("BLND", vec![T(mode.to_string())]),
+        Instruction::DefinePalette(index, color) => ("PALD", vec![int(*index), C(*color)]),
+        Instruction::UsePalette(Value::Literal(index)) => ("PALU", vec![int(*index)]),
+        Instruction::UsePalette(Value::Register(register)) => ("PALU", vec![R(*register)]),
+        Instruction::UsePalette(Value::Time) => ("PALU", vec![Operand::Time]),
+        Instruction::Blot => ("BLOT", vec![]),
+        Instruction::PenUp => ("PENU", vec![]),
+        Instruction::PenDown => ("PEND", vec![]),
+        Instruction::PenWidth(width) => ("PENW", vec![int(*width)]),
+        Instruction::DynamicWidth(min, max) => ("DYNW", vec![int(*min), int(*max)]),
+        Instruction::BeginFill => ("BGNF", vec![]),
+        Instruction::EndFill => ("ENDF", vec![]),
+        Instruction::Hatch(angle, spacing) => ("HTCH", vec![I(*angle as i128), int(*spacing)]),
+        Instruction::BeginStencil => ("STNC", vec![]),
+        Instruction::EndStencil => ("ENDS", vec![]),
+        Instruction::ClearStencil => ("CLRS", vec![]),
+        Instruction::Fill => ("FILL", vec![]),
+        Instruction::Text(text) => ("TEXT", vec![T(text.clone())]),
+        Instruction::Push => ("PUSH", vec![]),
+        Instruction::Pop => ("POP", vec![]),
+        Instruction::Comment(text) => ("COMMENT", vec![T(text.clone())]),
+        Instruction::Parametric(name, parameter) => ("PARAMETRIC", vec![T(name.clone()), T(parameter.to_string())]),
+        Instruction::Layer(name) => ("LAYER", vec![T(name.clone())]),
+        Instruction::Goto(target) => ("GOTO", vec![int(*target)]),
+        Instruction::Jump(offset) => ("JUMP", vec![I(*offset as i128)]),
+        Instruction::JumpIfZero(r, offset) => ("JEZ", vec![R(*r), I(*offset as i128)]),
+        Instruction::JumpIfNotZero(r, offset) => ("JNZ", vec![R(*r), I(*offset as i128)]),
+        Instruction::Call(target) => ("CALL", vec![int(*target)]),
+        Instruction::Return => ("RTRN", vec![]),
+        Instruction::Repeat(target, times) => ("LOOP", vec![int(*target), int(*times)]),
+        Instruction::RepeatBlock(end, times) => ("REP", vec![int(*end), int(*times)]),
+        Instruction::EndRepeat => ("END", vec![]),
+        Instruction::Halt => ("HALT", vec![]),
+        Instruction::Load(r, n) => ("LOAD", vec![R(*r), I(*n as i128)]),
+        Instruction::PushArg(n) => ("PARG", vec![I(*n as i128)]),
+        Instruction::Arg(r, i) => ("ARG", vec![R(*r), int(*i)]),
+        Instruction::Add(r, a, b) => ("ADD", vec![R(*r), R(*a), R(*b)]),
+        Instruction::Sub(r, a, b) => ("SUB", vec![R(*r), R(*a), R(*b)]),
+        Instruction::Mul(r, a, b) => ("MUL", vec![R(*r), R(*a), R(*b)]),
+        Instruction::Random(r, lo, hi) => ("RAND", vec![R(*r), I(*lo as i128), I(*hi as i128)])
+    }
+}
+
+/// Write a parsed program as JSON, for tools that want to work with programs without parsing them. Each
+/// instruction is tagged with its mnemonic, and jump targets are program counters; `labels` maps each label to
+/// the program counter of the line it's on. Registers are written as `{"register": n}`, and colors as `"#RRGGBBAA"`.
+///
+/// ```
+/// use penplot::{export, parsing};
+///
+/// let text = "RGB 255 0 0\nWALK R2 @ step\nGOTO step";
+/// let program = parsing::parse_program(String::from(text)).unwrap();
+/// let json = export::program_json(&program, &parsing::parse_symbol_table(text));
+/// assert!(json.contains("\"labels\": {\"step\": 1}"));
+/// assert!(json.contains("{\"op\": \"RGBA\", \"args\": [\"#FF0000FF\"]}"));
+/// assert!(json.contains("{\"op\": \"WALK\", \"args\": [{\"register\": 2}]}"));
+/// ```
+pub fn program_json(program: &[Instruction], symbol_table: &HashMap<String, usize>) -> String {
+    // labels are sorted by where they are, so the output doesn't change from run to run
+    let mut labels: Vec<(&String, &usize)> = symbol_table.iter().collect();
+    labels.sort_by_key(|&(label, pc)| (pc, label));
+    let labels: Vec<String> = labels.into_iter().map(|(label, pc)| format!("{}: {}", json_string(label), pc)).collect();
+    let mut json = String::from("{\n");
+    writeln!(json, "  \"labels\": {{{}}},", labels.join(", ")).unwrap();
+    json.push_str("  \"instructions\": [\n");
+    for (index, inst) in program.iter().enumerate() {
+        let (name, args) = operands(inst);
+        let args: Vec<String> = args.iter().map(Operand::to_json).collect();
+        let separator = if index + 1 < program.len() { "," } else { "" };
+        writeln!(json, "    {{\"op\": \"{}\", \"args\": [{}]}}{}", name, args.join(", "), separator).unwrap();
+    }
+    json.push_str("  ]\n}\n");
+    json
+}
@@ -1,23 +1,221 @@
-use crate::color::Color;
+use crate::color::{BlendMode, Color};
+use fixed::types::I48F16;
 use std::fmt::{self, Display, Formatter};
 
+// coordinates, distances and angles can have a fractional part
+// they're fixed point rather than floats so instructions can still be hashed for L systems
+pub type Coordinate = I48F16;
+
+// number of registers (R0 through R15)
+pub const REGISTER_COUNT: usize = 16;
+// number of colors a palette can hold
+pub const PALETTE_SIZE: usize = 16;
+
+// what the angles given to FACE, TURN and ARC are measured in
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum AngleUnit {
+    Degrees,
+    Radians,
+    Gradians
+}
+
+impl AngleUnit {
+    pub fn to_degrees(self, angle: f32) -> f32 {
+        match self {
+            AngleUnit::Degrees => angle,
+            AngleUnit::Radians => angle.to_degrees(),
+            AngleUnit::Gradians => angle * 0.9
+        }
+    }
+
+    // look up a unit by the name ANGLEMODE uses for it (case-insensitive)
+    pub fn from_name(name: &str) -> Option<AngleUnit> {
+        match name.to_ascii_uppercase().as_str() {
+            "DEG" => Some(AngleUnit::Degrees),
+            "RAD" => Some(AngleUnit::Radians),
+            "GRAD" => Some(AngleUnit::Gradians),
+            _ => None
+        }
+    }
+}
+
+impl Display for AngleUnit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AngleUnit::Degrees => write!(f, "DEG"),
+            AngleUnit::Radians => write!(f, "RAD"),
+            AngleUnit::Gradians => write!(f, "GRAD")
+        }
+    }
+}
+
+// an operand that's either written out or read from a register when the instruction runs
+// Time is the T operand, how far through an animation the current frame is (from 0 to 1), which only coordinates take
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Value<T> {
+    Literal(T),
+    Register(usize),
+    Time
+}
+
+impl<T: Display> Display for Value<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Literal(value) => write!(f, "{}", value),
+            Value::Register(register) => write!(f, "R{}", register),
+            Value::Time => write!(f, "T")
+        }
+    }
+}
+
+// arithmetic in the parameter of a parametric L system symbol
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div
+}
+
+impl Operator {
+    fn apply(self, a: Coordinate, b: Coordinate) -> Coordinate {
+        match self {
+            Operator::Add => a.saturating_add(b),
+            Operator::Sub => a.saturating_sub(b),
+            Operator::Mul => a.saturating_mul(b),
+            // dividing by zero saturates, like overflowing does
+            Operator::Div => a.checked_div(b).unwrap_or(if (a < 0) != (b < 0) { Coordinate::MIN } else { Coordinate::MAX })
+        }
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Sub => write!(f, "-"),
+            Operator::Mul => write!(f, "*"),
+            Operator::Div => write!(f, "/")
+        }
+    }
+}
+
+// the parameter of a parametric L system symbol, like the x*1.5 in <F>(x*1.5)
+// there are no brackets, so * and / always bind tighter than + and -, and the tree can be written back out as is
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum Expr {
+    Literal(Coordinate),
+    Parameter(String),
+    Binary(Box<Expr>, Operator, Box<Expr>)
+}
+
+impl Expr {
+    // the value of the expression, with the parameter (if any) set to value
+    // returns None if it uses a parameter that hasn't been given a value
+    pub fn evaluate(&self, value: Option<Coordinate>) -> Option<Coordinate> {
+        match self {
+            Expr::Literal(literal) => Some(*literal),
+            Expr::Parameter(_) => value,
+            Expr::Binary(a, op, b) => Some(op.apply(a.evaluate(value)?, b.evaluate(value)?))
+        }
+    }
+
+    // whether every parameter the expression uses is called name
+    pub fn only_uses(&self, name: &str) -> bool {
+        match self {
+            Expr::Literal(_) => true,
+            Expr::Parameter(parameter) => parameter == name,
+            Expr::Binary(a, _, b) => a.only_uses(name) && b.only_uses(name)
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(literal) => write!(f, "{}", literal),
+            Expr::Parameter(name) => write!(f, "{}", name),
+            Expr::Binary(a, op, b) => write!(f, "{}{}{}", a, op, b)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Instruction {
     Noop,                   // do nothing
-    Move(isize, isize),         // move to X, Y
-    MoveRel(isize, isize),      // move by dX, dY
-    MoveForward(isize),       // move forward by N
-    Face(isize),              // set heading to T
-    Turn(isize),              // change heading by dT
+    Move(Value<Coordinate>, Value<Coordinate>),     // move to X, Y
+    MoveRel(Value<Coordinate>, Value<Coordinate>),  // move by dX, dY
+    MoveForward(Value<Coordinate>),         // move forward by N
+    WalkDots(Value<Coordinate>, Value<Coordinate>), // move forward by N, leaving a dot every S instead of a line
+    Home,                   // go back to the origin without drawing, facing east
+    LineTo(Value<Coordinate>, Value<Coordinate>),   // move to X, Y and face the way the turtle went
+    Face(Value<Coordinate>), // set heading to T
+    FaceToward(Value<Coordinate>, Value<Coordinate>), // face the point X, Y without moving
+    Turn(Value<Coordinate>), // change heading by dT
+    Arc(Value<Coordinate>, Value<Coordinate>), // move along an arc of radius R, turning by dT
+    AngleMode(AngleUnit),   // measure the angles of later instructions in a different unit
+    Scale(Value<Coordinate>), // multiply later relative distances by S
+    Mirror,                 // swap the direction of later turns
+    Circle(Value<Coordinate>), // draw a circle of radius R around the current position
+    Disc(Value<Coordinate>), // fill a circle of radius R around the current position
+    Polygon(usize, Value<Coordinate>), // draw a regular polygon with n corners, R from the current position
     SetColor(Color),        // set pen color to c
+    Lerp(Color, Color, usize, usize), // set pen color to step i of n between two colors
+    AdjustColor(isize, isize, isize, isize), // add dR, dG, dB, dA to the pen color, clamping each channel
+    BlendMode(BlendMode),   // combine later strokes with what's under them in a different way
+    DefinePalette(usize, Color), // put color c in palette slot i
+    UsePalette(Value<usize>), // set pen color to the color in palette slot i
     Blot,                   // set current pixel to pen color
+    PenUp,                  // stop drawing when moving
+    PenDown,                // start drawing when moving again
+    PenWidth(usize),        // set pen width to N pixels
+    DynamicWidth(usize, usize), // scale pen width between min and max by segment length
+    BeginFill,              // start tracing a polygon to fill
+    EndFill,                // fill the traced polygon
+    Hatch(isize, usize),    // fill polygons with lines at angle T, N apart
+    BeginStencil,           // start tracing a stencil polygon
+    EndStencil,             // only draw inside the traced stencil from now on
+    ClearStencil,           // remove the stencil
+    Fill,                   // flood fill the empty area around the current pixel
+    Text(String),           // write text with its top left corner at the current position
+    Push,                   // save the turtle's position and heading
+    Pop,                    // go back to the last saved position and heading
     Comment(String),        // makes L-systems easier to implement
+    Parametric(String, Expr), // L system symbol (like <F>) or instruction (like WALK) with a parameter
+    Layer(String),          // tag the following instructions as part of a layer
     Goto(usize),            // set pc to i
     Jump(isize),            // set pc to pc + i + 1
+    JumpIfZero(usize, isize),    // jump by i if register r is zero
+    JumpIfNotZero(usize, isize), // jump by i if register r isn't zero
     Call(usize),            // call subroutine at position i
     Return,                 // return from subroutine call
     Repeat(usize, usize),   // repeat subroutine at position i n times
+    RepeatBlock(usize, usize), // repeat the following lines up to the END at position e n times
+    EndRepeat,              // end of a REP block
     Halt,                   // halt
+    Load(usize, isize),     // set register r to n
+    PushArg(isize),         // pass n as the next argument to the next CALL or LOOP
+    Arg(usize, usize),      // set register r to argument i of the current call
+    Add(usize, usize, usize), // set register r to a + b
+    Sub(usize, usize, usize), // set register r to a - b
+    Mul(usize, usize, usize), // set register r to a * b
+    Random(usize, isize, isize), // set register r to a random number from lo to hi
+}
+
+impl Instruction {
+    // the instructions that can be written as parametric symbols in an L system, like WALK(x*2)
+    pub fn with_parameter(name: &str, value: Coordinate) -> Option<Instruction> {
+        let value = Value::Literal(value);
+        match name.to_ascii_uppercase().as_str() {
+            "WALK" => Some(Instruction::MoveForward(value)),
+            "FACE" => Some(Instruction::Face(value)),
+            "TURN" => Some(Instruction::Turn(value)),
+            "SCAL" => Some(Instruction::Scale(value)),
+            "CIRC" => Some(Instruction::Circle(value)),
+            "DISC" => Some(Instruction::Disc(value)),
+            _ => None
+        }
+    }
 }
 
 impl Display for Instruction {
@@ -27,17 +225,62 @@ impl Display for Instruction {
             Instruction::Move(x, y) => write!(f, "MOVE {} {}", x, y),
             Instruction::MoveRel(dx, dy) => write!(f, "SHFT {} {}", dx, dy),
             Instruction::MoveForward(n) => write!(f, "WALK {}", n),
+            Instruction::WalkDots(n, spacing) => write!(f, "WLKD {} {}", n, spacing),
+            Instruction::Home => write!(f, "HOME"),
+            Instruction::LineTo(x, y) => write!(f, "LINE {} {}", x, y),
             Instruction::Face(theta) => write!(f, "FACE {}", theta),
+            Instruction::FaceToward(x, y) => write!(f, "FACP {} {}", x, y),
             Instruction::Turn(dt) => write!(f, "TURN {}", dt),
+            Instruction::Arc(radius, dt) => write!(f, "ARC {} {}", radius, dt),
+            Instruction::AngleMode(unit) => write!(f, "ANGLEMODE {}", unit),
+            Instruction::Scale(factor) => write!(f, "SCAL {}", factor),
+            Instruction::Mirror => write!(f, "MIRR"),
+            Instruction::Circle(radius) => write!(f, "CIRC {}", radius),
+            Instruction::Disc(radius) => write!(f, "DISC {}", radius),
+            Instruction::Polygon(sides, radius) => write!(f, "POLY {} {}", sides, radius),
             Instruction::SetColor(color) => write!(f, "RGBA {} {} {} {}", color.0, color.1, color.2, color.3),
+            Instruction::BlendMode(mode) => write!(f, "BLND {}", mode),
+            Instruction::DefinePalette(index, color) => write!(f, "PALD {} {}", index, color),
+            Instruction::UsePalette(index) => write!(f, "PALU {}", index),
+            Instruction::Lerp(from, to, steps, step) => write!(f, "LERP {} {} {} {} {} {} {} {}",
+                from.red(), from.green(), from.blue(), to.red(), to.green(), to.blue(), steps, step),
             Instruction::Blot => write!(f, "BLOT"),
+            Instruction::PenUp => write!(f, "PENU"),
+            Instruction::PenDown => write!(f, "PEND"),
+            Instruction::PenWidth(width) => write!(f, "PENW {}", width),
+            Instruction::DynamicWidth(min, max) => write!(f, "DYNW {} {}", min, max),
+            Instruction::BeginFill => write!(f, "BGNF"),
+            Instruction::EndFill => write!(f, "ENDF"),
+            Instruction::Hatch(angle, spacing) => write!(f, "HTCH {} {}", angle, spacing),
+            Instruction::BeginStencil => write!(f, "STNC"),
+            Instruction::EndStencil => write!(f, "ENDS"),
+            Instruction::ClearStencil => write!(f, "CLRS"),
+            Instruction::Fill => write!(f, "FILL"),
+            Instruction::Text(text) => write!(f, "TEXT {}", text),
+            Instruction::Push => write!(f, "PUSH"),
+            Instruction::Pop => write!(f, "POP"),
             Instruction::Comment(s) => write!(f, "; {}", s),
+            // only instructions with an unknown parameter are still parametric when a program is written out
+            Instruction::Parametric(name, parameter) => write!(f, "; {}({})", name, parameter),
+            Instruction::Layer(name) => write!(f, "; @layer {}", name),
             Instruction::Goto(i) => write!(f, "GOTO {}", i),
             Instruction::Jump(i) => write!(f, "JUMP {}", i),
+            Instruction::JumpIfZero(r, i) => write!(f, "JEZ R{} {}", r, i),
+            Instruction::JumpIfNotZero(r, i) => write!(f, "JNZ R{} {}", r, i),
             Instruction::Call(i) => write!(f, "CALL {}", i),
             Instruction::Return => write!(f, "RTRN"),
             Instruction::Repeat(i, n) => write!(f, "LOOP {} {}", i, n),
+            Instruction::RepeatBlock(_, n) => write!(f, "REP {}", n),
+            Instruction::EndRepeat => write!(f, "END"),
             Instruction::Halt => write!(f, "HALT"),
+            Instruction::Load(r, n) => write!(f, "LOAD R{} {}", r, n),
+            Instruction::PushArg(n) => write!(f, "PARG {}", n),
+            Instruction::Arg(r, i) => write!(f, "ARG R{} {}", r, i),
+            Instruction::Add(r, a, b) => write!(f, "ADD R{} R{} R{}", r, a, b),
+            Instruction::Sub(r, a, b) => write!(f, "SUB R{} R{} R{}", r, a, b),
+            Instruction::Mul(r, a, b) => write!(f, "MUL R{} R{} R{}", r, a, b),
+            Instruction::Random(r, lo, hi) => write!(f, "RAND R{} {} {}", r, lo, hi),
+            Instruction::AdjustColor(dr, dg, db, da) => write!(f, "ADJC {} {} {} {}", dr, dg, db, da),
         }
     }
 }
@@ -1,17 +1,212 @@
-use crate::instruction::Instruction;
-use crate::util;
+use crate::instruction::{Coordinate, Expr, Instruction};
+use crate::util::{self, Rng};
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 
+// one of the choices for a random rule, with its relative weight
+pub type Production = (f32, Vec<Instruction>);
+
+// the instruction a rule replaces, along with the neighbors it has to have for the rule to apply (if any)
+// written as "left < symbol > right", where either side can be left out
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct RuleKey {
+    pub left: Option<Instruction>,
+    pub symbol: Instruction,
+    pub right: Option<Instruction>
+}
+
+impl RuleKey {
+    // a context-free rule, which applies wherever the symbol is
+    pub fn new(symbol: Instruction) -> Self {
+        RuleKey { left: None, symbol, right: None }
+    }
+
+    pub fn has_context(&self) -> bool {
+        self.left.is_some() || self.right.is_some()
+    }
+}
+
+impl Display for RuleKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(left) = &self.left {
+            write!(f, "{} < ", Symbol(left))?;
+        }
+        write!(f, "{}", Symbol(&self.symbol))?;
+        if let Some(right) = &self.right {
+            write!(f, " > {}", Symbol(right))?;
+        }
+        Ok(())
+    }
+}
+
+/// A rule for a parametric symbol, like `<F>(x) { <F>(x*1.5) }`, whose production can use the symbol's parameter.
+/// Parametric instructions like `WALK(x)` become ordinary instructions once the L system is done.
+///
+/// ```
+/// use penplot::instruction::{Coordinate, Instruction, Value};
+/// use penplot::parsing;
+///
+/// let (_, l_system) = parsing::parse_l_system("seed {\n WALK(3)\n}\n\nWALK(x) {\n WALK(x*2-1)\n}\n").unwrap();
+/// assert_eq!(l_system.run(2), vec![Instruction::MoveForward(Value::Literal(Coordinate::from_num(9)))]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParametricRule {
+    pub parameter: String,
+    pub production: Vec<Instruction>
+}
+
+impl ParametricRule {
+    // the production, with the parameter of each of its parametric instructions worked out
+    fn apply(&self, value: Coordinate) -> impl Iterator<Item = Instruction> + '_ {
+        self.production.iter().map(move |inst| match inst {
+            Instruction::Parametric(name, parameter) => Instruction::Parametric(
+                name.clone(),
+                Expr::Literal(parameter.evaluate(Some(value)).expect("the parameter has a value"))
+            ),
+            inst => inst.clone()
+        })
+    }
+}
+
+// the parametric rule for an instruction, if there is one, along with the value of its parameter
+fn parametric_match<'a>(rules: &'a HashMap<String, ParametricRule>, inst: &Instruction) -> Option<(&'a ParametricRule, Coordinate)> {
+    match inst {
+        Instruction::Parametric(name, parameter) => rules.get(name).zip(parameter.evaluate(None)),
+        _ => None
+    }
+}
+
+// turn a parametric instruction like WALK(3) into the instruction it stands for, leaving everything else as it is
+fn resolve(inst: Instruction) -> Instruction {
+    if let Instruction::Parametric(name, parameter) = &inst {
+        if let Some(resolved) = parameter.evaluate(None).and_then(|value| Instruction::with_parameter(name, value)) {
+            return resolved;
+        }
+    }
+    inst
+}
+
+/// A seed and the rules that rewrite it. Rules are looked up by what they match rather than tried in order, so
+/// however they're stored, each symbol is rewritten by the first of these that applies:
+///
+/// 1. the rule for its name, if it's a parametric symbol like `<F>(2)`
+/// 2. a rule for it with both neighbors, then one with just the left neighbor, then one with just the right
+/// 3. a rule for it with no context
+///
+/// `rules` and `random_rules` never share a key: when a grammar defines the same rule twice, the last definition
+/// replaces the first, whether it has one production or several.
+///
+/// ```
+/// use penplot::instruction::Instruction;
+/// use penplot::parsing;
+///
+/// let spec = "seed {\n<A>\n}\n\n<A> {\n<B>\n} | {\n<C>\n}\n\n<A> {\nHALT\n}\n";
+/// let (_, l_system) = parsing::parse_l_system(spec).unwrap();
+/// assert_eq!(l_system.run(1), vec![Instruction::Halt]);
+/// ```
 pub struct LSystem {
     pub seed: Vec<Instruction>,
-    pub rules: HashMap<Instruction, Vec<Instruction>>,
-    pub aliases: Option<HashMap<Instruction, Vec<Instruction>>>
+    pub rules: HashMap<RuleKey, Vec<Instruction>>,
+    // rules with several weighted productions, one of which is picked at random each time the rule is used
+    pub random_rules: HashMap<RuleKey, Vec<Production>>,
+    // rules for parametric symbols, by the symbol's name
+    pub parametric_rules: HashMap<String, ParametricRule>,
+    pub aliases: Option<HashMap<Instruction, Vec<Instruction>>>,
+    pub parametric_aliases: HashMap<String, ParametricRule>,
+    // seed for picking random productions, so a run can be repeated
+    pub random_seed: u64
+}
+
+// pick one of a set of weighted productions
+fn choose<'a>(productions: &'a [Production], rng: &mut Rng) -> &'a [Instruction] {
+    let total: f32 = productions.iter().map(|(weight, _)| weight).sum();
+    let mut target = rng.next_f32() * total;
+    for (weight, production) in productions {
+        if target < *weight {
+            return production;
+        }
+        target -= weight;
+    }
+    // rounding can leave a little bit past the last weight
+    &productions[productions.len() - 1].1
+}
+
+// what a rule replaces its symbol with
+#[derive(Clone, Copy)]
+enum Replacement<'a> {
+    Fixed(&'a [Instruction]),
+    Random(&'a [Production])
+}
+
+impl<'a> Replacement<'a> {
+    fn production(self, rng: &mut Rng) -> &'a [Instruction] {
+        match self {
+            Replacement::Fixed(rule) => rule,
+            Replacement::Random(productions) => choose(productions, rng)
+        }
+    }
 }
 
 impl LSystem {
-    // advance the L system by one step
-    fn advance(&self, input: Vec<Instruction>) -> Vec<Instruction> {
-        util::replace(input, &self.rules)
+    fn replacement(&self, key: &RuleKey) -> Option<Replacement<'_>> {
+        match self.rules.get(key) {
+            Some(rule) => Some(Replacement::Fixed(rule)),
+            None => self.random_rules.get(key).map(|productions| Replacement::Random(productions))
+        }
+    }
+
+    // the contextual rule for the instruction at index, if any match its neighbors (None at either end of the input)
+    // the most specific rule wins: both neighbors, then just the left one, then just the right one
+    fn contextual_replacement(&self, input: &[Instruction], index: usize) -> Option<Replacement<'_>> {
+        let left = index.checked_sub(1).map(|before| &input[before]);
+        let right = input.get(index + 1);
+        let contexts = [
+            (left.is_some() && right.is_some(), left, right),
+            (left.is_some(), left, None),
+            (right.is_some(), None, right)
+        ];
+        contexts.into_iter()
+            .filter(|(possible, _, _)| *possible)
+            .find_map(|(_, left, right)| self.replacement(&RuleKey { left: left.cloned(), symbol: input[index].clone(), right: right.cloned() }))
+    }
+
+    // advance the L system by one step, or return None if the result would be longer than max_len
+    // this gives up as soon as it's too long, so an exploding grammar can't use up all the memory first
+    fn advance(&self, input: &[Instruction], rng: &mut Rng, max_len: usize) -> Option<Vec<Instruction>> {
+        // context-free rules are looked up by symbol alone, so most instructions don't need a key built for them
+        let context_free: HashMap<&Instruction, Replacement> = self.rules.iter()
+            .map(|(key, rule)| (key, Replacement::Fixed(rule)))
+            .chain(self.random_rules.iter().map(|(key, productions)| (key, Replacement::Random(productions))))
+            .filter(|(key, _)| !key.has_context())
+            .map(|(key, replacement)| (&key.symbol, replacement))
+            .collect();
+        // neighbors only need looking at if some rule cares about them
+        let contextual = self.rules.keys().chain(self.random_rules.keys()).any(RuleKey::has_context);
+        // size the result up front (counting random rules as one instruction, since their length isn't known yet)
+        let expected_len: usize = input.iter().map(|item| match context_free.get(item) {
+            Some(Replacement::Fixed(rule)) => rule.len(),
+            _ => 1
+        }).sum();
+        let mut result = Vec::with_capacity(expected_len.min(max_len.saturating_add(1)));
+        for (index, item) in input.iter().enumerate() {
+            if let Some((rule, value)) = parametric_match(&self.parametric_rules, item) {
+                result.extend(rule.apply(value));
+                if result.len() > max_len {
+                    return None;
+                }
+                continue;
+            }
+            let replacement = contextual.then(|| self.contextual_replacement(input, index)).flatten()
+                .or_else(|| context_free.get(item).copied());
+            match replacement {
+                Some(replacement) => result.extend_from_slice(replacement.production(rng)),
+                None => result.push(item.clone())
+            }
+            if result.len() > max_len {
+                return None;
+            }
+        }
+        Some(result)
     }
 
     pub fn run(&self, iters: usize) -> Vec<Instruction> {
@@ -23,14 +218,151 @@ impl LSystem {
         //         println!("{} -> {:?}", key, value);
         //     }
         // }
+        self.run_bounded(iters, usize::MAX).0
+    }
+
+    // like run, but stopping early if the next generation would be longer than max_len instructions
+    // returns the last generation that fit, along with how many iterations it took to get there
+    pub fn run_bounded(&self, iters: usize, max_len: usize) -> (Vec<Instruction>, usize) {
+        let mut rng = Rng::new(self.random_seed);
+        let mut acc = self.seed.clone();
+        for completed in 0..iters {
+            match self.advance(&acc, &mut rng, max_len) {
+                Some(next) => acc = next,
+                None => return (self.apply_aliases(acc), completed)
+            }
+        }
+        (self.apply_aliases(acc), iters)
+    }
+
+    // like run_bounded, but keeping every generation along the way (starting with the seed)
+    pub fn run_collecting(&self, iters: usize, max_len: usize) -> Vec<Vec<Instruction>> {
+        let mut rng = Rng::new(self.random_seed);
+        let mut generations = vec![];
         let mut acc = self.seed.clone();
         for _ in 0..iters {
-            acc = self.advance(acc);
+            let next = self.advance(&acc, &mut rng, max_len);
+            generations.push(self.apply_aliases(acc));
+            acc = match next {
+                Some(next) => next,
+                None => return generations
+            };
         }
-        if let Some(aliases) = &self.aliases {
-            util::replace(acc, aliases)
+        generations.push(self.apply_aliases(acc));
+        generations
+    }
+
+    // parametric aliases are applied after the others, and then parametric instructions become ordinary ones
+    fn apply_aliases(&self, program: Vec<Instruction>) -> Vec<Instruction> {
+        let program = if let Some(aliases) = &self.aliases {
+            util::replace(program, aliases)
         } else {
-            acc
+            program
+        };
+        if !program.iter().any(|inst| matches!(inst, Instruction::Parametric(_, _))) {
+            return program;
+        }
+        let mut result = Vec::with_capacity(program.len());
+        for inst in program {
+            match parametric_match(&self.parametric_aliases, &inst) {
+                Some((alias, value)) => result.extend(alias.apply(value)),
+                None => result.push(inst)
+            }
         }
+        result.into_iter().map(resolve).collect()
+    }
+}
+// write a block of instructions, one per line, in the format parse_l_system reads
+fn write_block(f: &mut Formatter, instructions: &[Instruction]) -> fmt::Result {
+    writeln!(f, "{{")?;
+    for inst in instructions {
+        writeln!(f, "    {}", Symbol(inst))?;
+    }
+    write!(f, "}}")
+}
+
+// write a block of rules (given with how their keys are written), sorted so the output doesn't change from run to run
+fn write_rules<'a>(f: &mut Formatter, rules: impl Iterator<Item = (String, &'a Vec<Instruction>)>) -> fmt::Result {
+    let mut rules: Vec<_> = rules.collect();
+    rules.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (key, rule) in rules {
+        writeln!(f)?;
+        write!(f, "{} ", key)?;
+        write_block(f, rule)?;
+        writeln!(f)?;
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// parametric rules, with their keys written like <F>(x), for write_rules
+fn parametric_rules(rules: &HashMap<String, ParametricRule>) -> impl Iterator<Item = (String, &Vec<Instruction>)> {
+    rules.iter().map(|(name, rule)| (format!("{}({})", name, rule.parameter), &rule.production))
+}
+
+// write random rules like fixed ones, but with each weighted production separated by |
+fn write_random_rules(f: &mut Formatter, rules: &HashMap<RuleKey, Vec<Production>>) -> fmt::Result {
+    let mut rules: Vec<_> = rules.iter().map(|(key, productions)| (key.to_string(), productions)).collect();
+    rules.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (key, productions) in rules {
+        writeln!(f)?;
+        write!(f, "{} ", key)?;
+        for (index, (weight, production)) in productions.iter().enumerate() {
+            if index > 0 {
+                write!(f, " | ")?;
+            }
+            write_block(f, production)?;
+            write!(f, " {}", weight)?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+// single-character comments are written as <c>, since a ; comment would swallow the rest of its line
+struct Symbol<'a>(&'a Instruction);
+
+impl Display for Symbol<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Instruction::Comment(s) if s.chars().count() == 1 => write!(f, "<{}>", s),
+            Instruction::Parametric(name, parameter) => write!(f, "{}({})", name, parameter),
+            inst => write!(f, "{}", inst)
+        }
+    }
+}
+
+impl Display for LSystem {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "seed ")?;
+        write_block(f, &self.seed)?;
+        writeln!(f)?;
+        if let Some(aliases) = &self.aliases {
+            writeln!(f)?;
+            writeln!(f, "aliases {{")?;
+            write_rules(f, aliases.iter().map(|(inst, rule)| (Symbol(inst).to_string(), rule))
+                .chain(parametric_rules(&self.parametric_aliases)))?;
+            writeln!(f, "}}")?;
+        }
+        write_rules(f, self.rules.iter().map(|(key, rule)| (key.to_string(), rule)).chain(parametric_rules(&self.parametric_rules)))?;
+        write_random_rules(f, &self.random_rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::{Coordinate, Instruction, Value};
+    use crate::parsing;
+
+    // A grows a B each iteration, and the aliases turn them into a walk and turns
+    // if A were aliased between iterations it would stop growing after the first, leaving just one turn
+    // and if the WALK it became were then rewritten too, there would be more than one walk
+    const SPEC: &str = "seed {\n A\n}\n\naliases {\n A {\n WALK 1\n }\n B {\n TURN 90\n }\n}\n\nA {\n A\n B\n}\n\nWALK 1 {\n WALK 1\n WALK 1\n}\n";
+
+    #[test]
+    fn aliases_are_applied_once_after_the_last_iteration() {
+        let (_, l_system) = parsing::parse_l_system(SPEC).unwrap();
+        let walk = Instruction::MoveForward(Value::Literal(Coordinate::from_num(1)));
+        let turn = Instruction::Turn(Value::Literal(Coordinate::from_num(90)));
+        assert_eq!(l_system.run(3), vec![walk, turn.clone(), turn.clone(), turn]);
+    }
+}
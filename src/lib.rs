@@ -0,0 +1,29 @@
+//! Penplot is an assembly-like turtle graphics language. This crate has its parser, its virtual
+//! machine, and the canvases it draws on, so programs can be run from other Rust code.
+//!
+//! ```
+//! use penplot::canvas::PixelCanvas;
+//! use penplot::color::Color;
+//! use penplot::parsing;
+//! use penplot::program_state::ProgramState;
+//!
+//! let commands = parsing::parse_program(String::from("RGB 255 0 0\nMOVE 10 0")).unwrap();
+//! let mut program = ProgramState::new(PixelCanvas::new(11, 1, 0, 0));
+//! program.execute(&commands).unwrap();
+//! let canvas = program.into_canvas();
+//! assert_ne!(canvas.pixel(5, 0), Some(Color::transparent()));
+//! ```
+
+pub mod bytecode;
+pub mod canvas;
+pub mod check;
+pub mod color;
+pub mod export;
+pub mod instruction;
+pub mod l_system;
+pub mod parsing;
+pub mod program_state;
+pub mod reference;
+pub mod resume;
+pub mod turtle;
+mod util;
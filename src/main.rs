@@ -1,20 +1,106 @@
-mod canvas;
-mod color;
-mod instruction;
-mod l_system;
-mod parsing;
-mod program_state;
-mod util;
-
-use crate::canvas::{PixelCanvas, SizingCanvas};
-use crate::instruction::Instruction;
-use crate::program_state::ProgramState;
+use penplot::bytecode;
+use penplot::canvas::{self, DrawingCanvas, HpglCanvas, PathCanvas, PixelCanvas, PolylineCanvas, RasterFormat, RecordingCanvas, SaveableCanvas, ScaledCanvas, SizingCanvas, StatsCanvas, SvgCanvas};
+use penplot::check::{self, Severity};
+use penplot::color::Color;
+use penplot::export;
+use penplot::instruction::Instruction;
+use penplot::l_system::{LSystem, RuleKey};
+use penplot::parsing::{self, ParseError, SourceMap};
+use penplot::program_state::{ExecutionError, ProgramSnapshot, ProgramState};
+use penplot::reference::{InstructionInfo, INSTRUCTION_SET};
+use penplot::resume::ResumeState;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::Result as IoResult;
 use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use clap::{Args, Parser, Subcommand};
+use image::{Delay, Frame, RgbaImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+
+// how many steps a program can run before it's assumed to be stuck in a loop
+const DEFAULT_MAX_STEPS: usize = 10_000_000;
+// how many calls and loops can be in progress at once
+const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+// how many pixels `size` lets an image have before saying it's too big (a 10,000 by 10,000 image, or 400 MB)
+const DEFAULT_MAX_PIXELS: usize = 100_000_000;
+// how long an L system's output can grow before it's assumed to be exploding
+const DEFAULT_MAX_INSTRUCTIONS: usize = 5_000_000;
+// space under each thumbnail on a contact sheet for its filename
+const SHEET_LABEL_HEIGHT: usize = 10;
+// gray, so the labels stand out from drawings without competing with them
+const SHEET_LABEL_COLOR: Color = Color(128, 128, 128, 255);
+// how often --watch checks the input file for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+// how long the input file has to stay unchanged before --watch re-renders it
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+// output filename that means "write a png to stdout"
+const STDOUT_OUTPUT: &str = "-";
+// the usual resolution of a screen, for converting physical units to pixels
+const DEFAULT_DPI: f32 = 96.0;
+const MM_PER_INCH: f32 = 25.4;
+// hp-gl coordinates are in plotter units of 0.025mm
+const HPGL_UNITS_PER_INCH: f32 = MM_PER_INCH * 40.0;
+
+// how much gets reported on stderr, set once from --quiet and --verbose
+// errors are always reported, warnings unless --quiet, and what's going on only with --verbose
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { eprintln!("error: {}", format_args!($($arg)*)) }
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            eprintln!("warning: {}", format_args!($($arg)*));
+        }
+    }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Verbose {
+            eprintln!("info: {}", format_args!($($arg)*));
+        }
+    }
+}
+
+// progress that's worth seeing by default (like --watch's), on stdout so it doesn't look like a problem
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            println!($($arg)*);
+        }
+    }
+}
 
 fn save_program(code: &[Instruction], filename: &str) -> IoResult<()> {
+    if filename.ends_with(bytecode::EXTENSION) {
+        return fs::write(filename, bytecode::encode_program(code));
+    }
     let mut buffer = File::create(filename)?;
     for line in code {
         writeln!(buffer, "{}", line)?;
@@ -22,6 +108,143 @@ fn save_program(code: &[Instruction], filename: &str) -> IoResult<()> {
     Ok(())
 }
 
+// settings for how programs are run
+#[derive(Clone)]
+struct ProgramOptions {
+    debug_bounds: bool,
+    layers: Option<HashSet<String>>,
+    step_limit: Option<usize>,
+    max_call_depth: Option<usize>,
+    // how many times bigger pixel canvases are drawn before being shrunk back down (1 for no supersampling)
+    supersample: usize,
+    // whether pixel canvases draw thin lines anti-aliased
+    smooth_lines: bool,
+    // whether supersampled canvases are dithered as they're shrunk back down
+    dither: bool,
+    // pixels per unit of the program's coordinates, for programs measured in physical units
+    unit_scale: f32,
+    // empty pixels around each side of a canvas that's sized to fit the drawing
+    margin: usize,
+    // seed for RAND
+    seed: u64,
+    trace: bool,
+    // where each instruction of the program is in its source, for errors
+    source_lines: SourceLines
+}
+
+impl Default for ProgramOptions {
+    fn default() -> Self {
+        ProgramOptions {
+            debug_bounds: false,
+            layers: None,
+            step_limit: Some(DEFAULT_MAX_STEPS),
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            supersample: 1,
+            smooth_lines: false,
+            dither: false,
+            unit_scale: 1.0,
+            margin: 0,
+            seed: 0,
+            trace: false,
+            source_lines: None
+        }
+    }
+}
+
+impl ProgramOptions {
+    fn program<T: DrawingCanvas>(&self, canvas: T) -> ProgramState<T> {
+        ProgramState::new(canvas)
+            .with_debug_bounds(self.debug_bounds)
+            .with_layers(self.layers.clone())
+            .with_step_limit(self.step_limit)
+            .with_max_call_depth(self.max_call_depth)
+            .with_seed(self.seed)
+            .with_trace(self.trace)
+            .with_source_lines(self.source_lines.clone())
+    }
+}
+
+// run a program, warning if it hit the step limit (since what it drew up to then is still worth keeping)
+// anything else that stops it is a mistake in the program, so that's an error
+fn execute<T: DrawingCanvas>(program: &mut ProgramState<T>, commands: &[Instruction]) -> Result<(), String> {
+    let result = program.execute(commands);
+    info!("ran {} instructions", program.steps());
+    match result {
+        Err(e @ ExecutionError::StepLimitExceeded { .. }) => warning!("{}", e),
+        Err(e) => return Err(e.to_string()),
+        Ok(()) => ()
+    }
+    Ok(())
+}
+
+// work out how big a canvas a program's drawing needs, as (width, height, x_offset, y_offset)
+// like execute, hitting the step limit is only a warning, and what was drawn up to then is what gets measured
+fn measure(program: &mut ProgramState<SizingCanvas>, commands: &[Instruction]) -> Result<(usize, usize, isize, isize), String> {
+    let result = program.measure(commands);
+    info!("ran {} instructions", program.steps());
+    match result {
+        Err(e @ ExecutionError::StepLimitExceeded { .. }) => {
+            warning!("{}", e);
+            let canvas = program.canvas();
+            let ((width, height), (x_offset, y_offset)) = (canvas.dimensions(), canvas.offsets());
+            Ok((width, height, x_offset, y_offset))
+        }
+        result => result.map_err(|e| e.to_string())
+    }
+}
+
+// run a program on a canvas, sized to fit its drawing unless a size is given
+// auto-sizing records the drawing and replays it onto the sized canvas, so the program only runs once
+// supersampled renders draw everything bigger onto a bigger canvas, then shrink it back to the requested size
+fn render(commands: &[Instruction], size: Option<(usize, usize)>, options: &ProgramOptions) -> Result<(PixelCanvas, ProgramSnapshot), String> {
+    let factor = options.supersample;
+    let shrink = |canvas: ScaledCanvas<PixelCanvas>| {
+        let canvas = canvas.into_inner();
+        if factor > 1 { canvas.downsample(factor) } else { canvas }
+    };
+    if let Some((width, height)) = size {
+        let canvas = PixelCanvas::new(width * factor, height * factor, 0, 0)
+            .with_smooth_lines(options.smooth_lines)
+            .with_dither(options.dither);
+        let mut program = options.program(ScaledCanvas::new(canvas, factor).with_unit_scale(options.unit_scale));
+        execute(&mut program, commands)?;
+        let snapshot = program.snapshot();
+        Ok((shrink(program.into_canvas()), snapshot))
+    } else {
+        // the recording is in pixels, so its size takes the unit scale into account
+        let mut recording_program = options.program(ScaledCanvas::new(RecordingCanvas::new(), 1).with_unit_scale(options.unit_scale));
+        execute(&mut recording_program, commands)?;
+        let snapshot = recording_program.snapshot();
+        // since the program took ownership of the recording canvas, we need to get it back
+        let recording_canvas = recording_program.into_canvas().into_inner();
+        let (width, height) = recording_canvas.dimensions();
+        let (x_offset, y_offset) = recording_canvas.offsets();
+        let margin = options.margin;
+        let (width, height) = (width + 2 * margin, height + 2 * margin);
+        let (x_offset, y_offset) = (x_offset + margin as isize, y_offset + margin as isize);
+        let (scaled_x_offset, scaled_y_offset) = (x_offset * factor as isize, y_offset * factor as isize);
+        let canvas = PixelCanvas::new(width * factor, height * factor, scaled_x_offset, scaled_y_offset)
+            .with_smooth_lines(options.smooth_lines)
+            .with_dither(options.dither);
+        let mut canvas = ScaledCanvas::new(canvas, factor);
+        recording_canvas.replay(&mut canvas);
+        Ok((shrink(canvas), snapshot))
+    }
+}
+
+// carry on a run from a saved state, drawing onto the image it left behind
+fn resume(commands: &[Instruction], state: &ResumeState, options: &ProgramOptions) -> Result<(PixelCanvas, ProgramSnapshot), String> {
+    let (x_offset, y_offset) = state.offsets;
+    let canvas = PixelCanvas::load(&state.image, x_offset, y_offset)
+        .map_err(|e| format!("could not load {} ({})", state.image, e))?
+        .with_smooth_lines(options.smooth_lines);
+    let canvas = ScaledCanvas::new(canvas, 1).with_unit_scale(options.unit_scale);
+    let mut program = options.program(canvas).resumed_from(&state.snapshot);
+    execute(&mut program, commands)?;
+    let snapshot = program.snapshot();
+    Ok((program.into_canvas().into_inner(), snapshot))
+}
+
 fn read_stdin_to_string() -> String {
     let mut acc = String::new();
     for line in io::stdin().lines() {
@@ -31,18 +254,194 @@ fn read_stdin_to_string() -> String {
     acc
 }
 
+// parse a crop rectangle given as x,y,w,h
+fn parse_crop(text: &str) -> Result<(usize, usize, usize, usize), String> {
+    let values = text.split(',')
+        .map(|value| value.trim().parse::<usize>().map_err(|e| format!("invalid crop value '{}' ({})", value, e)))
+        .collect::<Result<Vec<usize>, String>>()?;
+    match values[..] {
+        [_, _, 0, _] | [_, _, _, 0] => Err(String::from("crop width and height have to be at least 1")),
+        [x, y, width, height] => Ok((x, y, width, height)),
+        _ => Err(String::from("crop must be four numbers: x,y,w,h"))
+    }
+}
+
+// what the coordinates of a program are measured in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Units {
+    Pixels,
+    Millimeters,
+    Inches
+}
+
+impl Units {
+    fn pixels_per_unit(self, dpi: f32) -> f32 {
+        match self {
+            Units::Pixels => 1.0,
+            Units::Millimeters => dpi / MM_PER_INCH,
+            Units::Inches => dpi
+        }
+    }
+}
+
+fn parse_units(text: &str) -> Result<Units, String> {
+    match text.to_lowercase().as_str() {
+        "px" => Ok(Units::Pixels),
+        "mm" => Ok(Units::Millimeters),
+        "in" => Ok(Units::Inches),
+        _ => Err(format!("unknown unit '{}' (expected px, mm or in)", text))
+    }
+}
+
+// parse a color given as a CSS name or in hex
+fn parse_color(text: &str) -> Result<Color, String> {
+    Color::from_name(text).or_else(|| Color::from_hex(text)).ok_or_else(|| format!("unknown color '{}'", text))
+}
+
+// how an animation is saved
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AnimationFormat {
+    Gif,
+    // animated png, which keeps full alpha where gif only has on and off
+    Apng
+}
+
+impl AnimationFormat {
+    // the format an output filename's extension asks for, if it's one
+    fn from_filename(filename: &str) -> Option<AnimationFormat> {
+        let filename = filename.to_lowercase();
+        if filename.ends_with(".gif") {
+            Some(AnimationFormat::Gif)
+        } else if filename.ends_with(".apng") {
+            Some(AnimationFormat::Apng)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_animation_format(text: &str) -> Result<AnimationFormat, String> {
+    match text.to_lowercase().as_str() {
+        "gif" => Ok(AnimationFormat::Gif),
+        "apng" => Ok(AnimationFormat::Apng),
+        _ => Err(format!("unknown animation format '{}' (expected gif or apng)", text))
+    }
+}
+
+// parse a program, or report everything wrong with it (in the files its lines came from) and exit
+fn parse_or_exit(source_code: String, sources: &SourceMap) -> Vec<Instruction> {
+    parsing::parse_program(source_code).unwrap_or_else(|errors| {
+        print_parse_errors(&sources.locate(errors));
+        process::exit(1);
+    })
+}
+
+// expand a file's #include lines and parse it, with any errors pointing at the files they're in
+fn parse_with_includes(source_code: &str, path: &Path) -> Result<(Vec<Instruction>, SourceLines), Vec<ParseError>> {
+    let (source_code, sources) = parsing::expand_includes(source_code, Some(path))?;
+    let source_lines = source_lines(&source_code, &sources);
+    let program = parsing::parse_program(source_code).map_err(|errors| sources.locate(errors))?;
+    Ok((program, source_lines))
+}
+
+fn print_parse_errors(errors: &[ParseError]) {
+    for error in errors {
+        error!("{}", error);
+    }
+}
+
+// read the given file, or stdin if there isn't one
+fn read_input(filename: &Option<String>) -> String {
+    if let Some(filename) = filename {
+        fs::read_to_string(filename).expect("Something went wrong reading the file")
+    } else {
+        read_stdin_to_string()
+    }
+}
+
+// read a program from a file (or stdin), with its #include lines replaced by the files they name
+// along with where each of its lines came from
+fn read_program(filename: &Option<String>) -> (String, SourceMap) {
+    let source_code = read_input(filename);
+    parsing::expand_includes(&source_code, filename.as_deref().map(Path::new)).unwrap_or_else(|errors| {
+        print_parse_errors(&errors);
+        process::exit(1);
+    })
+}
+
+// where each instruction of a program is in its source ("line 3", or "leaf.pen, line 2"), by program counter
+// or None for a program that was loaded from bytecode, which doesn't keep line numbers
+type SourceLines = Option<Rc<[String]>>;
+
+fn source_lines(source_code: &str, sources: &SourceMap) -> SourceLines {
+    Some(parsing::parse_line_numbers(source_code).into_iter().map(|line| sources.describe(line)).collect())
+}
+
+// load a program, along with its labels and source lines, from bytecode if the filename ends with .penc and from
+// text otherwise
+// bytecode doesn't keep labels or lines, so it comes with none
+fn load_program_with_labels(filename: &Option<String>) -> (Vec<Instruction>, HashMap<String, usize>, SourceLines) {
+    match filename {
+        Some(filename) if filename.ends_with(bytecode::EXTENSION) => {
+            let program = fs::read(filename).map_err(|e| e.to_string())
+                .and_then(|bytes| bytecode::decode_program(&bytes).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    error!("could not load {} ({})", filename, e);
+                    process::exit(1);
+                });
+            (program, HashMap::new(), None)
+        }
+        _ => {
+            let (source_code, sources) = read_program(filename);
+            let symbol_table = parsing::parse_symbol_table(&source_code);
+            let source_lines = source_lines(&source_code, &sources);
+            (parse_or_exit(source_code, &sources), symbol_table, source_lines)
+        }
+    }
+}
+
+fn load_program(filename: &Option<String>) -> (Vec<Instruction>, SourceLines) {
+    let (program, _, source_lines) = load_program_with_labels(filename);
+    info!("loaded {} instructions from {}", program.len(), filename.as_deref().unwrap_or("stdin"));
+    (program, source_lines)
+}
+
 #[derive(Parser)]
 #[clap(author = "May Lawver", version, about = "A pseudo-assembly turtle graphics language.", long_about = None)]
 struct Command {
     #[clap(subcommand)]
-    which: PenplotCommand
+    which: PenplotCommand,
+    /// Only report errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also report what's going on, like how many instructions ran and what was saved
+    #[clap(short, long, global = true)]
+    verbose: bool
 }
 
 impl Command {
     fn run(&self) {
+        let verbosity = if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        };
+        VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
         match &self.which {
             PenplotCommand::Run(args) => args.run(),
-            PenplotCommand::Fractal(args) => args.run()
+            PenplotCommand::Fractal(args) => args.run(),
+            PenplotCommand::Sheet(args) => args.run(),
+            PenplotCommand::Estimate(args) => args.run(),
+            PenplotCommand::Size(args) => args.run(),
+            PenplotCommand::Seed(args) => args.run(),
+            PenplotCommand::Animate(args) => args.run(),
+            PenplotCommand::Frames(args) => args.run(),
+            PenplotCommand::Disasm(args) => args.run(),
+            PenplotCommand::Check(args) => args.run(),
+            PenplotCommand::Export(args) => args.run(),
+            PenplotCommand::Instructions(args) => args.run()
         }
     }
 }
@@ -50,7 +449,17 @@ impl Command {
 #[derive(Subcommand)]
 enum PenplotCommand {
     Run(RunArgs),
-    Fractal(FractalArgs)
+    Fractal(FractalArgs),
+    Sheet(SheetArgs),
+    Estimate(EstimateArgs),
+    Size(SizeArgs),
+    Seed(SeedArgs),
+    Animate(AnimateArgs),
+    Frames(FramesArgs),
+    Disasm(DisasmArgs),
+    Check(CheckArgs),
+    Export(ExportArgs),
+    Instructions(InstructionsArgs)
 }
 
 /// Run a specified program and render its output to file.
@@ -60,42 +469,290 @@ struct RunArgs {
     #[clap(short, long)]
     input: Option<String>,
     #[clap(short, long)]
-    /// Filename to save the resulting image as
+    /// Filename to save the resulting image as, or - to write a PNG to stdout
     output: String,
     /// Width of canvas
     #[clap(long)]
     width: Option<usize>,
     /// Height of canvas
     #[clap(long)]
-    height: Option<usize>
+    height: Option<usize>,
+    /// Width of canvas in millimeters, converted to pixels with --dpi
+    #[clap(long, conflicts_with = "width")]
+    width_mm: Option<f32>,
+    /// Height of canvas in millimeters, converted to pixels with --dpi
+    #[clap(long, conflicts_with = "height")]
+    height_mm: Option<f32>,
+    /// What the program's coordinates are measured in: px, mm or in
+    #[clap(long, default_value = "px", parse(try_from_str = parse_units))]
+    units: Units,
+    /// Pixels per inch, for converting --units, --width-mm and --height-mm to pixels
+    #[clap(long, default_value_t = DEFAULT_DPI)]
+    dpi: f32,
+    /// Filename to also save the pen strokes to as JSON polylines
+    #[clap(long)]
+    polylines: Option<String>,
+    /// Outline the bounding box of everything drawn by each subroutine call
+    #[clap(long)]
+    debug_bounds: bool,
+    /// Re-render whenever the input file changes
+    #[clap(long, requires = "input")]
+    watch: bool,
+    /// Only save the rectangle x,y,w,h of the rendered image (clamped to the image)
+    #[clap(long, parse(try_from_str = parse_crop))]
+    crop: Option<(usize, usize, usize, usize)>,
+    /// Filename to save the turtle's state to when the program stops, for a later --resume
+    #[clap(long, conflicts_with = "crop")]
+    save_state: Option<String>,
+    /// Carry on from a state saved by --save-state, drawing onto the image saved with it
+    #[clap(long)]
+    resume: Option<String>,
+    /// Only draw these comma-separated layers (tagged with "; @layer name"), plus anything before the first tag
+    #[clap(long, use_value_delimiter = true)]
+    only_layers: Option<Vec<String>>,
+    /// Stop the program after this many steps (instructions, plus the lines they draw), in case it's stuck in a loop
+    #[clap(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+    /// Stop the program if this many calls and loops are in progress at once
+    #[clap(long, default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+    /// Color to put behind the image (a CSS color name, or hex like ffffff), instead of leaving it transparent
+    #[clap(long, parse(try_from_str = parse_color))]
+    background: Option<Color>,
+    /// Also print a preview of the image as text
+    #[clap(long)]
+    ascii: bool,
+    /// Width of the --ascii preview, in characters
+    #[clap(long, default_value_t = 80, requires = "ascii")]
+    cols: usize,
+    /// Seed for the numbers RAND picks (the same seed always draws the same picture)
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// Leave this many empty pixels around each side of the drawing, when the canvas is sized to fit it
+    #[clap(long, default_value_t = 0)]
+    margin: usize,
+    /// Smooth edges by drawing this many times bigger and shrinking the image back down (2 or 4 work well)
+    #[clap(long, default_value_t = 1, conflicts_with = "resume")]
+    aa: usize,
+    /// Dither the colors --aa averages together, so soft gradients don't band
+    #[clap(long)]
+    dither: bool,
+    /// Draw one pixel wide lines anti-aliased
+    #[clap(long)]
+    smooth_lines: bool,
+    /// Print every instruction to stderr as it runs, with the turtle's position and heading afterwards
+    #[clap(long)]
+    trace: bool
+}
+
+// turn a failed save into a message saying what couldn't be saved
+fn check_saved(result: IoResult<()>, filename: &str) -> Result<(), String> {
+    result.map_err(|e| format!("could not save {} ({})", filename, e))?;
+    info!("saved {}", filename);
+    Ok(())
+}
+
+// the time of day as hh:mm:ss, for --watch's status lines
+// this is UTC, since working out the local timezone would need another dependency
+fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    format!("{:02}:{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+fn modified_time(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename).and_then(|metadata| metadata.modified()).ok()
 }
 
 impl RunArgs {
     fn run(&self) {
+        // these would either mix text into the image, or need the image saved somewhere they can find it again
+        if self.output == STDOUT_OUTPUT && (self.ascii || self.watch || self.save_state.is_some()) {
+            error!("--ascii, --watch and --save-state can't be used when writing the image to stdout");
+            process::exit(1);
+        }
+        if self.watch && self.input.as_ref().is_some_and(|input| input.ends_with(bytecode::EXTENSION)) {
+            error!("--watch only works with programs written as text");
+            process::exit(1);
+        }
+        if self.dpi.is_nan() || self.dpi <= 0.0 {
+            error!("--dpi has to be more than 0");
+            process::exit(1);
+        }
+        if self.watch {
+            self.watch();
+            return;
+        }
         // load program
-        let source_code = if let Some(filename) = &self.input {
-            fs::read_to_string(filename).expect("Something went wrong reading the file")
-        } else {
-            read_stdin_to_string()
+        let (commands, source_lines) = load_program(&self.input);
+        if let Err(e) = self.render(&commands, source_lines) {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // re-run the program every time the input file is saved, until killed
+    fn watch(&self) {
+        let filename = self.input.as_ref().expect("--watch requires an input file");
+        let mut last_rendered = None;
+        status!("[{}] Watching {} for changes (Ctrl-C to stop)", timestamp(), filename);
+        loop {
+            let modified = modified_time(filename);
+            if modified.is_some() && modified != last_rendered {
+                // editors often write in several steps, so wait for the file to settle
+                thread::sleep(WATCH_DEBOUNCE);
+                if modified_time(filename) == modified {
+                    last_rendered = modified;
+                    // errors are reported without exiting, so we can keep watching
+                    match fs::read_to_string(filename) {
+                        Ok(source_code) => match parse_with_includes(&source_code, Path::new(filename)) {
+                            Ok((commands, source_lines)) => match self.render(&commands, source_lines) {
+                                Ok(()) => status!("[{}] Rendered {} to {}", timestamp(), filename, self.output),
+                                Err(e) => error!("[{}] {}", timestamp(), e)
+                            },
+                            Err(errors) => {
+                                error!("[{}] couldn't parse {}, keeping the last image", timestamp(), filename);
+                                print_parse_errors(&errors);
+                            }
+                        },
+                        Err(e) => error!("[{}] could not read {} ({})", timestamp(), filename, e)
+                    }
+                }
+            } else {
+                thread::sleep(WATCH_INTERVAL);
+            }
+        }
+    }
+
+    fn options(&self) -> ProgramOptions {
+        ProgramOptions {
+            debug_bounds: self.debug_bounds,
+            layers: self.only_layers.as_ref().map(|layers| layers.iter().cloned().collect()),
+            step_limit: Some(self.max_steps),
+            max_call_depth: Some(self.max_call_depth),
+            supersample: cmp::max(self.aa, 1),
+            smooth_lines: self.smooth_lines,
+            dither: self.dither,
+            unit_scale: self.units.pixels_per_unit(self.dpi),
+            margin: self.margin,
+            seed: self.seed,
+            trace: self.trace,
+            source_lines: None
+        }
+    }
+
+    // the size of the canvas in pixels (at the given resolution), if it was given
+    // physical sizes are rounded to the nearest pixel
+    fn canvas_size(&self, dpi: f32) -> Option<(usize, usize)> {
+        let to_pixels = |mm: f32| (mm / MM_PER_INCH * dpi).round() as usize;
+        let width = self.width.or_else(|| self.width_mm.map(to_pixels));
+        let height = self.height.or_else(|| self.height_mm.map(to_pixels));
+        width.zip(height)
+    }
+
+    fn render(&self, commands: &[Instruction], source_lines: SourceLines) -> Result<(), String> {
+        let options = ProgramOptions { source_lines, ..self.options() };
+        // polylines don't get debug bounds drawn into them, and running the program again shouldn't trace it twice
+        let polyline_options = ProgramOptions { debug_bounds: false, trace: false, ..options.clone() };
+        if self.output.to_lowercase().ends_with(".svg") {
+            return self.render_svg(commands, &options, &polyline_options);
+        }
+        if [".hpgl", ".plt"].iter().any(|extension| self.output.to_lowercase().ends_with(extension)) {
+            return self.render_hpgl(commands, &options, &polyline_options);
+        }
+        if self.output.to_lowercase().ends_with(".csv") {
+            return self.render_path(commands, &polyline_options);
+        }
+        // a resumed run keeps drawing on the earlier image, so its size can't change
+        let resume_state = self.resume.as_ref().map(|filename| ResumeState::load(filename)).transpose()?;
+        let (mut canvas, snapshot) = match &resume_state {
+            Some(state) => resume(commands, state, &options)?,
+            None => render(commands, self.canvas_size(self.dpi), &options)?
         };
-        let commands = parsing::parse_program(source_code).expect("Error parsing code");
-        // determine size + offset
-        let (width, height, x_offset, y_offset) = if let Some((width, height)) = self.width.zip(self.height) {
-            (width, height, 0, 0)
+        if let Some(filename) = &self.save_state {
+            let state = ResumeState { snapshot, offsets: canvas.offsets(), image: self.output.clone() };
+            check_saved(state.save(filename), filename)?;
+        }
+        if let Some((x, y, width, height)) = self.crop {
+            // clamping a rectangle that starts past the edge would leave an empty image
+            if x >= canvas.width() || y >= canvas.height() {
+                return Err(format!("--crop starts at {},{}, outside the {}x{} image", x, y, canvas.width(), canvas.height()));
+            }
+            canvas = canvas.crop(x, y, width, height);
+        }
+        if let Some(background) = self.background {
+            canvas = canvas.with_background(background);
+        }
+        info!("canvas is {}x{} pixels", canvas.width(), canvas.height());
+        if self.ascii {
+            print!("{}", canvas.to_ascii(self.cols));
+        }
+        if self.output == STDOUT_OUTPUT {
+            // rust never translates the bytes written to stdout, even on windows, so the png comes through intact
+            check_saved(canvas.encode_to_writer(io::stdout().lock(), RasterFormat::Png), "the image to stdout")?;
         } else {
-            let sizing_canvas = SizingCanvas::new();
-            let mut sizing_program = ProgramState::new(sizing_canvas);
-            sizing_program.execute(&commands);
-            // since the program took ownership of the sizing canvas, we need to get it back
-            let sizing_canvas = sizing_program.canvas();
-            let (width, height) = sizing_canvas.dimensions();
-            let (x_offset, y_offset) = sizing_canvas.offsets();
-            (width, height, x_offset, y_offset)
-        };
-        let canvas = PixelCanvas::new(width, height, x_offset, y_offset);
-        let mut program = ProgramState::new(canvas);
-        program.execute(&commands);
-        program.save_canvas(&self.output);
+            check_saved(canvas.save(&self.output), &self.output)?;
+        }
+        // export polylines if requested
+        if let Some(filename) = &self.polylines {
+            let mut polyline_program = polyline_options.program(PolylineCanvas::new());
+            if let Some(state) = &resume_state {
+                polyline_program = polyline_program.resumed_from(&state.snapshot);
+            }
+            execute(&mut polyline_program, commands)?;
+            check_saved(polyline_program.save_canvas(filename), filename)?;
+        }
+        Ok(())
+    }
+
+    // svg output draws vectors instead of pixels, so it can't be cropped or resumed
+    fn render_svg(&self, commands: &[Instruction], options: &ProgramOptions, polyline_options: &ProgramOptions) -> Result<(), String> {
+        if self.crop.is_some() || self.save_state.is_some() || self.resume.is_some() || self.ascii {
+            return Err(String::from("--crop, --save-state, --resume and --ascii don't work with svg output"));
+        }
+        // svg coordinates are already in the program's units
+        if self.units != Units::Pixels || self.width_mm.is_some() || self.height_mm.is_some() {
+            return Err(String::from("--units, --width-mm and --height-mm don't work with svg output"));
+        }
+        let mut program = options.program(SvgCanvas::new(self.width.zip(self.height)));
+        execute(&mut program, commands)?;
+        check_saved(program.save_canvas(&self.output), &self.output)?;
+        self.save_polylines(commands, polyline_options)
+    }
+
+    // hp-gl is vectors too, measured in plotter units instead of pixels
+    fn render_hpgl(&self, commands: &[Instruction], options: &ProgramOptions, polyline_options: &ProgramOptions) -> Result<(), String> {
+        if self.crop.is_some() || self.save_state.is_some() || self.resume.is_some() || self.ascii {
+            return Err(String::from("--crop, --save-state, --resume and --ascii don't work with hp-gl output"));
+        }
+        let canvas = HpglCanvas::new(self.canvas_size(HPGL_UNITS_PER_INCH));
+        let mut program = options.program(ScaledCanvas::new(canvas, 1).with_unit_scale(self.units.pixels_per_unit(HPGL_UNITS_PER_INCH)));
+        execute(&mut program, commands)?;
+        check_saved(program.into_canvas().into_inner().save(&self.output), &self.output)?;
+        self.save_polylines(commands, polyline_options)
+    }
+
+    // a csv of the lines drawn, in the program's own units like polylines (so nothing is drawn into it for debugging)
+    fn render_path(&self, commands: &[Instruction], polyline_options: &ProgramOptions) -> Result<(), String> {
+        if self.crop.is_some() || self.save_state.is_some() || self.resume.is_some() || self.ascii {
+            return Err(String::from("--crop, --save-state, --resume and --ascii don't work with csv output"));
+        }
+        if self.units != Units::Pixels || self.width_mm.is_some() || self.height_mm.is_some() {
+            return Err(String::from("--units, --width-mm and --height-mm don't work with csv output"));
+        }
+        let mut program = polyline_options.program(PathCanvas::new());
+        execute(&mut program, commands)?;
+        check_saved(program.save_canvas(&self.output), &self.output)?;
+        self.save_polylines(commands, polyline_options)
+    }
+
+    // export polylines if requested
+    fn save_polylines(&self, commands: &[Instruction], polyline_options: &ProgramOptions) -> Result<(), String> {
+        if let Some(filename) = &self.polylines {
+            let mut polyline_program = polyline_options.program(PolylineCanvas::new());
+            execute(&mut polyline_program, commands)?;
+            check_saved(polyline_program.save_canvas(filename), filename)?;
+        }
+        Ok(())
     }
 }
 
@@ -110,19 +767,42 @@ struct FractalArgs {
     output: Option<String>,
     #[clap(short, long)]
     /// Number of times to run
-    count: usize
+    count: usize,
+    /// Seed for picking between the productions of random rules
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// Output every generation instead of just the last, numbering the files (out.0.pen, out.1.pen, ...)
+    #[clap(long)]
+    all_generations: bool,
+    /// Stop iterating if the output would grow past this many instructions
+    #[clap(long, default_value_t = DEFAULT_MAX_INSTRUCTIONS)]
+    max_instructions: usize
+}
+
+// put a number before a filename's extension, so out.pen becomes out.3.pen
+fn numbered_filename(filename: &str, number: usize) -> String {
+    let path = Path::new(filename);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => format!("{}.{}.{}", stem.to_string_lossy(), number, extension.to_string_lossy()),
+        _ => format!("{}.{}", filename, number)
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
 }
 
 impl FractalArgs {
     fn run(&self) {
-        let system_spec = if let Some(filename) = &self.input {
-            fs::read_to_string(filename).expect("Something went wrong reading the file")
-        } else {
-            read_stdin_to_string()
-        };
+        let system_spec = read_input(&self.input);
         match parsing::parse_l_system(&system_spec) {
-            Ok((_, l_system)) => {
-                let program = l_system.run(self.count);
+            Ok((_, mut l_system)) => {
+                l_system.random_seed = self.seed;
+                if self.all_generations {
+                    let generations = l_system.run_collecting(self.count, self.max_instructions);
+                    self.warn_if_stopped(generations.len() - 1);
+                    self.output_generations(generations);
+                    return;
+                }
+                let (program, completed) = l_system.run_bounded(self.count, self.max_instructions);
+                self.warn_if_stopped(completed);
                 if let Some(filename) = &self.output {
                     save_program(&program, filename).expect("Error saving program");
                 } else {
@@ -131,8 +811,532 @@ impl FractalArgs {
                     }
                 }
             }
-            Err(e) => println!("L system could not be parsed (error {:?})", e)
+            Err(e) => {
+                error!("L system could not be parsed ({:?})", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn warn_if_stopped(&self, completed: usize) {
+        if completed < self.count {
+            warning!("stopped after {} of {} iterations, since the next would have more than {} instructions",
+                completed, self.count, self.max_instructions);
+        }
+    }
+
+    fn output_generations(&self, generations: Vec<Vec<Instruction>>) {
+        for (index, program) in generations.iter().enumerate() {
+            if let Some(filename) = &self.output {
+                let filename = numbered_filename(filename, index);
+                save_program(program, &filename).expect("Error saving program");
+            } else {
+                println!("; generation {}", index);
+                for inst in program {
+                    println!("{}", inst);
+                }
+            }
+        }
+    }
+}
+
+/// Render every program in a directory as a thumbnail on a single contact sheet
+#[derive(Args)]
+struct SheetArgs {
+    /// Directory of programs to render
+    #[clap(short, long)]
+    input: String,
+    #[clap(short, long)]
+    /// Filename to save the contact sheet as
+    output: String,
+    /// Size of the square cell each thumbnail is fit into
+    #[clap(long, default_value_t = 128)]
+    cell: usize,
+    /// Number of thumbnails per row
+    #[clap(long, default_value_t = 4)]
+    columns: usize
+}
+
+impl SheetArgs {
+    fn run(&self) {
+        let mut paths: Vec<_> = fs::read_dir(&self.input)
+            .expect("Something went wrong reading the directory")
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        // render each program, skipping any files that aren't valid programs
+        let cell = cmp::max(self.cell, 1);
+        let mut thumbnails = vec![];
+        for path in paths {
+            let source_code = match fs::read_to_string(&path) {
+                Ok(source_code) => source_code,
+                Err(e) => {
+                    warning!("skipping {} ({})", path.display(), e);
+                    continue;
+                }
+            };
+            match parse_with_includes(&source_code, &path) {
+                Ok((commands, source_lines)) => {
+                    let options = ProgramOptions { source_lines, ..ProgramOptions::default() };
+                    match render(&commands, None, &options) {
+                        Ok((canvas, _)) => thumbnails.push((path, canvas.thumbnail(cell, cell))),
+                        Err(e) => warning!("skipping {} ({})", path.display(), e)
+                    }
+                }
+                Err(_) => warning!("skipping {} (could not be parsed)", path.display())
+            }
+        }
+        // lay the thumbnails out in a grid, centered in their cells, with the filename underneath
+        let columns = cmp::max(self.columns, 1);
+        let rows = cmp::max(thumbnails.len().div_ceil(columns), 1);
+        let row_height = cell + SHEET_LABEL_HEIGHT;
+        let mut sheet = PixelCanvas::new(columns * cell, rows * row_height, 0, 0);
+        sheet.set_color(SHEET_LABEL_COLOR);
+        for (index, (path, thumbnail)) in thumbnails.iter().enumerate() {
+            let (row, column) = (index / columns, index % columns);
+            let x = column * cell + (cell - thumbnail.width()) / 2;
+            let y = row * row_height + (cell - thumbnail.height()) / 2;
+            sheet.blit(thumbnail, x, y);
+            // long names are cut off to fit the cell
+            let mut label: String = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            while canvas::text_size(&label).0 > cell {
+                label.pop();
+            }
+            let label_x = column * cell + (cell - canvas::text_size(&label).0) / 2;
+            sheet.draw_text(label_x as f32, (row * row_height + cell + 1) as f32, &label, 1);
+            status!("row {}, column {}: {}", row, column, path.display());
+        }
+        if let Err(e) = check_saved(sheet.save(&self.output), &self.output) {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Estimate how long a pen plotter would take to draw a program
+#[derive(Args)]
+struct EstimateArgs {
+    /// Filename of source code to measure (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    /// Feed rate while drawing, in units per second
+    #[clap(long, default_value_t = 25.0)]
+    draw_rate: f32,
+    /// Feed rate while travelling with the pen up, in units per second
+    #[clap(long, default_value_t = 75.0)]
+    travel_rate: f32,
+    /// Seconds taken to lower and raise the pen once
+    #[clap(long, default_value_t = 0.2)]
+    lift_time: f32
+}
+
+impl EstimateArgs {
+    fn run(&self) {
+        let (commands, source_lines) = load_program(&self.input);
+        let mut program = ProgramOptions { source_lines, ..ProgramOptions::default() }.program(StatsCanvas::new());
+        execute(&mut program, &commands).unwrap_or_else(|e| {
+            error!("{}", e);
+            process::exit(1);
+        });
+        let stats = program.into_canvas();
+        let seconds = stats.draw_distance() / self.draw_rate
+            + stats.travel_distance() / self.travel_rate
+            + stats.pen_lifts() as f32 * self.lift_time;
+        println!("Pen-down distance: {:.1}", stats.draw_distance());
+        println!("Pen-up distance: {:.1}", stats.travel_distance());
+        println!("Pen lifts: {}", stats.pen_lifts());
+        println!("Estimated time: {:.1} minutes", seconds / 60.0);
+    }
+}
+
+/// Work out how big a program's image would be, without drawing it
+#[derive(Args)]
+struct SizeArgs {
+    /// Filename of source code to measure (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    /// Leave this many empty pixels around each side of the drawing, as with run
+    #[clap(long, default_value_t = 0)]
+    margin: usize,
+    /// Exit with an error if the image would have more pixels than this
+    #[clap(long, default_value_t = DEFAULT_MAX_PIXELS)]
+    max_pixels: usize
+}
+
+impl SizeArgs {
+    fn run(&self) {
+        let (commands, source_lines) = load_program(&self.input);
+        let mut program = ProgramOptions { source_lines, ..ProgramOptions::default() }.program(SizingCanvas::new());
+        let (width, height, x_offset, y_offset) = measure(&mut program, &commands).unwrap_or_else(|e| {
+            error!("{}", e);
+            process::exit(1);
+        });
+        let (width, height) = (width + 2 * self.margin, height + 2 * self.margin);
+        let (x_offset, y_offset) = (x_offset + self.margin as isize, y_offset + self.margin as isize);
+        let pixels = width.saturating_mul(height);
+        println!("{}x{} (offset {}, {})", width, height, x_offset, y_offset);
+        println!("Instructions: {}", commands.len());
+        println!("Pixel buffer: {}", format_bytes(pixels.saturating_mul(mem::size_of::<Color>())));
+        if pixels > self.max_pixels {
+            error!("the image would have {} pixels, more than --max-pixels ({})", pixels, self.max_pixels);
+            process::exit(1);
+        }
+    }
+}
+
+// a number of bytes in the biggest unit that keeps it at least 1, like 1.5 MiB
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = None;
+    while size >= 1024.0 && unit.is_none_or(|i| i + 1 < UNITS.len()) {
+        size /= 1024.0;
+        unit = Some(unit.map_or(0, |i| i + 1));
+    }
+    match unit {
+        Some(i) => format!("{:.1} {}", size, UNITS[i]),
+        None => format!("{} bytes", bytes)
+    }
+}
+
+/// Turn a program into the seed of a starter L system spec, ready for rules to be added
+#[derive(Args)]
+struct SeedArgs {
+    /// Filename of source code to use as the seed (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    #[clap(short, long)]
+    /// Filename to save the L system spec as (if omitted, use stdout)
+    output: Option<String>
+}
+
+impl SeedArgs {
+    fn run(&self) {
+        let (mut seed, _) = load_program(&self.input);
+        if seed.is_empty() {
+            seed.push(Instruction::Noop);
+        }
+        if seed.iter().any(|inst| matches!(inst, Instruction::Goto(_) | Instruction::Call(_) | Instruction::Repeat(_, _))) {
+            warning!("jumps in the seed go to line numbers, which will move once rules are applied");
+        }
+        // there has to be at least one rule, so start with one that leaves an unused symbol as it is
+        let symbol = ('A'..='Z')
+            .map(|c| Instruction::Comment(c.to_string()))
+            .find(|symbol| !seed.contains(symbol))
+            .unwrap_or_else(|| Instruction::Comment(String::from("rule")));
+        let rules = HashMap::from([(RuleKey::new(symbol.clone()), vec![symbol])]);
+        let l_system = LSystem {
+            seed,
+            rules,
+            random_rules: HashMap::new(),
+            parametric_rules: HashMap::new(),
+            aliases: None,
+            parametric_aliases: HashMap::new(),
+            random_seed: 0
+        };
+        let spec = l_system.to_string();
+        // make sure the spec reads back in as the same seed
+        match parsing::parse_l_system(&spec) {
+            Ok((_, parsed)) if parsed.seed == l_system.seed => (),
+            _ => {
+                error!("this program can't be written as an L system seed");
+                process::exit(1);
+            }
+        }
+        if let Some(filename) = &self.output {
+            fs::write(filename, spec).expect("Error saving L system");
+        } else {
+            print!("{}", spec);
+        }
+    }
+}
+
+/// Print a program one instruction per line, numbered by program counter and with its labels
+#[derive(Args)]
+struct DisasmArgs {
+    /// Filename of source code to print (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>
+}
+
+impl DisasmArgs {
+    fn run(&self) {
+        let (program, symbol_table, _) = load_program_with_labels(&self.input);
+        // each line has at most one label, so the symbol table can be turned around into a label for each line
+        let mut labels: Vec<Option<&str>> = vec![None; program.len()];
+        for (label, &pc) in &symbol_table {
+            labels[pc] = Some(label);
+        }
+        // pad the numbers to the same width, so long programs still line up
+        let digits = cmp::max(program.len().saturating_sub(1).to_string().len(), 4);
+        for (pc, (inst, label)) in program.iter().zip(labels).enumerate() {
+            match label {
+                Some(label) => println!("{:0width$} {}: {}", pc, label, inst, width = digits),
+                None => println!("{:0width$} {}", pc, inst, width = digits)
+            }
+        }
+    }
+}
+
+/// List every instruction, with its operands and what it does
+#[derive(Args)]
+struct InstructionsArgs {}
+
+impl InstructionsArgs {
+    fn run(&self) {
+        let signature = |info: &InstructionInfo| format!("{} {}", info.mnemonic, info.operands).trim_end().to_string();
+        // line the descriptions up after the longest signature
+        let width = INSTRUCTION_SET.iter().map(|info| signature(info).len()).max().unwrap_or(0);
+        for info in INSTRUCTION_SET {
+            println!("{:width$}  {}", signature(info), info.description, width = width);
+        }
+    }
+}
+
+/// Look for likely mistakes in a program without running it, exiting with an error if any are serious
+#[derive(Args)]
+struct CheckArgs {
+    /// Filename of source code to check (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>
+}
+
+impl CheckArgs {
+    fn run(&self) {
+        let (source_code, sources) = read_program(&self.input);
+        let diagnostics = check::check_program(source_code).unwrap_or_else(|errors| {
+            print_parse_errors(&sources.locate(errors));
+            process::exit(1);
+        });
+        for diagnostic in &diagnostics {
+            eprintln!("{}: {}", diagnostic.severity(), diagnostic);
+        }
+        if diagnostics.iter().any(|diagnostic| diagnostic.severity() == Severity::Error) {
+            process::exit(1);
+        }
+    }
+}
+
+/// Write a parsed program as JSON, with each instruction tagged by its mnemonic, along with its labels
+#[derive(Args)]
+struct ExportArgs {
+    /// Filename of source code to export (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    /// Filename to save the JSON to (if omitted, use stdout)
+    #[clap(short, long)]
+    output: Option<String>
+}
+
+impl ExportArgs {
+    fn run(&self) {
+        let (program, symbol_table, _) = load_program_with_labels(&self.input);
+        let json = export::program_json(&program, &symbol_table);
+        match &self.output {
+            Some(filename) => if let Err(e) = check_saved(fs::write(filename, json), filename) {
+                error!("{}", e);
+                process::exit(1);
+            },
+            None => print!("{}", json)
+        }
+    }
+}
+
+/// Render a program drawing itself as an animated GIF or APNG
+#[derive(Args)]
+struct AnimateArgs {
+    /// Filename of source code to run (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    #[clap(short, long)]
+    /// Filename to save the animation as
+    output: String,
+    /// Width of canvas
+    #[clap(long)]
+    width: Option<usize>,
+    /// Height of canvas
+    #[clap(long)]
+    height: Option<usize>,
+    /// Number of instructions to run between frames
+    #[clap(long, default_value_t = 100)]
+    every: usize,
+    /// Frames per second
+    #[clap(long, default_value_t = 30)]
+    fps: u32,
+    /// Stop the program after this many instructions, in case it's stuck in a loop
+    #[clap(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+    /// Save as gif or apng (an animated PNG, with full transparency), instead of going by the output's extension
+    #[clap(long, parse(try_from_str = parse_animation_format))]
+    format: Option<AnimationFormat>
+}
+
+// write frames out as a looping gif
+fn save_gif(frames: Vec<RgbaImage>, delay: Delay, filename: &str) -> IoResult<()> {
+    // the default speed gives slightly better colors, but is far too slow for long animations
+    let mut encoder = GifEncoder::new_with_speed(File::create(filename)?, 10);
+    encoder.set_repeat(Repeat::Infinite).map_err(io::Error::other)?;
+    encoder.encode_frames(frames.into_iter().map(|frame| Frame::from_parts(frame, 0, 0, delay)))
+        .map_err(io::Error::other)
+}
+
+// write frames out as a looping animated png, each shown for 1/fps of a second
+// every frame of an apng covers the whole image, so they all have to be the same size
+fn save_apng(frames: Vec<RgbaImage>, fps: u32, filename: &str) -> IoResult<()> {
+    let (width, height) = frames.first().map_or((1, 1), RgbaImage::dimensions);
+    if frames.iter().any(|frame| frame.dimensions() != (width, height)) {
+        return Err(io::Error::other("every frame of an apng has to be the same size"));
+    }
+    let mut encoder = png::Encoder::new(io::BufWriter::new(File::create(filename)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // 0 plays means looping forever
+    encoder.set_animated(frames.len() as u32, 0).map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    let fps = cmp::min(fps, u16::MAX as u32) as u16;
+    for frame in frames {
+        writer.set_frame_delay(1, fps).map_err(io::Error::other)?;
+        writer.write_image_data(frame.as_raw()).map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+fn save_animation(frames: Vec<RgbaImage>, format: AnimationFormat, fps: u32, filename: &str) -> IoResult<()> {
+    let fps = cmp::max(fps, 1);
+    match format {
+        AnimationFormat::Gif => save_gif(frames, Delay::from_numer_denom_ms(1000, fps), filename),
+        AnimationFormat::Apng => save_apng(frames, fps, filename)
+    }
+}
+
+impl AnimateArgs {
+    fn run(&self) {
+        let (commands, source_lines) = load_program(&self.input);
+        if let Err(e) = self.animate(&commands, source_lines) {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    fn animate(&self, commands: &[Instruction], source_lines: SourceLines) -> Result<(), String> {
+        // stepping doesn't check the step limit, so that's done here
+        let options = ProgramOptions { step_limit: None, source_lines, ..ProgramOptions::default() };
+        // every frame has to be the same size, so size the canvas to fit the finished drawing
+        let canvas = match self.width.zip(self.height) {
+            Some((width, height)) => PixelCanvas::new(width, height, 0, 0),
+            None => {
+                let mut sizing_program = options.program(SizingCanvas::new()).with_step_limit(Some(self.max_steps));
+                let (width, height, x_offset, y_offset) = measure(&mut sizing_program, commands)?;
+                PixelCanvas::new(width, height, x_offset, y_offset)
+            }
+        };
+        let mut program = options.program(canvas);
+        let every = cmp::max(self.every, 1);
+        let mut frames = vec![];
+        let mut steps = 0;
+        loop {
+            if steps >= self.max_steps {
+                let (program_counter, location) = (program.snapshot().program_counter, program.location());
+                warning!("{}", ExecutionError::StepLimitExceeded { steps, program_counter, location });
+                break;
+            }
+            match program.step(commands) {
+                Ok(outcome) if outcome.halted => break,
+                Ok(_) => (),
+                Err(e) => return Err(e.to_string())
+            }
+            steps += 1;
+            if steps.is_multiple_of(every) {
+                frames.push(program.canvas().to_frame());
+            }
+        }
+        // always end on the finished drawing
+        frames.push(program.canvas().to_frame());
+        let format = self.format.or_else(|| AnimationFormat::from_filename(&self.output)).unwrap_or(AnimationFormat::Gif);
+        check_saved(save_animation(frames, format, self.fps, &self.output), &self.output)
+    }
+}
+
+/// Render a program once per frame of an animation, with T going from 0 on the first frame to 1 on the last
+#[derive(Args)]
+struct FramesArgs {
+    /// Filename of source code to run (if omitted, use stdin)
+    #[clap(short, long)]
+    input: Option<String>,
+    #[clap(short, long)]
+    /// Filename to save the frames as, numbered like out.0.png, out.1.png, ... (or a single animation for .gif and .apng)
+    output: String,
+    /// Width of canvas
+    #[clap(long)]
+    width: Option<usize>,
+    /// Height of canvas
+    #[clap(long)]
+    height: Option<usize>,
+    /// Number of frames to render
+    #[clap(long, default_value_t = 30)]
+    frames: usize,
+    /// Frames per second, when saving an animation
+    #[clap(long, default_value_t = 30)]
+    fps: u32,
+    /// Stop each frame's program after this many steps (instructions, plus the lines they draw), in case it's stuck in a loop
+    #[clap(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+    /// Save one animation as gif or apng (an animated PNG, with full transparency), instead of numbered images
+    #[clap(long, parse(try_from_str = parse_animation_format))]
+    format: Option<AnimationFormat>
+}
+
+impl FramesArgs {
+    fn run(&self) {
+        let (commands, source_lines) = load_program(&self.input);
+        if let Err(e) = self.render(&commands, source_lines) {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // how far through the animation a frame is, so the first is 0 and the last is 1
+    fn time(&self, frame: usize) -> f32 {
+        if self.frames > 1 { frame as f32 / (self.frames - 1) as f32 } else { 0.0 }
+    }
+
+    fn render(&self, commands: &[Instruction], source_lines: SourceLines) -> Result<(), String> {
+        let options = ProgramOptions { step_limit: Some(self.max_steps), source_lines, ..ProgramOptions::default() };
+        let frames = cmp::max(self.frames, 1);
+        // every frame has to be the same size, so without a size the canvas fits what every frame draws
+        let (width, height, x_offset, y_offset) = match self.width.zip(self.height) {
+            Some((width, height)) => (width, height, 0, 0),
+            None => {
+                // each frame is measured on the same canvas, so the last measurement fits them all
+                let (mut sizing_canvas, mut size) = (SizingCanvas::new(), (0, 0, 0, 0));
+                for frame in 0..frames {
+                    let mut program = options.program(sizing_canvas).with_time(self.time(frame));
+                    size = measure(&mut program, commands)?;
+                    sizing_canvas = program.into_canvas();
+                }
+                size
+            }
+        };
+        // each frame starts from scratch, with nothing carried over from the one before
+        // an animation needs every frame at once, while numbered images are saved as they're drawn
+        let format = self.format.or_else(|| AnimationFormat::from_filename(&self.output));
+        let mut animation_frames = vec![];
+        for frame in 0..frames {
+            let mut program = options.program(PixelCanvas::new(width, height, x_offset, y_offset)).with_time(self.time(frame));
+            execute(&mut program, commands)?;
+            let canvas = program.into_canvas();
+            if format.is_some() {
+                animation_frames.push(canvas.to_frame());
+            } else {
+                let filename = numbered_filename(&self.output, frame);
+                check_saved(canvas.save(&filename), &filename)?;
+            }
+        }
+        if let Some(format) = format {
+            check_saved(save_animation(animation_frames, format, self.fps, &self.output), &self.output)?;
         }
+        Ok(())
     }
 }
 
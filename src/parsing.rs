@@ -1,9 +1,16 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
 use nom::IResult;
-use nom::{branch, bytes::complete::{tag_no_case, take_while}, character::complete, combinator, multi, sequence};
-use crate::color::Color;
-use crate::instruction::Instruction;
-use crate::l_system::LSystem;
+use nom::error::{Error, ErrorKind};
+use nom::{branch, bytes::complete::{tag_no_case, take_while, take_while1}, character::complete, combinator, multi, sequence};
+use crate::color::{BlendMode, Color};
+use crate::instruction::{AngleUnit, Coordinate, Expr, Instruction, Operator, Value, PALETTE_SIZE, REGISTER_COUNT};
+use crate::l_system::{LSystem, ParametricRule, Production, RuleKey};
+
+// how much lighter / darker the light / dark variants of named colors are
+const SHADE_AMOUNT: f32 = 0.2;
 
 // predicate for if a char can go in a comment
 // this is every char except line ending chars (/r, /n) and the labeling char (@)
@@ -11,25 +18,129 @@ fn is_valid_comment_char(c: char) -> bool {
     !(c == '@' || c == '\n' || c == '\r')
 }
 
-// These function are their own thing so they can eventually accommodate registers
+// predicate for if a char can go in a layer name
+fn is_layer_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+// a number that doesn't fit is a failure (not just an error), so it gets reported instead of trying other parses
+fn number_too_large<T>(input: &str) -> IResult<&str, T> {
+    Err(nom::Err::Failure(Error::new(input, ErrorKind::TooLarge)))
+}
+
+// digits of a number literal along with their radix (0x for hex, 0b for binary, decimal otherwise)
+fn parse_radix_digits(input: &str) -> IResult<&str, (u32, &str)> {
+    branch::alt((
+        sequence::preceded(tag_no_case("0x"), combinator::map(complete::hex_digit1, |digits| (16, digits))),
+        sequence::preceded(tag_no_case("0b"), combinator::map(take_while1(|c| c == '0' || c == '1'), |digits| (2, digits))),
+        combinator::map(complete::digit1, |digits| (10, digits))
+    ))(input)
+}
+
+// values are parsed at the platform's native width (usize / isize), so nothing stops at u32::MAX
+// a number that doesn't fit at all is reported as too large rather than as an unrecognized line
 fn parse_usize_value(input: &str) -> IResult<&str, usize> {
-    complete::u32(input).map(|(x, y)| (x, y as usize))
+    let (rest, (radix, digits)) = parse_radix_digits(input)?;
+    match usize::from_str_radix(digits, radix) {
+        Ok(value) => Ok((rest, value)),
+        Err(_) => number_too_large(input)
+    }
 }
 
+// the sign goes before the radix prefix, so -0x10 is -16
 fn parse_isize_value(input: &str) -> IResult<&str, isize> {
-    complete::i32(input).map(|(x, y)| (x, y as isize))
+    let (rest, sign) = combinator::opt(complete::one_of("+-"))(input)?;
+    let (rest, (radix, digits)) = parse_radix_digits(rest)?;
+    match isize::from_str_radix(&format!("{}{}", sign.unwrap_or('+'), digits), radix) {
+        Ok(value) => Ok((rest, value)),
+        Err(_) => number_too_large(input)
+    }
+}
+
+// coordinates can also have a decimal fraction, like 1.5
+fn parse_coordinate_value(input: &str) -> IResult<&str, Coordinate> {
+    let fraction: IResult<&str, &str> = combinator::recognize(sequence::tuple((
+        combinator::opt(complete::one_of("+-")),
+        complete::digit1,
+        complete::char('.'),
+        complete::digit1
+    )))(input);
+    match fraction {
+        Ok((rest, text)) => match text.parse::<Coordinate>() {
+            Ok(value) => Ok((rest, value)),
+            Err(_) => number_too_large(input)
+        },
+        Err(_) => {
+            let (rest, value) = parse_isize_value(input)?;
+            match Coordinate::checked_from_num(value) {
+                Some(value) => Ok((rest, value)),
+                None => number_too_large(input)
+            }
+        }
+    }
+}
+
+// a register, R0 through R15
+fn parse_register(input: &str) -> IResult<&str, usize> {
+    combinator::map_opt(
+        sequence::preceded(tag_no_case("R"), complete::digit1),
+        |digits: &str| digits.parse::<usize>().ok().filter(|&register| register < REGISTER_COUNT)
+    )(input)
+}
+
+// an operand that can be read from a register instead of written out
+fn parse_value<'a, T, F>(literal: F) -> impl FnMut(&'a str) -> IResult<&'a str, Value<T>>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, T>
+{
+    branch::alt((
+        combinator::map(parse_register, Value::Register),
+        combinator::map(literal, Value::Literal)
+    ))
+}
+
+// a coordinate operand, which can also be T, the time through an animation
+fn parse_coordinate_operand(input: &str) -> IResult<&str, Value<Coordinate>> {
+    branch::alt((
+        parse_value(parse_coordinate_value),
+        combinator::value(Value::Time, tag_no_case("T"))
+    ))(input)
+}
+
+// a label that isn't in the symbol table is a failure too, so it can be reported by name
+// nom errors only carry a kind, so this is marked with Fail, and the error's input starts at the label
+fn undefined_label<T>(input: &str) -> IResult<&str, T> {
+    Err(nom::Err::Failure(Error::new(input, ErrorKind::Fail)))
+}
+
+// a label, resolved to the address of the line it's on
+// without a symbol table (like in L systems) there are no labels, so this is just an error
+fn parse_label<'a>(symbol_table: Option<&'a HashMap<String, usize>>) -> impl FnMut(&'a str) -> IResult<&'a str, usize> {
+    move |input| {
+        let (rest, label) = complete::alpha1(input)?;
+        match symbol_table {
+            Some(symbol_table) => match symbol_table.get(label) {
+                Some(&address) => Ok((rest, address)),
+                None => undefined_label(input)
+            },
+            None => Err(nom::Err::Error(Error::new(input, ErrorKind::MapOpt)))
+        }
+    }
 }
 
 fn parse_address<'a>(symbol_table: Option<&'a HashMap<String, usize>>) -> impl FnMut(&'a str) -> IResult<&'a str, usize> {
     branch::alt((
-        combinator::map(complete::u32, |x| x as usize), // a literal usize value
-        combinator::map_opt(complete::alpha1, move |tag| {
-            if let Some(symbol_table) = symbol_table {
-                symbol_table.get(tag).copied()
-            } else {
-                None
-            }
-        }) // a label
+        parse_usize_value, // a literal usize value
+        parse_label(symbol_table) // a label
+    ))
+}
+
+// a relative jump offset, or a label (which is turned into the offset from this line)
+fn parse_offset<'a>(symbol_table: Option<&'a HashMap<String, usize>>, line: usize) -> impl FnMut(&'a str) -> IResult<&'a str, isize> {
+    branch::alt((
+        parse_isize_value, // a literal offset
+        // jumps go to pc + offset + 1
+        combinator::map(parse_label(symbol_table), move |target| target as isize - line as isize - 1) // a label
     ))
 }
 
@@ -66,33 +177,12 @@ fn instruction_word<'a, F: Fn(&'a str) -> Instruction>(name: &'static str, instr
     combinator::map(tag_no_case(name), instruction)
 }
 
-pub fn parse_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, input: &'a str) -> IResult<&'a str, Instruction> {
+// instructions that change the flow of control
+fn parse_control_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, line: usize, input: &'a str) -> IResult<&'a str, Instruction> {
     branch::alt((
         instruction_word("NOOP", |_| Instruction::Noop), // no-op
         instruction_word("RTRN", |_| Instruction::Return), // return
-        instruction_word("BLOT", |_| Instruction::Blot), // blot
         instruction_word("HALT", |_| Instruction::Halt), // halt
-        instruction_word("BLNK", |_| Instruction::SetColor(Color(0, 0, 0, 0))), // blank
-        instruction_args("MOVE",
-            sequence::separated_pair(parse_isize_value, complete::space1, parse_isize_value),
-            |(x, y)| Instruction::Move(x, y)
-        ),
-        instruction_args("SHFT",
-            sequence::separated_pair(parse_isize_value, complete::space1, parse_isize_value),
-            |(dx, dy)| Instruction::MoveRel(dx, dy)
-        ), // move relative
-        instruction_args("WALK",
-            parse_isize_value,
-            Instruction::MoveForward
-        ), // move relative
-        instruction_args("FACE",
-            parse_isize_value,
-            Instruction::Face
-        ), // face
-        instruction_args("TURN",
-            parse_isize_value,
-            Instruction::Turn
-        ), // face
         instruction_args("GOTO",
             parse_address(symbol_table),
             Instruction::Goto
@@ -102,17 +192,201 @@ pub fn parse_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, i
             Instruction::Call
         ), // call
         instruction_args("JUMP",
-            parse_isize_value,
+            parse_offset(symbol_table, line),
             Instruction::Jump
         ), // jump
+        instruction_args("JEZ",
+            sequence::separated_pair(parse_register, complete::space1, parse_offset(symbol_table, line)),
+            |(register, offset)| Instruction::JumpIfZero(register, offset)
+        ), // jump if zero
+        instruction_args("JNZ",
+            sequence::separated_pair(parse_register, complete::space1, parse_offset(symbol_table, line)),
+            |(register, offset)| Instruction::JumpIfNotZero(register, offset)
+        ), // jump if not zero
         instruction_args("LOOP",
             sequence::separated_pair(parse_address(symbol_table), complete::space1, parse_usize_value),
             |(addr, num)| Instruction::Repeat(addr, num)
         ), // loop
-        combinator::map(
-            sequence::preceded(complete::char(';'), take_while(is_valid_comment_char)),
-            |s: &str| Instruction::Comment(s.trim().to_string())
-        ), // comment
+        // where the block ends is filled in once the matching END has been found
+        instruction_args("REP", parse_usize_value, |num| Instruction::RepeatBlock(0, num)), // repeat block
+        // END is the start of ENDF and ENDS, so it has to be the whole word
+        sequence::terminated(
+            instruction_word("END", |_| Instruction::EndRepeat),
+            combinator::not(complete::alphanumeric1)
+        ) // end repeat block
+    ))(input)
+}
+
+// instructions that work with registers
+fn parse_register_instruction(input: &str) -> IResult<&str, Instruction> {
+    // destination register, then two source registers
+    fn three_registers(input: &str) -> IResult<&str, (usize, usize, usize)> {
+        sequence::tuple((
+            parse_register,
+            sequence::preceded(complete::space1, parse_register),
+            sequence::preceded(complete::space1, parse_register)
+        ))(input)
+    }
+    branch::alt((
+        instruction_args("LOAD",
+            sequence::separated_pair(parse_register, complete::space1, parse_isize_value),
+            |(register, value)| Instruction::Load(register, value)
+        ), // load
+        instruction_args("PARG", parse_isize_value, Instruction::PushArg), // push argument
+        instruction_args("ARG",
+            sequence::separated_pair(parse_register, complete::space1, parse_usize_value),
+            |(register, index)| Instruction::Arg(register, index)
+        ), // argument
+        instruction_args("ADD", three_registers, |(r, a, b)| Instruction::Add(r, a, b)), // add
+        instruction_args("SUB", three_registers, |(r, a, b)| Instruction::Sub(r, a, b)), // subtract
+        instruction_args("MUL", three_registers, |(r, a, b)| Instruction::Mul(r, a, b)), // multiply
+        instruction_args("RAND",
+            sequence::tuple((
+                parse_register,
+                sequence::preceded(complete::space1, parse_isize_value),
+                sequence::preceded(complete::space1, parse_isize_value)
+            )),
+            |(register, lo, hi)| Instruction::Random(register, lo, hi)
+        ) // random
+    ))(input)
+}
+
+// instructions that move the pen or put things on the canvas
+fn parse_drawing_instruction(input: &str) -> IResult<&str, Instruction> {
+    branch::alt((
+        instruction_word("BLOT", |_| Instruction::Blot), // blot
+        instruction_word("PENU", |_| Instruction::PenUp), // pen up
+        instruction_word("PEND", |_| Instruction::PenDown), // pen down
+        instruction_args("MOVE",
+            sequence::separated_pair(parse_coordinate_operand, complete::space1, parse_coordinate_operand),
+            |(x, y)| Instruction::Move(x, y)
+        ),
+        instruction_args("LINE",
+            sequence::separated_pair(parse_coordinate_operand, complete::space1, parse_coordinate_operand),
+            |(x, y)| Instruction::LineTo(x, y)
+        ), // line to
+        instruction_args("SHFT",
+            sequence::separated_pair(parse_coordinate_operand, complete::space1, parse_coordinate_operand),
+            |(dx, dy)| Instruction::MoveRel(dx, dy)
+        ), // move relative
+        instruction_args("WALK",
+            parse_coordinate_operand,
+            Instruction::MoveForward
+        ), // move relative
+        instruction_args("WLKD",
+            sequence::separated_pair(
+                parse_coordinate_operand,
+                complete::space1,
+                // a register's spacing can only be checked once it's run
+                combinator::verify(parse_coordinate_operand, |spacing| !matches!(spacing, Value::Literal(n) if *n <= 0))
+            ),
+            |(dist, spacing)| Instruction::WalkDots(dist, spacing)
+        ), // walk leaving dots
+        instruction_args("FACE",
+            parse_coordinate_operand,
+            Instruction::Face
+        ), // face
+        instruction_args("TURN",
+            parse_coordinate_operand,
+            Instruction::Turn
+        ), // face
+        instruction_args("ARC",
+            sequence::separated_pair(parse_coordinate_operand, complete::space1, parse_coordinate_operand),
+            |(radius, theta)| Instruction::Arc(radius, theta)
+        ), // arc
+        instruction_args_opt("ANGLEMODE",
+            complete::alpha1,
+            |name| AngleUnit::from_name(name).map(Instruction::AngleMode)
+        ), // angle mode
+        instruction_args("SCAL",
+            parse_coordinate_operand,
+            Instruction::Scale
+        ), // scale
+        instruction_word("MIRR", |_| Instruction::Mirror), // mirror
+        instruction_args("CIRC",
+            parse_coordinate_operand,
+            Instruction::Circle
+        ), // circle
+        instruction_args("POLY",
+            sequence::separated_pair(
+                combinator::verify(parse_usize_value, |&sides| sides >= 3),
+                complete::space1,
+                parse_coordinate_operand
+            ),
+            |(sides, radius)| Instruction::Polygon(sides, radius)
+        ), // polygon
+        instruction_args("PENW",
+            parse_usize_value,
+            Instruction::PenWidth
+        ), // pen width
+        instruction_args("DYNW",
+            sequence::separated_pair(parse_usize_value, complete::space1, parse_usize_value),
+            |(min, max)| Instruction::DynamicWidth(min, max)
+        ), // dynamic width
+        instruction_args("TEXT",
+            take_while1(is_valid_comment_char),
+            |text: &str| Instruction::Text(text.trim_end().to_string())
+        ), // text
+        // alt only takes so many parsers at once, so the last few get their own
+        branch::alt((
+            instruction_word("HOME", |_| Instruction::Home), // home
+            instruction_args("FACP",
+                sequence::separated_pair(parse_coordinate_operand, complete::space1, parse_coordinate_operand),
+                |(x, y)| Instruction::FaceToward(x, y)
+            ), // face point
+            instruction_word("PUSH", |_| Instruction::Push), // push turtle
+            instruction_word("POP", |_| Instruction::Pop), // pop turtle
+            combinator::value(Instruction::Push, complete::char('[')), // push turtle (L-system style)
+            combinator::value(Instruction::Pop, complete::char(']')) // pop turtle (L-system style)
+        ))
+    ))(input)
+}
+
+// instructions that fill areas, or limit where the pen can draw
+fn parse_fill_instruction(input: &str) -> IResult<&str, Instruction> {
+    branch::alt((
+        instruction_word("BGNF", |_| Instruction::BeginFill), // begin fill
+        instruction_word("ENDF", |_| Instruction::EndFill), // end fill
+        instruction_args("HTCH",
+            sequence::separated_pair(parse_isize_value, complete::space1, parse_usize_value),
+            |(angle, spacing)| Instruction::Hatch(angle, spacing)
+        ), // hatch
+        instruction_word("FILL", |_| Instruction::Fill), // flood fill
+        instruction_args("DISC",
+            parse_coordinate_operand,
+            Instruction::Disc
+        ), // filled circle
+        instruction_word("STNC", |_| Instruction::BeginStencil), // begin stencil
+        instruction_word("ENDS", |_| Instruction::EndStencil), // end stencil
+        instruction_word("CLRS", |_| Instruction::ClearStencil) // clear stencil
+    ))(input)
+}
+
+// a named color, optionally followed by light or dark, which shift its lightness but keep its hue
+fn parse_named_color<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, Instruction> {
+    instruction_args_opt(name,
+        sequence::pair(
+            complete::alpha1,
+            combinator::opt(sequence::preceded(
+                complete::space1,
+                branch::alt((tag_no_case("light"), tag_no_case("dark")))
+            ))
+        ),
+        |(name, shade): (&str, Option<&str>)| {
+            let color = Color::from_name(name)?;
+            Some(Instruction::SetColor(match shade.map(|shade| shade.to_ascii_lowercase()).as_deref() {
+                Some("light") => color.lighten(SHADE_AMOUNT),
+                Some("dark") => color.darken(SHADE_AMOUNT),
+                _ => color
+            }))
+        }
+    )
+}
+
+// instructions that set the pen color
+fn parse_color_instruction(input: &str) -> IResult<&str, Instruction> {
+    branch::alt((
+        instruction_word("BLNK", |_| Instruction::SetColor(Color(0, 0, 0, 0))), // blank
         instruction_args_opt("RGBA",
             sequence::separated_pair(
                 sequence::separated_pair(parse_usize_value, complete::space1, parse_usize_value),
@@ -132,6 +406,68 @@ pub fn parse_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, i
                 Instruction::SetColor(Color::from_ints(r, g, b, 255)?)
             )
         ), // set color (RGB)
+        instruction_args_opt("HEX",
+            combinator::recognize(sequence::pair(complete::char('#'), complete::hex_digit1)),
+            |hex| Some(Instruction::SetColor(Color::from_hex(hex)?))
+        ), // set color (hex)
+        instruction_args_opt("LERP",
+            multi::separated_list1(complete::space1, parse_usize_value),
+            |values| match values[..] {
+                [r1, g1, b1, r2, g2, b2, steps, step] => Some(Instruction::Lerp(
+                    Color::from_ints(r1, g1, b1, 255)?,
+                    Color::from_ints(r2, g2, b2, 255)?,
+                    steps,
+                    step
+                )),
+                _ => None
+            }
+        ), // set color (interpolated)
+        instruction_args("ADJC",
+            sequence::tuple((
+                parse_isize_value,
+                sequence::preceded(complete::space1, parse_isize_value),
+                sequence::preceded(complete::space1, parse_isize_value),
+                sequence::preceded(complete::space1, parse_isize_value)
+            )),
+            |(dr, dg, db, da)| Instruction::AdjustColor(dr, dg, db, da)
+        ), // adjust color
+        parse_named_color("COLR"), // set color (named)
+        parse_named_color("COLOR"), // set color (named, long form)
+        instruction_args_opt("BLND",
+            complete::alpha1,
+            |name| BlendMode::from_name(name).map(Instruction::BlendMode)
+        ), // blend mode
+        instruction_args_opt("PALD",
+            sequence::separated_pair(parse_palette_index, complete::space1, take_while1(|c: char| c == '#' || c.is_ascii_alphanumeric())),
+            |(index, color)| Some(Instruction::DefinePalette(index, Color::from_hex(color).or_else(|| Color::from_name(color))?))
+        ), // define palette color
+        instruction_args("PALU", parse_value(parse_palette_index), Instruction::UsePalette) // use palette color
+    ))(input)
+}
+
+fn parse_palette_index(input: &str) -> IResult<&str, usize> {
+    combinator::verify(parse_usize_value, |&index| index < PALETTE_SIZE)(input)
+}
+
+// line is the index of the line being parsed, which labels in relative jumps are measured from
+pub fn parse_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, line: usize, input: &'a str) -> IResult<&'a str, Instruction> {
+    branch::alt((
+        |input| parse_control_instruction(symbol_table, line, input),
+        parse_drawing_instruction,
+        parse_fill_instruction,
+        parse_color_instruction,
+        parse_register_instruction,
+        combinator::map(
+            sequence::preceded(
+                sequence::tuple((complete::char(';'), complete::space0, tag_no_case("@layer"), complete::space1)),
+                take_while1(is_layer_name_char)
+            ),
+            |name: &str| Instruction::Layer(name.to_string())
+        ), // layer tag
+        combinator::map(
+            sequence::preceded(complete::char(';'), take_while(is_valid_comment_char)),
+            |s: &str| Instruction::Comment(s.trim().to_string())
+        ), // comment
         combinator::map(
             sequence::delimited(complete::char('<'), complete::anychar, complete::char('>')),
             |c| Instruction::Comment(c.to_string())
@@ -140,39 +476,530 @@ pub fn parse_instruction<'a>(symbol_table: Option<&'a HashMap<String, usize>>, i
 }
 
 fn parse_instruction_symless(input: &str) -> IResult<&str, Instruction> {
-    parse_instruction(None, input)
+    parse_instruction(None, 0, input)
 }
 
-// TODO: these need to return proper errors
-pub fn parse_program(text: String) -> Option<Vec<Instruction>> {
-    let split: Vec<&str> = text.trim().split('\n').collect();
-    // generate symbol table
+// why a line couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Unrecognized,
+    TooLarge,
+    DuplicateLabel(String, Option<String>, usize), // the label, and the included file (if any) and (1-based) line it was first defined on
+    UndefinedLabel(String),
+    IncludeFailed(Vec<String>, String), // the files doing the including, outermost first, and why it couldn't be read
+    IncludeCycle(Vec<String>),  // the files doing the including, ending with the one that's already being included
+    UnclosedRepeat,
+    UnmatchedEnd,
+    UnclosedComment,
+    UnclosedWhile,
+    UnmatchedWend
+}
+
+// a line of a program that couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize, // 1-based
+    pub file: Option<String>, // the included file the line is in, or None if it's in the program itself
+    pub text: String,
+    pub kind: ParseErrorKind
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: ", describe_line(&self.file, self.line))?;
+        match &self.kind {
+            ParseErrorKind::Unrecognized => write!(f, "unrecognized instruction '{}'", self.text),
+            ParseErrorKind::TooLarge => write!(f, "number too large in '{}'", self.text),
+            ParseErrorKind::DuplicateLabel(label, file, first_line) =>
+                write!(f, "label '{}' was already defined on {}", label, describe_line(file, *first_line)),
+            ParseErrorKind::UndefinedLabel(label) => write!(f, "label '{}' is never defined", label),
+            ParseErrorKind::IncludeFailed(chain, reason) =>
+                write!(f, "couldn't include '{}' from {} ({})", self.text, chain.join(" -> "), reason),
+            ParseErrorKind::IncludeCycle(chain) => write!(f, "files include each other: {}", chain.join(" -> ")),
+            ParseErrorKind::UnclosedRepeat => write!(f, "REP has no END to close it"),
+            ParseErrorKind::UnmatchedEnd => write!(f, "END has no REP to close"),
+            ParseErrorKind::UnclosedComment => write!(f, "/* comment is never closed with */"),
+            ParseErrorKind::UnclosedWhile => write!(f, "WHNZ has no WEND to close it"),
+            ParseErrorKind::UnmatchedWend => write!(f, "WEND has no WHNZ to close")
+        }
+    }
+}
+
+// "line 3", or "leaf.pen, line 3" for a line of an included file
+fn describe_line(file: &Option<String>, line: usize) -> String {
+    match file {
+        Some(file) => format!("{}, line {}", file, line),
+        None => format!("line {}", line)
+    }
+}
+
+// the label a line defines, if any
+// layer tags ("; @layer name") use @ too, but they aren't labels
+fn line_label(line: &str) -> Option<&str> {
+    let (before, label) = line.trim().split_once('@')?;
+    let label = label.split('@').next().unwrap_or(label).trim();
+    let is_layer_tag = before.trim() == ";" && label.to_ascii_lowercase().starts_with("layer");
+    if is_layer_tag {
+        None
+    } else {
+        Some(label)
+    }
+}
+
+// the file an #include line names, like "leaf.pen" in #include "leaf.pen"
+fn include_target(line: &str) -> Option<&str> {
+    line.trim().strip_prefix("#include")?.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+// the files currently being included, as their canonical paths (to compare) and as they were written (to report)
+type IncludeChain = Vec<(Option<PathBuf>, String)>;
+
+/// Where each line of a program with its `#include`s expanded came from, so errors in it can be pointed at the file
+/// and line they're really on:
+///
+/// ```
+/// use penplot::parsing;
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join("penplot-source-map-doctest");
+/// fs::create_dir_all(&dir).unwrap();
+/// fs::write(dir.join("leaf.pen"), "WALK 5\nWLAK 5\n").unwrap();
+/// let (text, sources) = parsing::expand_includes("TURN 90\n#include \"leaf.pen\"\nTRUN 90", Some(&dir.join("tree.pen"))).unwrap();
+/// let errors = sources.locate(parsing::parse_program(text).unwrap_err());
+/// // the typo in the included file is on its second line, and the one after the #include is still on line 3
+/// assert_eq!((errors[0].file.as_deref(), errors[0].line), (Some(dir.join("leaf.pen").to_str().unwrap()), 2));
+/// assert_eq!((errors[1].file.as_deref(), errors[1].line), (None, 3));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    // for each line of the expanded program, the included file it's from (None for the program itself) and its line there
+    lines: Vec<(Option<String>, usize)>
+}
+
+impl SourceMap {
+    /// Describe a line of an expanded program by where it came from, like "line 3" or "leaf.pen, line 2".
+    pub fn describe(&self, line: usize) -> String {
+        match line.checked_sub(1).and_then(|index| self.lines.get(index)) {
+            Some((file, line)) => describe_line(file, *line),
+            None => describe_line(&None, line)
+        }
+    }
+
+    /// Point errors in an expanded program at the file and line they came from.
+    pub fn locate(&self, errors: Vec<ParseError>) -> Vec<ParseError> {
+        let source = |line: usize| line.checked_sub(1).and_then(|index| self.lines.get(index)).cloned();
+        errors.into_iter().map(|mut error| {
+            if let Some((file, line)) = source(error.line) {
+                (error.file, error.line) = (file, line);
+            }
+            if let ParseErrorKind::DuplicateLabel(_, file, first_line) = &mut error.kind {
+                if let Some(source) = source(*first_line) {
+                    (*file, *first_line) = source;
+                }
+            }
+            error
+        }).collect()
+    }
+}
+
+fn inline_includes(text: &str, dir: &Path, chain: &mut IncludeChain, lines: &mut Vec<String>, sources: &mut SourceMap) -> Result<(), ParseError> {
+    // lines of the program itself aren't given a file, since whoever is reporting on it knows which one it is
+    let file = chain.split_first().and_then(|(_, included)| included.last()).map(|(_, name)| name.clone());
+    for (index, line) in text.split('\n').enumerate() {
+        let target = match include_target(line) {
+            Some(target) => dir.join(target),
+            None => {
+                lines.push(line.to_string());
+                sources.lines.push((file.clone(), index + 1));
+                continue;
+            }
+        };
+        let names = || chain.iter().map(|(_, name)| name.clone()).collect::<Vec<_>>();
+        let error = |kind| ParseError { line: index + 1, file: file.clone(), text: line.trim().to_string(), kind };
+        let failed = |e: std::io::Error| error(ParseErrorKind::IncludeFailed(names(), e.to_string()));
+        // the same file can be reached by different paths, so cycles are looked for by canonical path
+        let canonical = fs::canonicalize(&target).map_err(failed)?;
+        if chain.iter().any(|(path, _)| path.as_ref() == Some(&canonical)) {
+            let mut cycle = names();
+            cycle.push(target.display().to_string());
+            return Err(error(ParseErrorKind::IncludeCycle(cycle)));
+        }
+        let included = fs::read_to_string(&canonical).map_err(failed)?;
+        chain.push((Some(canonical), target.display().to_string()));
+        // only the end is trimmed, so the included file's lines keep their numbers
+        let inlined = inline_includes(included.trim_end(), target.parent().unwrap_or(dir), chain, lines, sources);
+        chain.pop();
+        inlined?;
+    }
+    Ok(())
+}
+
+/// Replace each `#include "file"` line with the contents of that file (and whatever it includes), so programs can
+/// share subroutines. Paths are relative to the directory of the file doing the including; `path` is the file `text`
+/// came from, or None (for stdin) to use the working directory. Labels are shared by the whole combined program.
+/// The `SourceMap` that comes with it turns line numbers in the combined program back into lines of the files.
+pub fn expand_includes(text: &str, path: Option<&Path>) -> Result<(String, SourceMap), Vec<ParseError>> {
+    let name = path.map_or_else(|| String::from("<stdin>"), |path| path.display().to_string());
+    let mut chain = vec![(path.and_then(|path| fs::canonicalize(path).ok()), name)];
+    let dir = path.and_then(Path::parent).unwrap_or(Path::new(""));
+    let (mut lines, mut sources) = (vec![], SourceMap::default());
+    inline_includes(text, dir, &mut chain, &mut lines, &mut sources).map_err(|error| vec![error])?;
+    Ok((lines.join("\n"), sources))
+}
+
+/// Blank out every `/* block comment */`. Lines are kept even when that leaves them empty, so line numbers still
+/// match the source, but empty lines aren't instructions. Unlike `;` comments (which L systems use as symbols),
+/// block comments don't count towards jumps.
+///
+/// ```
+/// use penplot::parsing;
+///
+/// let text = "/* a square\n   of side 10 */\nWALK 10 /* forward */\nTURN 90";
+/// assert_eq!(parsing::strip_block_comments(text).unwrap(), "\n\nWALK 10 \nTURN 90");
+/// ```
+pub fn strip_block_comments(text: &str) -> Result<String, ParseError> {
+    let mut lines = vec![];
+    // the line the comment being skipped started on, if there is one
+    let mut open: Option<(usize, &str)> = None;
+    for (index, line) in text.split('\n').enumerate() {
+        let (mut kept, mut rest) = (String::new(), line);
+        loop {
+            if open.is_some() {
+                match rest.split_once("*/") {
+                    Some((_, after)) => {
+                        open = None;
+                        rest = after;
+                    }
+                    None => break
+                }
+            } else {
+                match rest.split_once("/*") {
+                    Some((before, after)) => {
+                        kept.push_str(before);
+                        open = Some((index, line));
+                        rest = after;
+                    }
+                    None => {
+                        kept.push_str(rest);
+                        break;
+                    }
+                }
+            }
+        }
+        lines.push(kept);
+    }
+    match open {
+        Some((index, line)) => Err(ParseError { line: index + 1, file: None, text: line.trim().to_string(), kind: ParseErrorKind::UnclosedComment }),
+        None => Ok(lines.join("\n"))
+    }
+}
+
+// every line that isn't blank is one instruction, so indices into this are program counters
+// each line comes with its (1-based) line number in the source, for errors
+// lines are trimmed, so they can be indented (and \r\n line endings work)
+// block comments have to be stripped out first
+pub(crate) fn program_lines(text: &str) -> Vec<(usize, &str)> {
+    text.split('\n').enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect()
+}
+
+// map each label to the program counter it's on, along with an error for every label that's defined more than once
+fn build_symbol_table(lines: &[(usize, &str)]) -> (HashMap<String, usize>, Vec<ParseError>) {
     let mut symbol_table: HashMap<String, usize> = HashMap::new();
-    for (index, line) in split.iter().enumerate() {
-        let mut command = line.trim().split('@');
-        if let Some(label) = command.nth(1) {
-            symbol_table.insert(label.trim().to_string(), index);
+    let mut errors: Vec<ParseError> = vec![];
+    for (pc, &(line_number, line)) in lines.iter().enumerate() {
+        if let Some(label) = line_label(line) {
+            if let Some(&first) = symbol_table.get(label) {
+                errors.push(ParseError {
+                    line: line_number,
+                    file: None,
+                    text: line.trim().to_string(),
+                    kind: ParseErrorKind::DuplicateLabel(label.to_string(), None, lines[first].0)
+                });
+            } else {
+                symbol_table.insert(label.to_string(), pc);
+            }
+        }
+    }
+    (symbol_table, errors)
+}
+
+/// The (1-based) line of the source each instruction of a program is on, by program counter. Blank lines and
+/// comments aren't instructions, so these can run ahead of the program counters:
+///
+/// ```
+/// use penplot::parsing;
+///
+/// let text = "/* a square */\nREP 4\n\n    WALK 10\n    TURN 90\nEND";
+/// assert_eq!(parsing::parse_program(String::from(text)).unwrap().len(), 4);
+/// assert_eq!(parsing::parse_line_numbers(text), vec![2, 4, 5, 6]);
+/// ```
+pub fn parse_line_numbers(text: &str) -> Vec<usize> {
+    // a comment that's never closed is reported by parse_program, so this just leaves it in
+    let text = strip_block_comments(text).unwrap_or_else(|_| text.to_string());
+    program_lines(&text).into_iter().map(|(line, _)| line).collect()
+}
+
+// the labels a program defines, and the program counter each one points to
+pub fn parse_symbol_table(text: &str) -> HashMap<String, usize> {
+    // a comment that's never closed is reported by parse_program, so this just leaves it in
+    let text = strip_block_comments(text).unwrap_or_else(|_| text.to_string());
+    build_symbol_table(&program_lines(&text)).0
+}
+
+// the kinds of block, which each have to be closed by their own word
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Repeat, // REP ... END
+    While   // WHNZ ... WEND
+}
+
+impl Block {
+    fn unclosed(self) -> ParseErrorKind {
+        match self {
+            Block::Repeat => ParseErrorKind::UnclosedRepeat,
+            Block::While => ParseErrorKind::UnclosedWhile
         }
     }
+
+    fn unmatched(self) -> ParseErrorKind {
+        match self {
+            Block::Repeat => ParseErrorKind::UnmatchedEnd,
+            Block::While => ParseErrorKind::UnmatchedWend
+        }
+    }
+}
+
+// a line that opens or closes a block
+enum BlockEdge {
+    Open(Block),
+    Close(Block)
+}
+
+// WHNZ R and WEND, which aren't instructions of their own: parse_program lowers them into a JEZ R past the WEND and
+// a JUMP back to the WHNZ, so the register is the register WHNZ loops on, or None for WEND
+fn parse_while_line(input: &str) -> IResult<&str, Option<usize>> {
+    branch::alt((
+        combinator::map(sequence::preceded(sequence::pair(tag_no_case("WHNZ"), complete::space1), parse_register), Some),
+        combinator::value(None, sequence::terminated(tag_no_case("WEND"), combinator::not(complete::alphanumeric1)))
+    ))(input)
+}
+
+/// Parse a program, one instruction per line. Parsing carries on past bad lines, so every error in the program is
+/// reported at once, each with the line it's on:
+///
+/// ```
+/// use penplot::parsing::{self, ParseErrorKind};
+///
+/// let errors = parsing::parse_program(String::from("WLAK 10\nWALK 10\nJUMP nowhere")).unwrap_err();
+/// assert_eq!(errors.len(), 2);
+/// assert_eq!((errors[0].line, &errors[0].kind), (1, &ParseErrorKind::Unrecognized));
+/// assert_eq!((errors[1].line, &errors[1].kind), (3, &ParseErrorKind::UndefinedLabel(String::from("nowhere"))));
+/// ```
+///
+/// Blank lines are skipped, so they don't count towards jumps or program counters, though error line numbers are
+/// still those of the source:
+///
+/// ```
+/// use penplot::instruction::Instruction;
+/// use penplot::parsing;
+///
+/// let text = "GOTO end\n; skipped\n\nWALK 10\n\nNOOP @ end\nJUMP end";
+/// let program = parsing::parse_program(String::from(text)).unwrap();
+/// assert_eq!(program.len(), 5);
+/// assert_eq!(program[0], Instruction::Goto(3));
+/// assert_eq!(program[4], Instruction::Jump(-2));
+/// assert_eq!(parsing::parse_symbol_table(text)["end"], 3);
+///
+/// let errors = parsing::parse_program(String::from("WALK 10\n\nWLAK 10")).unwrap_err();
+/// assert_eq!(errors[0].line, 3);
+/// ```
+///
+/// Lines can be indented or have trailing whitespace (or end with `\r\n`):
+///
+/// ```
+/// use penplot::parsing;
+///
+/// let indented = parsing::parse_program(String::from("REP 4\n    WALK 10  \n\tTURN 90\r\nEND")).unwrap();
+/// let flush = parsing::parse_program(String::from("REP 4\nWALK 10\nTURN 90\nEND")).unwrap();
+/// assert_eq!(indented, flush);
+/// ```
+///
+/// `WHNZ R` and `WEND` blocks become jumps, past the `WEND` while the register is zero and back to the `WHNZ` at
+/// the `WEND`:
+///
+/// ```
+/// use penplot::instruction::Instruction;
+/// use penplot::parsing;
+///
+/// let loops = parsing::parse_program(String::from("WHNZ R0\nWHNZ R1\nSUB R1 R1 R2\nWEND\nSUB R0 R0 R2\nWEND")).unwrap();
+/// assert_eq!(loops[0], Instruction::JumpIfZero(0, 5));
+/// assert_eq!(loops[1], Instruction::JumpIfZero(1, 2));
+/// assert_eq!(loops[3], Instruction::Jump(-3));
+/// assert_eq!(loops[5], Instruction::Jump(-6));
+/// ```
+pub fn parse_program(text: String) -> Result<Vec<Instruction>, Vec<ParseError>> {
+    let text = strip_block_comments(&text).map_err(|error| vec![error])?;
+    let split = program_lines(&text);
+    // generate symbol table
+    let (symbol_table, mut errors) = build_symbol_table(&split);
     // parse instructions
     let mut program: Vec<Instruction> = vec![];
-    for string in split {
-        match parse_instruction(Some(&symbol_table), string) {
+    // blocks that haven't been closed yet, and the lines of the ones that have, with the lines that close them
+    let (mut open_blocks, mut blocks) = (vec![], vec![]);
+    for (pc, &(line_number, string)) in split.iter().enumerate() {
+        // a WHNZ or WEND starts out as a jump to itself, until the other end of its block is known
+        let (parsed, edge) = match parse_while_line(string) {
+            Ok((rest, Some(register))) => (Ok((rest, Instruction::JumpIfZero(register, -1))), Some(BlockEdge::Open(Block::While))),
+            Ok((rest, None)) => (Ok((rest, Instruction::Jump(-1))), Some(BlockEdge::Close(Block::While))),
+            Err(_) => {
+                let parsed = parse_instruction(Some(&symbol_table), pc, string);
+                let edge = match &parsed {
+                    Ok((_, Instruction::RepeatBlock(_, _))) => Some(BlockEdge::Open(Block::Repeat)),
+                    Ok((_, Instruction::EndRepeat)) => Some(BlockEdge::Close(Block::Repeat)),
+                    _ => None
+                };
+                (parsed, edge)
+            }
+        };
+        match edge {
+            Some(BlockEdge::Open(block)) => open_blocks.push((pc, block)),
+            // blocks can't overlap, so only the innermost open block can be closed
+            Some(BlockEdge::Close(block)) => match open_blocks.last() {
+                Some(&(start, open)) if open == block => {
+                    open_blocks.pop();
+                    blocks.push((start, pc));
+                }
+                _ => errors.push(ParseError { line: line_number, file: None, text: string.trim().to_string(), kind: block.unmatched() })
+            },
+            None => ()
+        }
+        match parsed {
             Ok((_, inst)) => program.push(inst),
-            Err(e) => {
-                println!("Error parsing code {:?}", e);
-                return None;
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => errors.push(ParseError {
+                line: line_number,
+                file: None,
+                text: string.trim().to_string(),
+                kind: match e.code {
+                    ErrorKind::TooLarge => ParseErrorKind::TooLarge,
+                    ErrorKind::Fail => ParseErrorKind::UndefinedLabel(e.input.chars().take_while(|c| c.is_alphabetic()).collect()),
+                    _ => ParseErrorKind::Unrecognized
+                }
+            }),
+            Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input")
+        }
+    }
+    for (start, block) in open_blocks {
+        let (line_number, string) = split[start];
+        errors.push(ParseError { line: line_number, file: None, text: string.trim().to_string(), kind: block.unclosed() });
+    }
+    if errors.is_empty() {
+        // with no errors, every line became an instruction, so lines and instructions line up
+        for (start, end) in blocks {
+            match &mut program[start] {
+                Instruction::RepeatBlock(block_end, _) => *block_end = end,
+                // jumps go to pc + offset + 1, so WHNZ goes to the line after its WEND, and WEND back to its WHNZ
+                Instruction::JumpIfZero(_, offset) => {
+                    *offset = (end - start) as isize;
+                    program[end] = Instruction::Jump(start as isize - end as isize - 1);
+                }
+                _ => ()
             }
         }
+        Ok(program)
+    } else {
+        errors.sort_by_key(|error| error.line);
+        Err(errors)
+    }
+}
+
+// a number, or the name of a rule's parameter
+fn parse_expr_operand(input: &str) -> IResult<&str, Expr> {
+    branch::alt((
+        combinator::map(parse_coordinate_value, Expr::Literal),
+        combinator::map(complete::alpha1, |name: &str| Expr::Parameter(name.to_string()))
+    ))(input)
+}
+
+// operands joined by any of the given operators, read left to right
+fn parse_expr_chain<'a>(
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+    operators: &'static str
+) -> impl FnMut(&'a str) -> IResult<&'a str, Expr> {
+    let operator = combinator::map(complete::one_of(operators), |c| match c {
+        '+' => Operator::Add,
+        '-' => Operator::Sub,
+        '*' => Operator::Mul,
+        _ => Operator::Div
+    });
+    let mut rest = multi::many0(sequence::pair(sequence::delimited(complete::space0, operator, complete::space0), operand));
+    move |input| {
+        let (input, first) = operand(input)?;
+        let (input, rest) = rest(input)?;
+        let expr = rest.into_iter().fold(first, |a, (op, b)| Expr::Binary(Box::new(a), op, Box::new(b)));
+        Ok((input, expr))
     }
-    Some(program)
+}
+
+fn parse_expr_term(input: &str) -> IResult<&str, Expr> {
+    parse_expr_chain(parse_expr_operand, "*/")(input)
+}
+
+// the parameter of a parametric symbol, like x*1.5 or x/2+1
+fn parse_expr(input: &str) -> IResult<&str, Expr> {
+    parse_expr_chain(parse_expr_term, "+-")(input)
+}
+
+// a symbol or instruction with a parameter, like <F>(x*1.5) or WALK(x), which only L systems use
+fn parse_parametric(input: &str) -> IResult<&str, Instruction> {
+    combinator::map(
+        sequence::pair(
+            branch::alt((
+                combinator::recognize(sequence::delimited(complete::char('<'), complete::anychar, complete::char('>'))),
+                combinator::verify(complete::alpha1, |name: &str| Instruction::with_parameter(name, Coordinate::ZERO).is_some())
+            )),
+            sequence::delimited(
+                sequence::pair(complete::char('('), complete::space0),
+                parse_expr,
+                sequence::pair(complete::space0, complete::char(')'))
+            )
+        ),
+        // symbols are case-sensitive, but mnemonics aren't
+        |(name, parameter): (&str, Expr)| {
+            let name = if name.starts_with('<') { name.to_string() } else { name.to_ascii_uppercase() };
+            Instruction::Parametric(name, parameter)
+        }
+    )(input)
+}
+
+// a lone character like F or + standing on its own, which means the same thing as <F> or <+>
+// characters that are part of how specs are written are left out, and [ and ] are already PUSH and POP
+fn parse_bare_symbol(input: &str) -> IResult<&str, Instruction> {
+    combinator::map(
+        sequence::terminated(
+            complete::satisfy(|c| !c.is_whitespace() && !"{}|<>()[];@".contains(c)),
+            combinator::peek(complete::multispace1)
+        ),
+        |c| Instruction::Comment(c.to_string())
+    )(input)
+}
+
+// anything that can be a symbol in an l system
+fn parse_l_system_symbol(input: &str) -> IResult<&str, Instruction> {
+    branch::alt((parse_instruction_symless, parse_bare_symbol))(input)
+}
+
+fn parse_l_system_instruction(input: &str) -> IResult<&str, Instruction> {
+    branch::alt((parse_parametric, parse_l_system_symbol))(input)
 }
 
 // this parses the big curly-brace delimited
 fn parse_l_system_value(input: &str) -> IResult<&str, Vec<Instruction>> {
     sequence::delimited(
         sequence::pair(complete::char('{'), complete::multispace1),
-        multi::many1(sequence::terminated(parse_instruction_symless, complete::multispace1)),
+        multi::many1(sequence::terminated(parse_l_system_instruction, complete::multispace1)),
         sequence::delimited(complete::multispace0, complete::char('}'), complete::multispace0)
     )(input)
 }
@@ -185,14 +1012,29 @@ fn parse_seed(input: &str) -> IResult<&str, Vec<Instruction>> {
     )(input)
 }
 
-fn parse_aliases(input: &str) -> IResult<&str, HashMap<Instruction, Vec<Instruction>>> {
+type Aliases = (HashMap<Instruction, Vec<Instruction>>, HashMap<String, ParametricRule>);
+
+fn parse_aliases(input: &str) -> IResult<&str, Aliases> {
+    // aliases are only applied once, at the end, so they can't be random
+    // and they're applied to each instruction on its own, so they can't have context either
+    let fixed_rule = combinator::map_opt(parse_rule, |(key, productions)| {
+        (productions.len() == 1 && !key.has_context()).then_some(RuleDefinition::Plain(key, productions))
+    });
+    let alias = branch::alt((parse_parametric_rule, fixed_rule));
     sequence::delimited(
         sequence::pair(tag_no_case("aliases"), complete::multispace1),
         sequence::delimited(
             sequence::pair(complete::char('{'), complete::multispace1),
-            multi::fold_many1(sequence::terminated(parse_rule, complete::multispace0), HashMap::new, |mut map, (inst, rule)| {
-                map.insert(inst, rule);
-                map
+            multi::fold_many1(sequence::terminated(alias, complete::multispace0), || (HashMap::new(), HashMap::new()), |(mut fixed, mut parametric), alias| {
+                match alias {
+                    RuleDefinition::Parametric(name, rule) => {
+                        parametric.insert(name, rule);
+                    }
+                    RuleDefinition::Plain(key, mut productions) => {
+                        fixed.insert(key.symbol, productions.remove(0).1);
+                    }
+                }
+                (fixed, parametric)
             }),
             sequence::delimited(complete::multispace0, complete::char('}'), complete::multispace0)
         ),
@@ -200,27 +1042,174 @@ fn parse_aliases(input: &str) -> IResult<&str, HashMap<Instruction, Vec<Instruct
     )(input)
 }
 
-fn parse_rule(input: &str) -> IResult<&str, (Instruction, Vec<Instruction>)> {
+// relative weight of a random production, like 3 or 0.25
+fn parse_weight(input: &str) -> IResult<&str, f32> {
+    combinator::map_res(
+        combinator::recognize(sequence::pair(complete::digit1, combinator::opt(sequence::pair(complete::char('.'), complete::digit1)))),
+        str::parse
+    )(input)
+}
+
+// the instruction a rule replaces, optionally with the neighbors it needs: "left < symbol > right"
+fn parse_rule_key(input: &str) -> IResult<&str, RuleKey> {
+    combinator::map(
+        sequence::tuple((
+            combinator::opt(sequence::terminated(
+                parse_l_system_symbol,
+                sequence::tuple((complete::space1, complete::char('<'), complete::space1))
+            )),
+            parse_l_system_symbol,
+            combinator::opt(sequence::preceded(
+                sequence::tuple((complete::space1, complete::char('>'), complete::space1)),
+                parse_l_system_symbol
+            ))
+        )),
+        |(left, symbol, right)| RuleKey { left, symbol, right }
+    )(input)
+}
+
+// a rule is one or more productions separated by |, each optionally followed by a weight (1 if left out)
+fn parse_rule(input: &str) -> IResult<&str, (RuleKey, Vec<Production>)> {
     sequence::separated_pair(
-        parse_instruction_symless,
+        parse_rule_key,
         complete::multispace1,
-        parse_l_system_value
+        multi::separated_list1(
+            sequence::pair(complete::char('|'), complete::multispace0),
+            combinator::map(
+                sequence::pair(parse_l_system_value, combinator::opt(sequence::terminated(parse_weight, complete::multispace0))),
+                |(production, weight)| (weight.unwrap_or(1.0), production)
+            )
+        )
+    )(input)
+}
+
+// the two kinds of rule, which are kept apart once they're parsed
+enum RuleDefinition {
+    Parametric(String, ParametricRule),
+    Plain(RuleKey, Vec<Production>)
+}
+
+// a rule for a parametric symbol, like <F>(x) { <F>(x*1.5) }
+// these always have a single production, which can only use the parameter the rule names
+fn parse_parametric_rule(input: &str) -> IResult<&str, RuleDefinition> {
+    combinator::map_opt(
+        sequence::separated_pair(parse_parametric, complete::multispace1, parse_l_system_value),
+        |(symbol, production)| match symbol {
+            Instruction::Parametric(name, Expr::Parameter(parameter)) => {
+                let bound = production.iter().all(|inst| match inst {
+                    Instruction::Parametric(_, expr) => expr.only_uses(&parameter),
+                    _ => true
+                });
+                bound.then_some(RuleDefinition::Parametric(name, ParametricRule { parameter, production }))
+            }
+            _ => None
+        }
     )(input)
 }
 
+/// Parses an L system spec. A character standing on its own, like `F` or `+`, is the same symbol as `<F>` or `<+>`,
+/// so grammars can be written the compact way and given meaning with aliases:
+///
+/// ```
+/// use penplot::instruction::{Coordinate, Instruction, Value};
+/// use penplot::parsing;
+///
+/// let spec = "seed {\n F\n}\n\naliases {\n F {\n WALK 1\n }\n + {\n TURN 90\n }\n}\n\nF {\n F\n +\n <F>\n}\n";
+/// let (_, l_system) = parsing::parse_l_system(spec).unwrap();
+/// let walk = Instruction::MoveForward(Value::Literal(Coordinate::from_num(1)));
+/// let turn = Instruction::Turn(Value::Literal(Coordinate::from_num(90)));
+/// assert_eq!(l_system.run(1), vec![walk.clone(), turn, walk]);
+/// ```
 pub fn parse_l_system(input: &str) -> IResult<&str, LSystem> {
     // get the parameters in sequence
     let (input, seed) = parse_seed(input)?;
     // there might be a cleaner way to do this, but the idea is to allow aliases to exist here, but accept if they don't
-    let (input, aliases) = match parse_aliases(input) {
-        Ok((input, aliases)) => (input, Some(aliases)),
-        Err(_) => (input, None)
+    let (input, (aliases, parametric_aliases)) = match parse_aliases(input) {
+        Ok((input, (aliases, parametric_aliases))) => (input, (Some(aliases), parametric_aliases)),
+        Err(_) => (input, (None, HashMap::new()))
     };
-    // then we parse the rules...
-    let (input, rules) = multi::fold_many1(parse_rule, HashMap::new, |mut map, (inst, rule)| {
-        map.insert(inst, rule);
-        map
+    // then we parse the rules, keeping the ones with a single production apart since they don't need any randomness...
+    // a rule that's defined again is replaced, whichever kind each definition is, so the last one always wins
+    let rule = branch::alt((parse_parametric_rule, combinator::map(parse_rule, |(key, productions)| RuleDefinition::Plain(key, productions))));
+    let empty = || (HashMap::new(), HashMap::new(), HashMap::new());
+    let (input, (rules, random_rules, parametric_rules)) = multi::fold_many1(rule, empty, |(mut rules, mut random_rules, mut parametric_rules), rule| {
+        match rule {
+            RuleDefinition::Parametric(name, rule) => {
+                parametric_rules.insert(name, rule);
+            }
+            RuleDefinition::Plain(key, mut productions) if productions.len() == 1 => {
+                random_rules.remove(&key);
+                rules.insert(key, productions.remove(0).1);
+            }
+            RuleDefinition::Plain(key, productions) => {
+                rules.remove(&key);
+                random_rules.insert(key, productions);
+            }
+        }
+        (rules, random_rules, parametric_rules)
     })(input)?;
     // and then we're done
-    Ok((input, LSystem { seed, rules, aliases }))
-}
\ No newline at end of file
+    Ok((input, LSystem { seed, rules, random_rules, parametric_rules, aliases, parametric_aliases, random_seed: 0 }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::StatsCanvas;
+    use crate::program_state::ProgramState;
+
+    #[test]
+    fn values_are_native_width() {
+        let above_u32 = u32::MAX as u128 + 1;
+        assert_eq!(parse_usize_value(&u32::MAX.to_string()), Ok(("", u32::MAX as usize)));
+        match usize::try_from(above_u32) {
+            Ok(value) => assert_eq!(parse_usize_value(&above_u32.to_string()), Ok(("", value))),
+            Err(_) => assert!(parse_usize_value(&above_u32.to_string()).is_err())
+        }
+        assert_eq!(parse_usize_value(&usize::MAX.to_string()), Ok(("", usize::MAX)));
+        assert_eq!(parse_isize_value(&isize::MIN.to_string()), Ok(("", isize::MIN)));
+    }
+
+    #[test]
+    fn values_that_dont_fit_are_too_large() {
+        let too_large = |text: String| matches!(parse_program(text).unwrap_err()[..],
+            [ParseError { kind: ParseErrorKind::TooLarge, .. }]);
+        assert!(too_large(format!("PENW {}", usize::MAX as u128 + 1)));
+        assert!(too_large(format!("JUMP {}", isize::MIN as i128 - 1)));
+    }
+
+    #[test]
+    fn the_biggest_jump_goes_past_the_end() {
+        // rather than overflowing when it's added to the program counter
+        let commands = parse_program(format!("RGB 255 0 0\nJUMP {}\nWALK 10", isize::MAX)).unwrap();
+        let mut program = ProgramState::new(StatsCanvas::new());
+        program.execute(&commands).unwrap();
+        assert_eq!(program.canvas().draw_distance(), 0.0);
+    }
+
+    #[test]
+    fn light_and_dark_keep_the_hue() {
+        let hsl = |text: &str| match parse_named_color("COLR")(text) {
+            Ok(("", Instruction::SetColor(color))) => color.to_hsl(),
+            other => panic!("expected a color, got {:?}", other)
+        };
+        let (hue, saturation, lightness) = hsl("COLR red");
+        let (light_hue, light_saturation, light_lightness) = hsl("COLR red light");
+        assert!(light_lightness > lightness + 0.1);
+        assert!((light_hue - hue).abs() < 1.0 && (light_saturation - saturation).abs() < 0.05);
+        let (dark_hue, _, dark_lightness) = hsl("COLR RED DARK");
+        assert!(dark_lightness < lightness - 0.1 && (dark_hue - hue).abs() < 1.0);
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(parse_radix_digits("0x1F"), Ok(("", (16, "1F"))));
+        assert_eq!(parse_radix_digits("0B1010"), Ok(("", (2, "1010"))));
+        assert_eq!(parse_radix_digits("42 R0"), Ok((" R0", (10, "42"))));
+        // a prefix with no digits after it is just a zero, followed by something that isn't part of the number
+        assert_eq!(parse_radix_digits("0b2"), Ok(("b2", (10, "0"))));
+        // the sign goes before the prefix
+        assert_eq!(parse_isize_value("-0x10"), Ok(("", -16)));
+        assert_eq!(parse_coordinate_value("0x10"), Ok(("", Coordinate::from_num(16))));
+    }
+}
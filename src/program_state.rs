@@ -1,124 +1,1043 @@
-use crate::canvas::{DrawingCanvas, SaveableCanvas};
-use crate::instruction::Instruction;
+use crate::canvas::{self, DrawingCanvas, SaveableCanvas, SizingCanvas};
+use crate::color::{BlendMode, Color};
+use crate::instruction::{AngleUnit, Coordinate, Instruction, Value, PALETTE_SIZE, REGISTER_COUNT};
+use crate::turtle::{Turtle, TurtleState};
+use crate::util::{self, Rng};
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::io::Result as IoResult;
+use std::mem;
+use std::rc::Rc;
+
+// color used to outline subroutine output when debugging bounds
+const DEBUG_BOUNDS_COLOR: Color = Color(255, 0, 255, 255);
+// longest chord used to draw an ARC, in pixels
+const ARC_SEGMENT_LENGTH: f32 = 2.0;
+// smallest turn between the chords of an ARC, in degrees, so a huge radius can't make an arc take forever
+// only arcs more than about 2000 pixels across get fewer chords because of this, and their corners are too shallow to see
+const ARC_SEGMENT_ANGLE: f32 = 0.1;
+
+// bounding box as (min_x, min_y, max_x, max_y)
+type Bounds = (f32, f32, f32, f32);
+
+// where a program stopped, so a later run can carry on from there
+#[derive(Clone, Debug)]
+pub struct ProgramSnapshot {
+    pub turtle: TurtleState,
+    pub hatch: Option<(f32, f32)>,
+    pub call_stack: Vec<CallFrame>,
+    pub pending_args: Vec<isize>,
+    pub turtle_stack: Vec<(f32, f32, f32, f32, f32)>,
+    pub registers: [isize; REGISTER_COUNT],
+    pub palette: [Option<Color>; PALETTE_SIZE],
+    pub rng_state: u64,
+    pub angle_unit: AngleUnit,
+    pub scale: f32,
+    pub handedness: f32,
+    pub blend_mode: BlendMode,
+    pub program_counter: usize
+}
+
+// a subroutine call or loop in progress
+// a call is a loop with no iterations remaining
+#[derive(Clone, Debug)]
+pub struct CallFrame {
+    pub return_pc: usize,
+    pub body_pc: usize,
+    // times the body still has to run after this one
+    pub remaining: usize,
+    // arguments pushed with PARG before the call, read with ARG
+    pub args: Vec<isize>
+}
+
+// where a program is after running one instruction
+#[derive(Clone, Copy, Debug)]
+pub struct StepOutcome {
+    // whether the program has finished (by running off the end or with HALT)
+    pub halted: bool,
+    pub program_counter: usize,
+    pub turtle: TurtleState
+}
+
+// why a program had to be stopped, and where (as a program counter, and as a place in the source to report)
+#[derive(Clone, Debug)]
+pub enum ExecutionError {
+    StepLimitExceeded { steps: usize, program_counter: usize, location: String },
+    CallStackOverflow { depth: usize, program_counter: usize, location: String },
+    // WLKD was given a spacing from a register that wasn't positive
+    InvalidSpacing { spacing: f32, program_counter: usize, location: String }
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ExecutionError::StepLimitExceeded { steps, location, .. } =>
+                write!(f, "stopped after {} steps, at {}", steps, location),
+            ExecutionError::CallStackOverflow { depth, location, .. } =>
+                write!(f, "stopped at {}, with {} calls already in progress", location, depth),
+            ExecutionError::InvalidSpacing { spacing, location, .. } =>
+                write!(f, "stopped at {}, since dots can't be spaced {} apart", location, spacing)
+        }
+    }
+}
 
 pub struct ProgramState<T: DrawingCanvas> {
-    pen_x: f32,
-    pen_y: f32,
-    heading: f32,
-    canvas: T,
+    turtle: Turtle<T>,
+    // vertices of the polygon being traced for a fill
+    fill_vertices: Option<Vec<(f32, f32)>>,
+    // vertices of the stencil polygon being traced, and whether the pen was down before tracing it
+    stencil_vertices: Option<(Vec<(f32, f32)>, bool)>,
+    // angle (in radians) and spacing of hatch lines, or None for solid fills
+    hatch: Option<(f32, f32)>,
     program_counter: usize,
     executing: bool,
-    call_stack: Vec<usize>,
+    call_stack: Vec<CallFrame>,
+    // arguments for the next CALL or LOOP
+    pending_args: Vec<isize>,
+    // positions, headings, scales and handedness saved by PUSH
+    turtle_stack: Vec<(f32, f32, f32, f32, f32)>,
+    registers: [isize; REGISTER_COUNT],
+    // colors set with PALD, or None for slots that haven't been
+    palette: [Option<Color>; PALETTE_SIZE],
+    // where RAND gets its numbers
+    rng: Rng,
+    // unit for the angles of FACE, TURN and ARC
+    angle_unit: AngleUnit,
+    // multiplier for relative distances, set by SCAL
+    scale: f32,
+    // 1, or -1 when MIRR has flipped which way turns go
+    handedness: f32,
+    // kept here as well as in the canvas, so it can be saved with the rest of the state
+    blend_mode: BlendMode,
+    debug_bounds: bool,
+    // bounds of what has been drawn in each call frame (only tracked when debugging bounds)
+    frame_bounds: Vec<Option<Bounds>>,
+    // layers to draw, or None to draw everything
+    layers: Option<HashSet<String>>,
+    // whether the current layer isn't being drawn
+    hidden: bool,
+    // most steps to run in one execute, or None to run until the program ends
+    step_limit: Option<usize>,
+    // the step limit while execute is running (stepping on its own has no limit), and the steps spent against it
+    budget: Option<usize>,
+    spent: usize,
+    // most calls and loops that can be in progress at once, or None for no limit
+    max_call_depth: Option<usize>,
+    // whether to log every instruction run to stderr
+    trace: bool,
+    // where each instruction is in the source (like "line 3"), by program counter, for reporting errors
+    source_lines: Option<Rc<[String]>>,
+    // what T reads as: how far through an animation this frame is, from 0 to 1
+    time: f32,
+    // whether the next execute carries on from a snapshot, rather than starting at the top of the program
+    resumed: bool,
+    // instructions run so far, across every execute and step
+    steps: usize,
 }
 
 impl<T: DrawingCanvas> ProgramState<T> {
     pub fn new(canvas: T) -> ProgramState<T> {
         ProgramState {
-            canvas,
-            pen_x: 0.0,
-            pen_y: 0.0,
-            heading: 0.0,
+            turtle: Turtle::new(canvas),
+            fill_vertices: None,
+            stencil_vertices: None,
+            hatch: None,
             program_counter: 0,
             executing: true,
             call_stack: vec![],
+            pending_args: vec![],
+            turtle_stack: vec![],
+            registers: [0; REGISTER_COUNT],
+            palette: [None; PALETTE_SIZE],
+            rng: Rng::new(0),
+            angle_unit: AngleUnit::Degrees,
+            scale: 1.0,
+            handedness: 1.0,
+            blend_mode: BlendMode::Normal,
+            debug_bounds: false,
+            frame_bounds: vec![],
+            layers: None,
+            hidden: false,
+            step_limit: None,
+            budget: None,
+            spent: 0,
+            max_call_depth: None,
+            trace: false,
+            source_lines: None,
+            time: 0.0,
+            resumed: false,
+            steps: 0,
         }
     }
 
-    pub fn execute(&mut self, commands: &[Instruction]) {
-        self.program_counter = 0;
+    // outline the drawn output of each subroutine call when it returns
+    pub fn with_debug_bounds(mut self, debug_bounds: bool) -> Self {
+        self.debug_bounds = debug_bounds;
+        self
+    }
+
+    /// Only draw instructions tagged with one of these layers (and anything before the first tag). Hidden layers
+    /// still move the turtle, so the layers that are drawn end up where they would with everything drawn.
+    ///
+    /// ```
+    /// use penplot::canvas::PixelCanvas;
+    /// use penplot::color::Color;
+    /// use penplot::parsing;
+    /// use penplot::program_state::ProgramState;
+    /// use std::collections::HashSet;
+    ///
+    /// let text = "RGB 255 255 255\n; @layer top\nWALK 9\n; @layer bottom\nMOVE 0 9\nWALK 9";
+    /// let commands = parsing::parse_program(String::from(text)).unwrap();
+    /// let render = |layers: Option<&[&str]>| {
+    ///     let layers = layers.map(|layers| layers.iter().map(|layer| layer.to_string()).collect::<HashSet<_>>());
+    ///     let mut program = ProgramState::new(PixelCanvas::new(10, 10, 0, 0)).with_layers(layers);
+    ///     program.execute(&commands).unwrap();
+    ///     let canvas = program.into_canvas();
+    ///     (canvas.pixel(5, 0) != Some(Color::transparent()), canvas.pixel(5, 9) != Some(Color::transparent()))
+    /// };
+    /// assert_eq!(render(None), (true, true));
+    /// assert_eq!(render(Some(&["bottom"])), (false, true));
+    /// assert_eq!(render(Some(&["top"])), (true, false));
+    /// ```
+    pub fn with_layers(mut self, layers: Option<HashSet<String>>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Stop programs that run too long (like ones stuck in a loop). Every instruction is a step, and instructions
+    /// that draw many lines or dots at once (`ARC`, `CIRC`, `DISC`, `POLY`, `WLKD`, `TEXT` and fills) cost a
+    /// step for each of those as well, so the limit bounds how much drawing a program does and not just how many
+    /// instructions it runs. A costly instruction is stopped before it draws anything if it would go over.
+    ///
+    /// ```
+    /// use penplot::canvas::StatsCanvas;
+    /// use penplot::parsing;
+    /// use penplot::program_state::{ExecutionError, ProgramState};
+    ///
+    /// let run = |text: &str| {
+    ///     let commands = parsing::parse_program(String::from(text)).unwrap();
+    ///     ProgramState::new(StatsCanvas::new()).with_step_limit(Some(1000)).execute(&commands)
+    /// };
+    /// // each of these circles is drawn as about 315 chords, so the fourth one goes over
+    /// let circles = "ARC 100 360\n".repeat(5);
+    /// assert!(matches!(run(&circles), Err(ExecutionError::StepLimitExceeded { program_counter: 3, .. })));
+    /// // while five ordinary instructions are nowhere near
+    /// assert!(run(&"WALK 100\n".repeat(5)).is_ok());
+    /// ```
+    pub fn with_step_limit(mut self, step_limit: Option<usize>) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    // stop programs that recurse too deeply
+    pub fn with_max_call_depth(mut self, max_call_depth: Option<usize>) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    // the same seed always gives the same RAND numbers, on any platform
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    // print each instruction to stderr as it's run, along with where it left the turtle
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Say where each instruction is in the source (by program counter) when reporting errors, since blank lines,
+    /// comments and `#include`s mean that isn't just the program counter. Without this, errors count instructions.
+    ///
+    /// ```
+    /// use penplot::canvas::StatsCanvas;
+    /// use penplot::parsing;
+    /// use penplot::program_state::ProgramState;
+    ///
+    /// let text = "/* walk in smaller and smaller steps */\nLOAD R0 10\n\nWLKD 20 R0 @ loop\nLOAD R1 5\nSUB R0 R0 R1\nJUMP loop";
+    /// let commands = parsing::parse_program(String::from(text)).unwrap();
+    /// let lines = parsing::parse_line_numbers(text).iter().map(|line| format!("line {}", line)).collect();
+    /// let error = |program: ProgramState<StatsCanvas>| program.with_step_limit(Some(100)).execute(&commands).unwrap_err().to_string();
+    /// // the third time around the loop, WLKD (the second instruction, on the fourth line) gets a spacing of 0
+    /// assert_eq!(error(ProgramState::new(StatsCanvas::new()).with_source_lines(Some(lines))), "stopped at line 4, since dots can't be spaced 0 apart");
+    /// assert_eq!(error(ProgramState::new(StatsCanvas::new())), "stopped at instruction 2, since dots can't be spaced 0 apart");
+    /// ```
+    pub fn with_source_lines(mut self, source_lines: Option<Rc<[String]>>) -> Self {
+        self.source_lines = source_lines;
+        self
+    }
+
+    /// Set what the `T` operand reads as, which is how far through an animation this frame is (from 0 to 1):
+    ///
+    /// ```
+    /// use penplot::canvas::SizingCanvas;
+    /// use penplot::parsing;
+    /// use penplot::program_state::ProgramState;
+    ///
+    /// // scaling by T grows the line from nothing at the start of the animation to 100 pixels at the end
+    /// let commands = parsing::parse_program(String::from("RGB 255 255 255\nSCAL T\nWALK 100")).unwrap();
+    /// let width = |time: f32| {
+    ///     let mut program = ProgramState::new(SizingCanvas::new()).with_time(time);
+    ///     program.execute(&commands).unwrap();
+    ///     program.canvas().dimensions().0
+    /// };
+    /// assert_eq!((width(0.0), width(0.5), width(1.0)), (1, 51, 101));
+    /// ```
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
+    }
+
+    // pick up where an earlier run left off, instead of starting at the top of the program
+    pub fn resumed_from(mut self, snapshot: &ProgramSnapshot) -> Self {
+        self.turtle.restore(&snapshot.turtle);
+        self.hatch = snapshot.hatch;
+        self.call_stack = snapshot.call_stack.clone();
+        self.pending_args = snapshot.pending_args.clone();
+        self.turtle_stack = snapshot.turtle_stack.clone();
+        self.registers = snapshot.registers;
+        self.palette = snapshot.palette;
+        self.rng = Rng::from_state(snapshot.rng_state);
+        self.angle_unit = snapshot.angle_unit;
+        self.scale = snapshot.scale;
+        self.handedness = snapshot.handedness;
+        self.blend_mode = snapshot.blend_mode;
+        self.turtle.canvas_mut().set_blend_mode(self.blend_mode);
+        self.program_counter = snapshot.program_counter;
+        self.resumed = true;
+        self
+    }
+
+    pub fn snapshot(&self) -> ProgramSnapshot {
+        ProgramSnapshot {
+            turtle: self.turtle.state(),
+            hatch: self.hatch,
+            call_stack: self.call_stack.clone(),
+            pending_args: self.pending_args.clone(),
+            turtle_stack: self.turtle_stack.clone(),
+            registers: self.registers,
+            palette: self.palette,
+            rng_state: self.rng.state(),
+            angle_unit: self.angle_unit,
+            scale: self.scale,
+            handedness: self.handedness,
+            blend_mode: self.blend_mode,
+            program_counter: self.program_counter
+        }
+    }
+
+    /// Run a program from the top, or from where the snapshot it was resumed from left off. If the step limit is
+    /// hit, the program counter is left at the next instruction, so a snapshot taken then can be resumed.
+    ///
+    /// ```
+    /// use penplot::canvas::StatsCanvas;
+    /// use penplot::parsing;
+    /// use penplot::program_state::ProgramState;
+    ///
+    /// let commands = parsing::parse_program(String::from("RGB 255 255 255\nPEND\nWALK 10\nHALT\nWALK 5")).unwrap();
+    /// let mut program = ProgramState::new(StatsCanvas::new());
+    /// program.execute(&commands).unwrap();
+    /// // running it again starts over at the top, rather than carrying on after the HALT
+    /// program.execute(&commands).unwrap();
+    /// assert_eq!(program.canvas().draw_distance(), 20.0);
+    /// // but a resumed program carries on from its snapshot, once
+    /// let mut resumed = ProgramState::new(StatsCanvas::new()).resumed_from(&program.snapshot());
+    /// resumed.execute(&commands).unwrap();
+    /// assert_eq!(resumed.canvas().draw_distance(), 5.0);
+    /// resumed.execute(&commands).unwrap();
+    /// assert_eq!(resumed.canvas().draw_distance(), 15.0);
+    /// ```
+    pub fn execute(&mut self, commands: &[Instruction]) -> Result<(), ExecutionError> {
+        if !mem::take(&mut self.resumed) {
+            self.program_counter = 0;
+        }
         self.executing = true;
-        while self.executing {
-            self.program_counter = match commands.get(self.program_counter) {
-                Some(command) => self.exec_instruction(command),
-                None => break,
+        self.budget = self.step_limit;
+        self.spent = 0;
+        let mut result = Ok(());
+        while result.is_ok() && self.executing && self.program_counter < commands.len() {
+            result = self.step(commands).map(|_| ());
+        }
+        self.budget = None;
+        result
+    }
+
+    // count steps against the step limit, stopping at the current instruction if there aren't enough left
+    fn spend(&mut self, steps: usize) -> Result<(), ExecutionError> {
+        if self.budget.is_some_and(|budget| self.spent.saturating_add(steps) > budget) {
+            return Err(ExecutionError::StepLimitExceeded { steps: self.spent, program_counter: self.program_counter, location: self.location() });
+        }
+        self.spent = self.spent.saturating_add(steps);
+        Ok(())
+    }
+
+    // run a single instruction (or nothing, if the program has finished)
+    // the step limit only applies to execute, since whoever is stepping decides when to stop
+    pub fn step(&mut self, commands: &[Instruction]) -> Result<StepOutcome, ExecutionError> {
+        if self.executing {
+            if let Some(command) = commands.get(self.program_counter) {
+                let program_counter = self.program_counter;
+                self.spend(1)?;
+                self.program_counter = self.exec_instruction(command)?;
+                self.steps += 1;
+                if self.trace {
+                    self.trace_instruction(program_counter, command);
+                }
             }
         }
+        Ok(StepOutcome {
+            halted: !self.executing || self.program_counter >= commands.len(),
+            program_counter: self.program_counter,
+            turtle: self.turtle.state()
+        })
+    }
+
+    // where the instruction about to run is in the source, or which instruction it is if that isn't known
+    pub fn location(&self) -> String {
+        match self.source_lines.as_ref().and_then(|lines| lines.get(self.program_counter)) {
+            Some(line) => line.clone(),
+            None => format!("instruction {}", self.program_counter + 1)
+        }
+    }
+
+    fn trace_instruction(&self, program_counter: usize, command: &Instruction) {
+        let (x, y) = self.turtle.position();
+        let heading = self.turtle.state().heading.to_degrees().rem_euclid(360.0);
+        eprintln!("pc={} {} -> pen({:.1}, {:.1}) heading={} next={} depth={}",
+            program_counter, command, x, y, (heading * 10.0).round() / 10.0, self.program_counter, self.call_stack.len());
+    }
+
+    // read an operand, from its register if it names one
+    fn coordinate(&self, value: &Value<Coordinate>) -> f32 {
+        match value {
+            Value::Literal(value) => value.to_num(),
+            Value::Register(register) => self.registers[*register] as f32,
+            Value::Time => self.time
+        }
+    }
+
+    // an angle in degrees, whatever unit it was given in
+    fn angle(&self, value: &Value<Coordinate>) -> f32 {
+        self.angle_unit.to_degrees(self.coordinate(value))
+    }
+
+    // where a relative jump by offset goes (pc + offset + 1, but never before the start, and past the end if that overflows)
+    fn jump_target(&self, offset: isize) -> usize {
+        match (self.program_counter as isize).checked_add(offset).and_then(|new_pc| new_pc.checked_add(1)) {
+            Some(new_pc) if new_pc < 0 => 0,
+            Some(new_pc) => new_pc as usize,
+            None => usize::MAX
+        }
+    }
+
+    // move the turtle, keeping track of where it went for bounds and fills
+    fn track_move<F: FnOnce(&mut Turtle<T>)>(&mut self, movement: F) {
+        // hidden layers still move the turtle, just with the pen up
+        let lifted = self.hidden && self.turtle.is_pen_down();
+        if lifted {
+            self.turtle.pen_up();
+        }
+        let (start_x, start_y) = self.turtle.position();
+        movement(&mut self.turtle);
+        let (end_x, end_y) = self.turtle.position();
+        let drawing = self.turtle.is_drawing();
+        if lifted {
+            self.turtle.pen_down();
+        }
+        if drawing {
+            self.extend_frame_bounds(start_x, start_y);
+            self.extend_frame_bounds(end_x, end_y);
+        }
+        if let Some(vertices) = &mut self.fill_vertices {
+            vertices.push((end_x, end_y));
+        }
+        if let Some((vertices, _)) = &mut self.stencil_vertices {
+            vertices.push((end_x, end_y));
+        }
+    }
+
+    // fill a polygon with parallel lines: one unit apart for a solid fill, or further apart for hatching
+    // the lines are ordinary pen strokes, so this works the same on every canvas; a solid fill draws them one pixel
+    // wide so it doesn't bleed past the outline, while hatching keeps the pen width
+    fn fill_polygon(&mut self, vertices: &[(f32, f32)]) -> Result<(), ExecutionError> {
+        if vertices.len() < 3 || !self.turtle.is_drawing() || self.hidden {
+            return Ok(());
+        }
+        let (angle, spacing) = self.hatch.unwrap_or((0.0, 1.0));
+        let (sin, cos) = angle.sin_cos();
+        // rotate the polygon so the fill lines run along u, with v increasing between lines
+        let rotated: Vec<(f32, f32)> = vertices.iter().map(|&(x, y)| (x * cos + y * sin, y * cos - x * sin)).collect();
+        let min_v = rotated.iter().map(|&(_, v)| v).fold(f32::INFINITY, f32::min);
+        let max_v = rotated.iter().map(|&(_, v)| v).fold(f32::NEG_INFINITY, f32::max);
+        let mut v = (min_v / spacing).ceil() * spacing;
+        // a step for each line across the polygon
+        self.spend(((max_v - v) / spacing).floor().max(0.0) as usize + 1)?;
+        let (pen_x, pen_y) = self.turtle.position();
+        let state = self.turtle.state();
+        if self.hatch.is_none() {
+            self.turtle.set_pen_width(1);
+        }
+        while v <= max_v {
+            // the inside of the polygon is between pairs of crossings
+            for span in util::scanline_crossings(&rotated, v).chunks_exact(2) {
+                let (start_x, start_y) = (span[0] * cos - v * sin, span[0] * sin + v * cos);
+                let (end_x, end_y) = (span[1] * cos - v * sin, span[1] * sin + v * cos);
+                self.turtle.travel_to(start_x, start_y);
+                self.turtle.goto(end_x, end_y);
+                self.extend_frame_bounds(start_x, start_y);
+                self.extend_frame_bounds(end_x, end_y);
+            }
+            v += spacing;
+        }
+        self.turtle.travel_to(pen_x, pen_y);
+        if self.hatch.is_none() {
+            self.turtle.set_pen_width(state.pen_width);
+            self.turtle.set_dynamic_width(state.dynamic_width);
+        }
+        Ok(())
+    }
+
+    // record a drawn point in the current call frame's bounds
+    fn extend_frame_bounds(&mut self, x: f32, y: f32) {
+        if let Some(bounds) = self.frame_bounds.last_mut() {
+            *bounds = Some(match bounds {
+                Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                None => (x, y, x, y)
+            });
+        }
+    }
+
+    fn push_frame_bounds(&mut self) {
+        if self.debug_bounds {
+            self.frame_bounds.push(None);
+        }
+    }
+
+    // close the current call frame, outlining what it drew, and return its bounds
+    fn pop_frame_bounds(&mut self) -> Option<Bounds> {
+        let bounds = self.frame_bounds.pop().flatten();
+        if let Some((min_x, min_y, max_x, max_y)) = bounds {
+            // the caller's bounds include everything its subroutines drew
+            self.extend_frame_bounds(min_x, min_y);
+            self.extend_frame_bounds(max_x, max_y);
+            // travel to the box with the pen up, draw it, then come back
+            let (pen_x, pen_y) = self.turtle.position();
+            let (color, pen_down) = (self.turtle.color(), self.turtle.is_pen_down());
+            self.turtle.travel_to(min_x, min_y);
+            self.turtle.set_color(DEBUG_BOUNDS_COLOR);
+            self.turtle.pen_down();
+            self.turtle.goto(max_x, min_y);
+            self.turtle.goto(max_x, max_y);
+            self.turtle.goto(min_x, max_y);
+            self.turtle.goto(min_x, min_y);
+            self.turtle.travel_to(pen_x, pen_y);
+            self.turtle.set_color(color);
+            if !pen_down {
+                self.turtle.pen_up();
+            }
+        }
+        bounds
+    }
+
+    // start a call or loop, unless that would go past the maximum depth
+    fn push_call_frame(&mut self, frame: CallFrame) -> Result<(), ExecutionError> {
+        let depth = self.call_stack.len();
+        if self.max_call_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return Err(ExecutionError::CallStackOverflow { depth, program_counter: self.program_counter, location: self.location() });
+        }
+        self.call_stack.push(frame);
+        self.push_frame_bounds();
+        Ok(())
     }
 
     // returns new program counter
-    fn exec_instruction(&mut self, command: &Instruction) -> usize {
+    fn exec_instruction(&mut self, command: &Instruction) -> Result<usize, ExecutionError> {
         let new_pc: Option<usize> = match command {
             Instruction::Noop => None,
             Instruction::Move(x, y) => {
-                let (x, y) = (*x as f32, *y as f32);
-                self.canvas.move_pen_to(x, y);
-                self.pen_x = x;
-                self.pen_y = y;
+                let (x, y) = (self.coordinate(x), self.coordinate(y));
+                self.track_move(|turtle| turtle.goto(x, y));
+                None
+            }
+            Instruction::LineTo(x, y) => {
+                let (x, y) = (self.coordinate(x), self.coordinate(y));
+                self.track_move(|turtle| turtle.line_to(x, y));
                 None
             }
             Instruction::MoveRel(dx, dy) => {
-                let (dx, dy) = (*dx as f32, *dy as f32);
-                self.canvas.move_pen_to(self.pen_x + dx, self.pen_y + dy);
-                self.pen_x += dx;
-                self.pen_y += dy;
+                let (dx, dy) = (self.coordinate(dx) * self.scale, self.coordinate(dy) * self.scale);
+                self.track_move(|turtle| turtle.shift(dx, dy));
                 None
             }
             Instruction::MoveForward(dist) => {
-                let dist = *dist as f32;
-                let dx = dist * self.heading.cos();
-                let dy = dist * self.heading.sin();
-                self.canvas.move_pen_to(self.pen_x + dx, self.pen_y + dy);
-                self.pen_x += dx;
-                self.pen_y += dy;
+                let dist = self.coordinate(dist) * self.scale;
+                self.track_move(|turtle| turtle.forward(dist));
+                None
+            }
+            Instruction::WalkDots(dist, spacing) => {
+                let (dist, spacing) = (self.coordinate(dist), self.coordinate(spacing));
+                if spacing.is_nan() || spacing <= 0.0 {
+                    return Err(ExecutionError::InvalidSpacing { spacing, program_counter: self.program_counter, location: self.location() });
+                }
+                // the dots are counted before scaling, so scaling them doesn't change how many there are
+                let dots = (dist.abs() / spacing).floor() as usize;
+                self.spend(dots)?;
+                let step = spacing * dist.signum() * self.scale;
+                let (start_x, start_y) = self.turtle.position();
+                let heading = self.turtle.state().heading;
+                let point = |distance: f32| (start_x + distance * heading.cos(), start_y + distance * heading.sin());
+                let (end_x, end_y) = point(dist * self.scale);
+                self.track_move(|turtle| {
+                    // the first dot is one space ahead, so a run of these doesn't put two dots in the same place
+                    for dot in 1..=dots {
+                        let (x, y) = point(step * dot as f32);
+                        turtle.travel_to(x, y);
+                        turtle.blot();
+                    }
+                    turtle.travel_to(end_x, end_y);
+                });
+                None
+            }
+            Instruction::Home => {
+                // the pen is lifted on the way, so this doesn't draw anything (or count towards the bounds)
+                self.turtle.travel_to(0.0, 0.0);
+                self.turtle.face(0.0);
                 None
             }
             Instruction::Face(theta) => {
-                self.heading = (*theta as f32).to_radians();
+                let theta = self.angle(theta) * self.handedness;
+                self.turtle.face(theta);
+                None
+            }
+            Instruction::FaceToward(x, y) => {
+                // the point is where it is, whatever MIRR says, so this isn't mirrored
+                let (x, y) = (self.coordinate(x), self.coordinate(y));
+                self.turtle.face_toward(x, y);
                 None
             }
             Instruction::Turn(theta) => {
-                self.heading += (*theta as f32).to_radians();
+                let theta = self.angle(theta) * self.handedness;
+                self.turtle.turn(theta);
+                None
+            }
+            Instruction::AngleMode(unit) => {
+                self.angle_unit = *unit;
+                None
+            }
+            Instruction::Scale(factor) => {
+                self.scale = self.coordinate(factor);
+                None
+            }
+            Instruction::Mirror => {
+                self.handedness = -self.handedness;
+                None
+            }
+            Instruction::Arc(radius, theta) => {
+                let (radius, theta) = (self.coordinate(radius) * self.scale, self.angle(theta) * self.handedness);
+                // the arc is drawn as chords, short enough that it looks smooth however big it is
+                let segments = ((radius.abs() * theta.to_radians().abs()) / ARC_SEGMENT_LENGTH)
+                    .min(theta.abs() / ARC_SEGMENT_ANGLE).ceil().max(1.0);
+                self.spend(segments as usize)?;
+                let step = theta / segments;
+                let chord = 2.0 * radius * (step.to_radians() / 2.0).sin().abs();
+                for _ in 0..segments as usize {
+                    // each chord points halfway between the headings at its ends
+                    self.turtle.turn(step / 2.0);
+                    self.track_move(|turtle| turtle.forward(chord));
+                    self.turtle.turn(step / 2.0);
+                }
                 None
             }
             Instruction::SetColor(color) => {
-                self.canvas.set_color(*color);
+                self.turtle.set_color(*color);
+                None
+            }
+            Instruction::BlendMode(mode) => {
+                self.blend_mode = *mode;
+                self.turtle.canvas_mut().set_blend_mode(*mode);
+                None
+            }
+            Instruction::DefinePalette(index, color) => {
+                self.palette[*index] = Some(*color);
+                None
+            }
+            Instruction::UsePalette(index) => {
+                // an index from a register can be anything, and slots past the end (or never defined) leave the color alone
+                let index = match index {
+                    Value::Literal(index) => Some(*index),
+                    Value::Register(register) => usize::try_from(self.registers[*register]).ok(),
+                    Value::Time => None
+                };
+                if let Some(color) = index.and_then(|index| self.palette.get(index).copied().flatten()) {
+                    self.turtle.set_color(color);
+                }
+                None
+            }
+            Instruction::AdjustColor(dr, dg, db, da) => {
+                let color = self.turtle.color().adjust(*dr, *dg, *db, *da);
+                self.turtle.set_color(color);
+                None
+            }
+            Instruction::Lerp(from, to, steps, step) => {
+                self.turtle.set_color(Color::lerp(*from, *to, *step, *steps));
                 None
             }
             Instruction::Blot => {
-                self.canvas.blot(self.pen_x, self.pen_y);
+                if !self.hidden {
+                    self.turtle.blot();
+                    if self.turtle.is_drawing() {
+                        let (pen_x, pen_y) = self.turtle.position();
+                        self.extend_frame_bounds(pen_x, pen_y);
+                    }
+                }
                 None
             }
-            Instruction::Comment(_) => None,
-            Instruction::Goto(pc) => Some(*pc),
-            Instruction::Jump(i) => {
-                let new_pc = self.program_counter as isize + *i + 1;
-                if new_pc < 0 {
-                    Some(0)
+            Instruction::PenWidth(width) => {
+                self.turtle.set_pen_width(*width);
+                None
+            }
+            Instruction::DynamicWidth(min, max) => {
+                self.turtle.set_dynamic_width(Some((*min, *max)));
+                None
+            }
+            Instruction::BeginFill => {
+                self.fill_vertices = Some(vec![self.turtle.position()]);
+                None
+            }
+            Instruction::EndFill => {
+                if let Some(vertices) = self.fill_vertices.take() {
+                    self.fill_polygon(&vertices)?;
+                }
+                None
+            }
+            Instruction::PenUp | Instruction::PenDown => {
+                let pen_down = matches!(command, Instruction::PenDown);
+                // while tracing a stencil the pen stays up, and this is where it goes once the stencil is done
+                if let Some((_, stencil_pen_down)) = &mut self.stencil_vertices {
+                    *stencil_pen_down = pen_down;
+                } else if pen_down {
+                    self.turtle.pen_down();
                 } else {
-                    Some(new_pc as usize)
+                    self.turtle.pen_up();
+                }
+                None
+            }
+            Instruction::Fill => {
+                if !self.hidden {
+                    self.turtle.fill();
                 }
+                None
+            }
+            Instruction::Circle(radius) | Instruction::Disc(radius) => {
+                if !self.hidden {
+                    let radius = (self.coordinate(radius) * self.scale).abs();
+                    // costs as much as drawing it with ARC would
+                    self.spend((std::f32::consts::TAU * radius / ARC_SEGMENT_LENGTH).min(360.0 / ARC_SEGMENT_ANGLE).ceil() as usize)?;
+                    self.turtle.circle(radius, matches!(command, Instruction::Disc(_)));
+                    if self.turtle.is_drawing() {
+                        let (pen_x, pen_y) = self.turtle.position();
+                        self.extend_frame_bounds(pen_x - radius, pen_y - radius);
+                        self.extend_frame_bounds(pen_x + radius, pen_y + radius);
+                    }
+                }
+                None
+            }
+            Instruction::Polygon(sides, radius) => {
+                // like a circle, the polygon is drawn around the turtle without moving it
+                if !self.hidden && self.turtle.is_drawing() {
+                    self.spend(*sides)?;
+                    let radius = self.coordinate(radius) * self.scale;
+                    let (center_x, center_y) = self.turtle.position();
+                    let heading = self.turtle.state().heading;
+                    // the first corner is straight ahead, and the last is the first again to close the outline
+                    let corners: Vec<(f32, f32)> = (0..=*sides).map(|corner| {
+                        let angle = heading + std::f32::consts::TAU * corner as f32 / *sides as f32;
+                        (center_x + radius * angle.cos(), center_y + radius * angle.sin())
+                    }).collect();
+                    self.turtle.travel_to(corners[0].0, corners[0].1);
+                    for &(x, y) in &corners[1..] {
+                        self.turtle.goto(x, y);
+                        self.extend_frame_bounds(x, y);
+                    }
+                    self.turtle.travel_to(center_x, center_y);
+                }
+                None
+            }
+            Instruction::Text(text) => {
+                if !self.hidden {
+                    self.spend(text.chars().count())?;
+                    self.turtle.text(text);
+                    let (width, height) = canvas::text_size(text);
+                    if self.turtle.is_drawing() && width > 0 {
+                        let (pen_x, pen_y) = self.turtle.position();
+                        self.extend_frame_bounds(pen_x, pen_y);
+                        self.extend_frame_bounds(pen_x + width as f32 - 1.0, pen_y + height as f32 - 1.0);
+                    }
+                }
+                None
+            }
+            Instruction::BeginStencil => {
+                // the stencil outline is traced with the pen up
+                self.stencil_vertices = Some((vec![self.turtle.position()], self.turtle.is_pen_down()));
+                self.turtle.pen_up();
+                None
+            }
+            Instruction::EndStencil => {
+                if let Some((vertices, pen_down)) = self.stencil_vertices.take() {
+                    if pen_down {
+                        self.turtle.pen_down();
+                    }
+                    self.turtle.canvas_mut().set_stencil(Some(&vertices));
+                }
+                None
+            }
+            Instruction::ClearStencil => {
+                self.turtle.canvas_mut().set_stencil(None);
+                None
+            }
+            Instruction::Push => {
+                let state = self.turtle.state();
+                self.turtle_stack.push((state.x, state.y, state.heading, self.scale, self.handedness));
+                None
+            }
+            Instruction::Pop => {
+                // popping an empty stack does nothing
+                if let Some((x, y, heading, scale, handedness)) = self.turtle_stack.pop() {
+                    self.scale = scale;
+                    self.handedness = handedness;
+                    // restoring jumps back with the pen up
+                    self.track_move(|turtle| turtle.restore(&TurtleState { x, y, heading, ..turtle.state() }));
+                }
+                None
+            }
+            Instruction::Hatch(angle, spacing) => {
+                // a spacing of 0 goes back to solid fills
+                self.hatch = match spacing {
+                    0 => None,
+                    _ => Some(((*angle as f32).to_radians(), *spacing as f32))
+                };
+                None
+            }
+            Instruction::Comment(_) | Instruction::Parametric(_, _) => None,
+            Instruction::Layer(name) => {
+                self.hidden = self.layers.as_ref().is_some_and(|layers| !layers.contains(name));
+                None
             }
+            Instruction::Goto(pc) => Some(*pc),
+            Instruction::Jump(i) => Some(self.jump_target(*i)),
+            Instruction::JumpIfZero(register, i) => (self.registers[*register] == 0).then(|| self.jump_target(*i)),
+            Instruction::JumpIfNotZero(register, i) => (self.registers[*register] != 0).then(|| self.jump_target(*i)),
             Instruction::Call(pc) => {
-                self.call_stack.push(self.program_counter + 1);
+                let args = std::mem::take(&mut self.pending_args);
+                self.push_call_frame(CallFrame { return_pc: self.program_counter + 1, body_pc: *pc, remaining: 0, args })?;
                 Some(*pc)
             }
-            Instruction::Return => self.call_stack.pop(),
-            Instruction::Repeat(pc, n) => {
-                let pc = *pc;
-                self.call_stack.push(self.program_counter + 1);
-                for _ in 0..(*n - 1) {
-                    self.call_stack.push(pc);
+            Instruction::Return | Instruction::EndRepeat => match self.call_stack.last_mut() {
+                Some(frame) if frame.remaining > 0 => {
+                    // go around the loop again
+                    frame.remaining -= 1;
+                    let body_pc = frame.body_pc;
+                    // each iteration's bounds include the iterations before it
+                    let bounds = self.pop_frame_bounds();
+                    if self.debug_bounds {
+                        self.frame_bounds.push(bounds);
+                    }
+                    Some(body_pc)
                 }
-                Some(pc)
+                Some(_) => {
+                    let frame = self.call_stack.pop();
+                    self.pop_frame_bounds();
+                    frame.map(|frame| frame.return_pc)
+                }
+                None => None
+            },
+            // looping zero times skips the body entirely
+            Instruction::Repeat(_, 0) => {
+                self.pending_args.clear();
+                None
+            }
+            Instruction::Repeat(pc, n) => {
+                // every time around the loop sees the same arguments
+                let args = std::mem::take(&mut self.pending_args);
+                self.push_call_frame(CallFrame { return_pc: self.program_counter + 1, body_pc: *pc, remaining: *n - 1, args })?;
+                Some(*pc)
+            }
+            Instruction::RepeatBlock(end, 0) => Some(*end + 1),
+            Instruction::RepeatBlock(end, n) => {
+                // the block is still part of whatever call it's in, so it can read that call's arguments
+                let args = self.call_stack.last().map(|frame| frame.args.clone()).unwrap_or_default();
+                self.push_call_frame(CallFrame { return_pc: *end + 1, body_pc: self.program_counter + 1, remaining: *n - 1, args })?;
+                None
+            }
+            Instruction::Load(register, value) => {
+                self.registers[*register] = *value;
+                None
+            }
+            Instruction::PushArg(value) => {
+                self.pending_args.push(*value);
+                None
+            }
+            Instruction::Arg(register, index) => {
+                // arguments that weren't passed (or reading them outside a call) give 0
+                self.registers[*register] = self.call_stack.last().and_then(|frame| frame.args.get(*index)).copied().unwrap_or(0);
+                None
+            }
+            // arithmetic wraps around instead of overflowing
+            Instruction::Add(register, a, b) => {
+                self.registers[*register] = self.registers[*a].wrapping_add(self.registers[*b]);
+                None
+            }
+            Instruction::Sub(register, a, b) => {
+                self.registers[*register] = self.registers[*a].wrapping_sub(self.registers[*b]);
+                None
+            }
+            Instruction::Mul(register, a, b) => {
+                self.registers[*register] = self.registers[*a].wrapping_mul(self.registers[*b]);
+                None
+            }
+            Instruction::Random(register, lo, hi) => {
+                self.registers[*register] = self.rng.next_in_range(*lo, *hi);
+                None
             }
             Instruction::Halt => {
+                // leave the program counter after the HALT, so resuming carries on past it
                 self.executing = false;
                 None
             }
         };
-        match new_pc {
+        Ok(match new_pc {
             None => self.program_counter + 1,
             Some(pc) => pc,
-        }
+        })
+    }
+
+    // how many instructions have been run, for reporting
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    // look at the canvas without finishing the program, e.g. between steps or after a sizing run
+    pub fn canvas(&self) -> &T {
+        self.turtle.canvas()
     }
 
-    pub fn canvas(self) -> T {
-        self.canvas
+    // take the canvas once the program is done with it, e.g. to keep a rendered image in memory
+    pub fn into_canvas(self) -> T {
+        self.turtle.into_canvas()
     }
 }
 
 impl<T: DrawingCanvas + SaveableCanvas> ProgramState<T> {
-    pub fn save_canvas(&self, filename: &str) {
-        self.canvas.save(filename);
+    pub fn save_canvas(&self, filename: &str) -> IoResult<()> {
+        self.turtle.canvas().save(filename)
+    }
+}
+
+impl ProgramState<SizingCanvas> {
+    // run a program just to see how big a canvas its drawing needs, as (width, height, x_offset, y_offset)
+    // the canvas carries on from wherever it was, so measuring again fits both runs (e.g. every frame of an animation)
+    pub fn measure(&mut self, commands: &[Instruction]) -> Result<(usize, usize, isize, isize), ExecutionError> {
+        self.execute(commands)?;
+        let canvas = self.canvas();
+        let ((width, height), (x_offset, y_offset)) = (canvas.dimensions(), canvas.offsets());
+        Ok((width, height, x_offset, y_offset))
+    }
+}
+
+/// Work out how big a canvas has to be to fit everything a program draws, as `(width, height, x_offset, y_offset)`,
+/// ready to pass to `PixelCanvas::new`. This runs the whole program on a `SizingCanvas`, stopping with
+/// `StepLimitExceeded` if it takes more than `step_limit` steps, so a program that never ends can't hang the caller.
+///
+/// ```
+/// use penplot::canvas::PixelCanvas;
+/// use penplot::parsing;
+/// use penplot::program_state::{self, ExecutionError, ProgramState};
+///
+/// let commands = parsing::parse_program(String::from("RGB 255 0 0\nMOVE -10 0\nMOVE -10 5")).unwrap();
+/// let (width, height, x_offset, y_offset) = program_state::measure(&commands, Some(1000)).unwrap();
+/// assert_eq!((width, height, x_offset, y_offset), (11, 6, 10, 0));
+/// let mut program = ProgramState::new(PixelCanvas::new(width, height, x_offset, y_offset));
+/// program.execute(&commands).unwrap();
+///
+/// let forever = parsing::parse_program(String::from("WALK 1 @ loop\nGOTO loop")).unwrap();
+/// assert!(matches!(program_state::measure(&forever, Some(1000)), Err(ExecutionError::StepLimitExceeded { .. })));
+/// ```
+pub fn measure(commands: &[Instruction], step_limit: Option<usize>) -> Result<(usize, usize, isize, isize), ExecutionError> {
+    ProgramState::new(SizingCanvas::new()).with_step_limit(step_limit).measure(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::{PixelCanvas, StatsCanvas};
+    use crate::parsing;
+
+    fn parse(text: &str) -> Vec<Instruction> {
+        parsing::parse_program(String::from(text)).unwrap()
+    }
+
+    #[test]
+    fn jnz_loops_to_a_label_or_an_offset() {
+        // count R0 down from 4, drawing a side of the square each time, until JNZ falls through
+        let with_label = "RGB 255 255 255\nLOAD R0 4\nLOAD R1 1\nWALK 10 @ side\nTURN 90\nSUB R0 R0 R1\nJNZ R0 side";
+        let with_offset = with_label.replace("JNZ R0 side", "JNZ R0 -5");
+        for text in [with_label, &with_offset] {
+            let commands = parse(text);
+            let mut program = ProgramState::new(StatsCanvas::new());
+            program.execute(&commands).unwrap();
+            assert_eq!(program.canvas().draw_distance(), 40.0);
+            assert_eq!(program.snapshot().registers[0], 0);
+            let mut program = ProgramState::new(PixelCanvas::new(11, 11, 0, 0));
+            program.execute(&commands).unwrap();
+            let canvas = program.canvas();
+            for (x, y) in [(0, 0), (10, 0), (10, 10), (0, 10), (5, 0), (10, 5), (5, 10), (0, 5)] {
+                assert_eq!(canvas.pixel(x, y), Some(Color(255, 255, 255, 255)));
+            }
+            assert_eq!(canvas.pixel(5, 5), Some(Color::transparent()));
+        }
+    }
+
+    #[test]
+    fn huge_arcs_have_a_chord_per_tenth_of_a_degree() {
+        let commands = parse("RGB 255 255 255\nARC 100000000 360");
+        let mut program = ProgramState::new(SizingCanvas::new());
+        program.execute(&commands).unwrap();
+        assert!(program.canvas().dimensions().0 > 100_000_000);
+        // each chord is a step, on top of the two instructions
+        let run = |limit| ProgramState::new(SizingCanvas::new()).with_step_limit(Some(limit)).execute(&commands);
+        assert!(run(3602).is_ok());
+        assert!(matches!(run(3601), Err(ExecutionError::StepLimitExceeded { .. })));
+    }
+
+    const SQUARE: &str = "RGB 255 255 255\nMOVE 10 10\nPEND\nBGNF\nMOVE 30 10\nMOVE 30 30\nMOVE 10 30\nMOVE 10 10\n";
+
+    #[test]
+    fn hatching_draws_a_line_every_spacing() {
+        let mut program = ProgramState::new(StatsCanvas::new());
+        program.execute(&parse(&format!("HTCH 0 4\n{}ENDF", SQUARE))).unwrap();
+        // the outline is one stroke, and hatching 4 apart adds a line at y = 10, 14, 18, 22 and 26
+        assert_eq!(program.canvas().pen_lifts(), 1 + 5);
+    }
+
+    #[test]
+    fn solid_fills_stay_inside_the_outline() {
+        // however wide the pen is, and the width is left as it was
+        let mut program = ProgramState::new(PixelCanvas::new(40, 60, 0, 0));
+        program.execute(&parse(&format!("{}PENW 5\nENDF\nPENU\nMOVE 10 50\nPEND\nMOVE 30 50", SQUARE))).unwrap();
+        let canvas = program.canvas();
+        assert_eq!(canvas.pixel(20, 20), Some(Color(255, 255, 255, 255)));
+        assert_eq!(canvas.pixel(8, 20), Some(Color::transparent()));
+        assert_eq!(canvas.pixel(20, 32), Some(Color::transparent()));
+        assert_eq!(canvas.pixel(20, 52), Some(Color(255, 255, 255, 255)));
+    }
+
+    #[test]
+    fn jez_skips_past_the_loop() {
+        // JEZ skips past the loop once the counter runs out, so this only walks twice
+        let text = "RGB 255 255 255\nLOAD R0 2\nLOAD R1 1\nJEZ R0 3 @ side\nWALK 10\nSUB R0 R0 R1\nJUMP side";
+        let mut program = ProgramState::new(StatsCanvas::new());
+        program.execute(&parse(text)).unwrap();
+        assert_eq!(program.canvas().draw_distance(), 20.0);
     }
 }
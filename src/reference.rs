@@ -0,0 +1,130 @@
+/// One instruction as it's written in programs, for `penplot instructions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionInfo {
+    pub mnemonic: &'static str,
+    pub operands: &'static str,
+    pub description: &'static str,
+    // a line using it, which has to parse
+    pub example: &'static str
+}
+
+const fn info(mnemonic: &'static str, operands: &'static str, description: &'static str, example: &'static str) -> InstructionInfo {
+    InstructionInfo { mnemonic, operands, description, example }
+}
+
+/// Every instruction the parser knows, in the order the README lists them. Each example is parsed to check the
+/// table against the parser, so a renamed or removed instruction can't linger here (and the tests below check the
+/// parser against the table, so a new one can't be left out):
+///
+/// ```
+/// use penplot::parsing::{self, ParseErrorKind};
+/// use penplot::reference::INSTRUCTION_SET;
+///
+/// for info in INSTRUCTION_SET {
+///     assert!(info.example.to_uppercase().starts_with(&info.mnemonic.to_uppercase()), "{}", info.mnemonic);
+///     // a block opened or closed on its own, or an include of a file that isn't there, still has to be recognized
+///     let recognized = match parsing::expand_includes(info.example, None) {
+///         Ok((text, _)) => parsing::parse_program(text).map_or_else(
+///             |errors| errors.iter().all(|error| error.kind != ParseErrorKind::Unrecognized),
+///             |program| program.len() <= 1
+///         ),
+///         Err(errors) => matches!(errors[0].kind, ParseErrorKind::IncludeFailed(..))
+///     };
+///     assert!(recognized, "{}", info.example);
+/// }
+/// ```
+pub const INSTRUCTION_SET: &[InstructionInfo] = &[
+    info("NOOP", "", "Do nothing.", "NOOP"),
+    info("MOVE", "x y", "Move the pen to (x, y).", "MOVE 10 -5"),
+    info("LINE", "x y", "Move the pen to (x, y), then face the direction it moved in.", "LINE 10 -5"),
+    info("SHFT", "dx dy", "Move the pen over by (dx, dy).", "SHFT 10 -5"),
+    info("WALK", "d", "Move the pen forward d pixels.", "WALK 10"),
+    info("WLKD", "d s", "Move the pen forward d pixels, leaving a dot every s pixels instead of a line.", "WLKD 100 10"),
+    info("HOME", "", "Move the pen back to (0, 0) without drawing, and face east.", "HOME"),
+    info("FACE", "t", "Set the heading to t degrees.", "FACE 90"),
+    info("FACP", "x y", "Face the point (x, y) without moving.", "FACP 10 -5"),
+    info("TURN", "t", "Turn counterclockwise t degrees.", "TURN 90"),
+    info("ARC", "r t", "Move along an arc of radius r, turning counterclockwise t degrees.", "ARC 10 90"),
+    info("ANGLEMODE", "u", "Measure later angles in DEG, RAD, or GRAD.", "ANGLEMODE RAD"),
+    info("SCAL", "s", "Multiply later relative distances by s.", "SCAL 1.5"),
+    info("MIRR", "", "Swap the direction of later angles.", "MIRR"),
+    info("CIRC", "r", "Draw a circle of radius r around the pen.", "CIRC 10"),
+    info("POLY", "n r", "Draw a regular polygon with n corners, each r from the pen.", "POLY 6 10"),
+    info("RGBA", "r g b a", "Set the pen color to (r, g, b, a).", "RGBA 255 0 0 128"),
+    info("RGB", "r g b", "Set the pen color to (r, g, b, 255).", "RGB 255 0 0"),
+    info("HEX", "#rrggbb", "Set the pen color from hex (#rrggbbaa, #rgb, and #rgba work too).", "HEX #ff8000"),
+    info("LERP", "r1 g1 b1 r2 g2 b2 n i", "Set the pen color to step i of n from one color to another.", "LERP 255 0 0 0 0 255 10 3"),
+    info("ADJC", "dr dg db da", "Add to each channel of the pen color, stopping at 0 and 255.", "ADJC 10 -10 0 0"),
+    info("COLR", "name [light|dark]", "Set the pen color to a CSS named color.", "COLR rebeccapurple"),
+    info("COLOR", "name [light|dark]", "Same as COLR.", "COLOR rebeccapurple dark"),
+    info("BLNK", "", "Set the pen color to (0, 0, 0, 0).", "BLNK"),
+    info("BLND", "mode", "Combine later strokes with what's under them: normal, multiply, screen, or additive.", "BLND multiply"),
+    info("PALD", "i c", "Put color c (hex or a CSS name) in palette slot i.", "PALD 0 #ff0000"),
+    info("PALU", "i", "Set the pen color to the color in palette slot i.", "PALU 0"),
+    info("PENU", "", "Lift the pen, so moving doesn't draw.", "PENU"),
+    info("PEND", "", "Put the pen back down.", "PEND"),
+    info("BLOT", "", "Set the current pixel to the pen color.", "BLOT"),
+    info("PENW", "n", "Set the pen width to n pixels.", "PENW 3"),
+    info("DYNW", "min max", "Scale the pen width from max (short strokes) down to min (long strokes).", "DYNW 1 5"),
+    info("BGNF", "", "Begin tracing a polygon to fill.", "BGNF"),
+    info("ENDF", "", "Fill the polygon traced since BGNF.", "ENDF"),
+    info("HTCH", "t n", "Fill polygons with hatch lines at t degrees, n pixels apart.", "HTCH 45 4"),
+    info("FILL", "", "Flood fill the empty area around the pen.", "FILL"),
+    info("DISC", "r", "Fill a circle of radius r around the pen.", "DISC 10"),
+    info("TEXT", "text", "Write text with its top left corner at the pen.", "TEXT hello"),
+    info("STNC", "", "Begin tracing a stencil polygon.", "STNC"),
+    info("ENDS", "", "Only draw inside the polygon traced since STNC.", "ENDS"),
+    info("CLRS", "", "Remove the stencil.", "CLRS"),
+    info("PUSH", "", "Save the position, heading, and scale ([ works too).", "PUSH"),
+    info("POP", "", "Go back to the last saved position, heading, and scale (] works too).", "POP"),
+    info("GOTO", "label", "Go to a label (or address).", "GOTO 0"),
+    info("JUMP", "n", "Jump ahead n instructions.", "JUMP 0"),
+    info("JEZ", "r n", "Jump ahead n instructions if register r is zero.", "JEZ R0 0"),
+    info("JNZ", "r n", "Jump ahead n instructions if register r isn't zero.", "JNZ R0 0"),
+    info("CALL", "label", "Call the subroutine at a label (or address).", "CALL 0"),
+    info("RTRN", "", "Return from a subroutine.", "RTRN"),
+    info("LOOP", "label n", "Repeat the subroutine at a label (or address) n times.", "LOOP 0 4"),
+    info("REP", "n", "Repeat the lines up to the matching END n times.", "REP 4"),
+    info("END", "", "Close the block opened by REP.", "END"),
+    info("WHNZ", "r", "Repeat the lines up to the matching WEND for as long as register r isn't zero.", "WHNZ R0"),
+    info("WEND", "", "Close the block opened by WHNZ.", "WEND"),
+    info("HALT", "", "Finish executing.", "HALT"),
+    info("LOAD", "r n", "Set register r to n.", "LOAD R0 4"),
+    info("ADD", "r a b", "Set register r to register a + register b.", "ADD R0 R1 R2"),
+    info("SUB", "r a b", "Set register r to register a - register b.", "SUB R0 R1 R2"),
+    info("MUL", "r a b", "Set register r to register a * register b.", "MUL R0 R1 R2"),
+    info("RAND", "r lo hi", "Set register r to a random whole number from lo to hi.", "RAND R0 1 6"),
+    info("PARG", "n", "Pass n as an argument to the next CALL or LOOP.", "PARG 10"),
+    info("ARG", "r i", "Set register r to argument i of the current subroutine.", "ARG R0 0"),
+    info(";", "text", "Comment (@layer name tags the following lines as a layer).", "; hello"),
+    info("/*", "text */", "Block comment, which can span lines and doesn't count towards jumps.", "/* hello */"),
+    info("#include", "\"file\"", "Replace this line with the lines of another file.", "#include \"leaf.pen\"")
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the parser's source, to find every mnemonic it accepts
+    const PARSER: &str = include_str!("parsing.rs");
+
+    // the words passed to the helpers that match mnemonics, like "WALK" in instruction_args("WALK", ...)
+    fn parsed_mnemonics() -> Vec<&'static str> {
+        let helpers = ["instruction_word", "instruction_args", "instruction_args_opt", "parse_named_color", "tag_no_case"];
+        PARSER.match_indices("(\"")
+            .filter(|(start, _)| helpers.iter().any(|helper| PARSER[..*start].ends_with(helper)))
+            .filter_map(|(start, _)| PARSER[start + 2..].split('"').next())
+            // tag_no_case also matches lowercase keywords like "light", which aren't instructions
+            .filter(|word| word.len() > 1 && word.chars().all(|c| c.is_ascii_uppercase()))
+            .collect()
+    }
+
+    #[test]
+    fn every_parsed_mnemonic_has_a_row() {
+        let mnemonics = parsed_mnemonics();
+        assert!(mnemonics.contains(&"WALK") && mnemonics.contains(&"WHNZ"));
+        for mnemonic in mnemonics {
+            assert!(INSTRUCTION_SET.iter().any(|info| info.mnemonic == mnemonic), "{} has no row", mnemonic);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::color::{BlendMode, Color};
+use crate::instruction::{AngleUnit, PALETTE_SIZE, REGISTER_COUNT};
+use crate::program_state::{CallFrame, ProgramSnapshot};
+use crate::turtle::TurtleState;
+use std::fs::{self, File};
+use std::io::Result as IoResult;
+use std::io::Write;
+use std::str::FromStr;
+
+// everything needed to carry on a run later: where the program stopped, and the image it had drawn so far
+// saved as plain text, one "name values..." line per field
+pub struct ResumeState {
+    pub snapshot: ProgramSnapshot,
+    pub offsets: (isize, isize),
+    pub image: String
+}
+
+impl ResumeState {
+    pub fn save(&self, filename: &str) -> IoResult<()> {
+        let turtle = &self.snapshot.turtle;
+        let Color(r, g, b, a) = turtle.color;
+        let mut buffer = File::create(filename)?;
+        writeln!(buffer, "position {} {}", turtle.x, turtle.y)?;
+        writeln!(buffer, "heading {}", turtle.heading)?;
+        writeln!(buffer, "color {} {} {} {}", r, g, b, a)?;
+        writeln!(buffer, "pen {}", if turtle.pen_down { "down" } else { "up" })?;
+        writeln!(buffer, "pen_width {}", turtle.pen_width)?;
+        if let Some((min, max)) = turtle.dynamic_width {
+            writeln!(buffer, "dynamic_width {} {}", min, max)?;
+        }
+        if let Some((angle, spacing)) = self.snapshot.hatch {
+            writeln!(buffer, "hatch {} {}", angle, spacing)?;
+        }
+        // frames are separated by commas, since each can have any number of arguments after its first three values
+        let call_stack: Vec<String> = self.snapshot.call_stack.iter()
+            .map(|frame| {
+                let args: String = frame.args.iter().map(|arg| format!(" {}", arg)).collect();
+                format!("{} {} {}{}", frame.return_pc, frame.body_pc, frame.remaining, args)
+            })
+            .collect();
+        writeln!(buffer, "call_stack {}", call_stack.join(", "))?;
+        let pending_args: Vec<String> = self.snapshot.pending_args.iter().map(|arg| arg.to_string()).collect();
+        writeln!(buffer, "pending_args {}", pending_args.join(" "))?;
+        let turtle_stack: Vec<String> = self.snapshot.turtle_stack.iter()
+            .map(|(x, y, heading, scale, handedness)| format!("{} {} {} {} {}", x, y, heading, scale, handedness))
+            .collect();
+        writeln!(buffer, "turtle_stack {}", turtle_stack.join(" "))?;
+        let registers: Vec<String> = self.snapshot.registers.iter().map(|value| value.to_string()).collect();
+        writeln!(buffer, "registers {}", registers.join(" "))?;
+        // only the slots that have been defined, as index and color pairs
+        let palette: Vec<String> = self.snapshot.palette.iter().enumerate()
+            .filter_map(|(index, color)| color.map(|color| format!("{} {}", index, color)))
+            .collect();
+        writeln!(buffer, "palette {}", palette.join(" "))?;
+        writeln!(buffer, "rng {}", self.snapshot.rng_state)?;
+        writeln!(buffer, "angle_unit {}", self.snapshot.angle_unit)?;
+        writeln!(buffer, "scale {}", self.snapshot.scale)?;
+        writeln!(buffer, "handedness {}", self.snapshot.handedness)?;
+        writeln!(buffer, "blend_mode {}", self.snapshot.blend_mode)?;
+        writeln!(buffer, "program_counter {}", self.snapshot.program_counter)?;
+        writeln!(buffer, "offsets {} {}", self.offsets.0, self.offsets.1)?;
+        // the filename goes last on its line, so it can contain spaces
+        writeln!(buffer, "image {}", self.image)?;
+        Ok(())
+    }
+
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("could not read {} ({})", filename, e))?;
+        let mut turtle = TurtleState {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            color: Color::transparent(),
+            pen_down: true,
+            pen_width: 1,
+            dynamic_width: None
+        };
+        let (mut hatch, mut call_stack, mut turtle_stack, mut program_counter) = (None, vec![], vec![], 0);
+        let mut pending_args = vec![];
+        let mut registers = [0; REGISTER_COUNT];
+        let mut palette = [None; PALETTE_SIZE];
+        let mut rng_state = 1;
+        let mut angle_unit = AngleUnit::Degrees;
+        let mut scale = 1.0;
+        let mut handedness = 1.0;
+        let mut blend_mode = BlendMode::Normal;
+        let mut offsets = (0, 0);
+        let mut image = None;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match name {
+                "position" => (turtle.x, turtle.y) = pair(rest)?,
+                "heading" => turtle.heading = single(rest)?,
+                "color" => match values::<u8>(rest)?[..] {
+                    [r, g, b, a] => turtle.color = Color(r, g, b, a),
+                    _ => return Err(format!("expected four values in '{}'", line))
+                },
+                "pen" => turtle.pen_down = rest.trim() == "down",
+                "pen_width" => turtle.pen_width = single(rest)?,
+                "dynamic_width" => turtle.dynamic_width = Some(pair(rest)?),
+                "hatch" => hatch = Some(pair(rest)?),
+                "call_stack" => {
+                    call_stack = rest.split(',').filter(|entry| !entry.trim().is_empty()).map(|entry| {
+                        let entry: Vec<&str> = entry.split_whitespace().collect();
+                        if entry.len() < 3 {
+                            return Err(format!("expected return, body and remaining count for each entry in '{}'", line));
+                        }
+                        let counts: Vec<usize> = values(&entry[..3].join(" "))?;
+                        let args = values(&entry[3..].join(" "))?;
+                        Ok(CallFrame { return_pc: counts[0], body_pc: counts[1], remaining: counts[2], args })
+                    }).collect::<Result<_, String>>()?;
+                }
+                "pending_args" => pending_args = values(rest)?,
+                "turtle_stack" => {
+                    let values: Vec<f32> = values(rest)?;
+                    if !values.len().is_multiple_of(5) {
+                        return Err(format!("expected x, y, heading, scale and handedness for each entry in '{}'", line));
+                    }
+                    turtle_stack = values.chunks_exact(5).map(|entry| (entry[0], entry[1], entry[2], entry[3], entry[4])).collect();
+                }
+                "registers" => {
+                    registers = values(rest)?.try_into()
+                        .map_err(|_| format!("expected {} registers in '{}'", REGISTER_COUNT, line))?;
+                }
+                "palette" => {
+                    let entries: Vec<&str> = rest.split_whitespace().collect();
+                    if !entries.len().is_multiple_of(2) {
+                        return Err(format!("expected index and color for each entry in '{}'", line));
+                    }
+                    for entry in entries.chunks_exact(2) {
+                        let color = Color::from_hex(entry[1]).ok_or_else(|| format!("invalid color '{}'", entry[1]))?;
+                        let slot = entry[0].parse::<usize>().ok().and_then(|index| palette.get_mut(index))
+                            .ok_or_else(|| format!("invalid palette index '{}'", entry[0]))?;
+                        *slot = Some(color);
+                    }
+                }
+                "rng" => rng_state = single(rest)?,
+                "angle_unit" => angle_unit = AngleUnit::from_name(rest.trim())
+                    .ok_or_else(|| format!("unknown angle unit '{}'", rest.trim()))?,
+                "scale" => scale = single(rest)?,
+                "handedness" => handedness = single(rest)?,
+                "blend_mode" => blend_mode = BlendMode::from_name(rest.trim())
+                    .ok_or_else(|| format!("unknown blend mode '{}'", rest.trim()))?,
+                "program_counter" => program_counter = single(rest)?,
+                "offsets" => offsets = pair(rest)?,
+                "image" => image = Some(rest.to_string()),
+                _ => return Err(format!("unknown state '{}'", name))
+            }
+        }
+        let image = image.ok_or_else(|| format!("{} doesn't say which image to resume", filename))?;
+        Ok(ResumeState {
+            snapshot: ProgramSnapshot { turtle, hatch, call_stack, pending_args, turtle_stack, registers, palette, rng_state, angle_unit, scale, handedness, blend_mode, program_counter },
+            offsets,
+            image
+        })
+    }
+}
+
+fn values<T: FromStr>(text: &str) -> Result<Vec<T>, String> {
+    text.split_whitespace()
+        .map(|value| value.parse::<T>().map_err(|_| format!("invalid value '{}'", value)))
+        .collect()
+}
+
+fn single<T: FromStr + Copy>(text: &str) -> Result<T, String> {
+    match values(text)?[..] {
+        [value] => Ok(value),
+        _ => Err(format!("expected one value in '{}'", text))
+    }
+}
+
+fn pair<T: FromStr + Copy>(text: &str) -> Result<(T, T), String> {
+    match values(text)?[..] {
+        [a, b] => Ok((a, b)),
+        _ => Err(format!("expected two values in '{}'", text))
+    }
+}
@@ -0,0 +1,289 @@
+use crate::canvas::DrawingCanvas;
+use crate::color::Color;
+
+// segments at least this long are drawn at the minimum dynamic width, and shorter ones scale up towards the maximum
+// this is in pixels, so it's picked against the usual stroke: a program's steps are mostly somewhere around 1 to 30
+// pixels, so 64 keeps those spread across the whole range, with only long runs across the page thinned all the way
+pub const DYNAMIC_WIDTH_LENGTH: f32 = 64.0;
+
+// everything about a turtle except its canvas, so it can be saved and picked up again later
+#[derive(Clone, Copy, Debug)]
+pub struct TurtleState {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32, // in radians
+    pub color: Color,
+    pub pen_down: bool,
+    pub pen_width: usize,
+    pub dynamic_width: Option<(usize, usize)>
+}
+
+/// A turtle that draws on a canvas, for scripting drawings directly instead of building instructions.
+/// This is also what `ProgramState` uses to carry out its movement instructions.
+///
+/// ```
+/// use penplot::canvas::PixelCanvas;
+/// use penplot::color::Color;
+/// use penplot::turtle::Turtle;
+///
+/// let mut canvas = PixelCanvas::new(11, 11, 0, 0);
+/// let mut turtle = Turtle::new(&mut canvas);
+/// turtle.set_color(Color(255, 255, 255, 255));
+/// for _ in 0..4 {
+///     turtle.forward(10.0);
+///     turtle.turn(90.0);
+/// }
+/// assert_ne!(canvas.pixel(10, 0), Some(Color::transparent()));
+/// assert_eq!(canvas.pixel(5, 5), Some(Color::transparent()));
+/// ```
+pub struct Turtle<C: DrawingCanvas> {
+    x: f32,
+    y: f32,
+    heading: f32, // in radians
+    color: Color,
+    pen_down: bool,
+    pen_width: usize,
+    // pen width range to scale between by segment length, if enabled
+    dynamic_width: Option<(usize, usize)>,
+    canvas: C
+}
+
+impl<C: DrawingCanvas> Turtle<C> {
+    pub fn new(canvas: C) -> Self {
+        Turtle {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            color: Color::transparent(),
+            pen_down: true,
+            pen_width: 1,
+            dynamic_width: None,
+            canvas
+        }
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn is_pen_down(&self) -> bool {
+        self.pen_down
+    }
+
+    // whether moving would actually put anything on the canvas
+    pub fn is_drawing(&self) -> bool {
+        self.pen_down && self.color != Color::transparent()
+    }
+
+    pub fn state(&self) -> TurtleState {
+        TurtleState {
+            x: self.x,
+            y: self.y,
+            heading: self.heading,
+            color: self.color,
+            pen_down: self.pen_down,
+            pen_width: self.pen_width,
+            dynamic_width: self.dynamic_width
+        }
+    }
+
+    // put the turtle back how a saved state left it, moving there without drawing
+    pub fn restore(&mut self, state: &TurtleState) {
+        self.heading = state.heading;
+        self.color = state.color;
+        self.set_pen_width(state.pen_width);
+        self.dynamic_width = state.dynamic_width;
+        self.pen_up();
+        self.canvas.move_pen_to(state.x, state.y);
+        self.x = state.x;
+        self.y = state.y;
+        if state.pen_down {
+            self.pen_down();
+        }
+    }
+
+    pub fn canvas(&self) -> &C {
+        &self.canvas
+    }
+
+    pub fn canvas_mut(&mut self) -> &mut C {
+        &mut self.canvas
+    }
+
+    pub fn into_canvas(self) -> C {
+        self.canvas
+    }
+
+    // move to (x, y), drawing a line if the pen is down
+    pub fn goto(&mut self, x: f32, y: f32) {
+        if let Some((min, max)) = self.dynamic_width {
+            // fast (long) strokes are thin, slow (short) strokes are thick
+            let length = (x - self.x).hypot(y - self.y);
+            let t = (length / DYNAMIC_WIDTH_LENGTH).min(1.0);
+            let width = max as f32 + (min as f32 - max as f32) * t;
+            self.canvas.set_pen_width(width.round() as usize);
+        }
+        self.canvas.move_pen_to(x, y);
+        self.x = x;
+        self.y = y;
+    }
+
+    // move by (dx, dy)
+    pub fn shift(&mut self, dx: f32, dy: f32) {
+        self.goto(self.x + dx, self.y + dy);
+    }
+
+    // move along the current heading
+    pub fn forward(&mut self, distance: f32) {
+        let dx = distance * self.heading.cos();
+        let dy = distance * self.heading.sin();
+        self.goto(self.x + dx, self.y + dy);
+    }
+
+    /// Move to (x, y) like `goto`, then face the way the turtle went. Going nowhere leaves the heading alone.
+    ///
+    /// ```
+    /// use penplot::canvas::SizingCanvas;
+    /// use penplot::turtle::Turtle;
+    ///
+    /// let mut turtle = Turtle::new(SizingCanvas::new());
+    /// turtle.line_to(10.0, 10.0);
+    /// assert!((turtle.state().heading.to_degrees() - 45.0).abs() < 1e-4);
+    /// turtle.line_to(10.0, 10.0);
+    /// assert!((turtle.state().heading.to_degrees() - 45.0).abs() < 1e-4);
+    /// ```
+    pub fn line_to(&mut self, x: f32, y: f32) {
+        self.face_toward(x, y);
+        self.goto(x, y);
+    }
+
+    /// Turn to face (x, y) without moving. Facing the point the turtle is already on leaves the heading alone.
+    ///
+    /// ```
+    /// use penplot::canvas::SizingCanvas;
+    /// use penplot::turtle::Turtle;
+    ///
+    /// let mut turtle = Turtle::new(SizingCanvas::new());
+    /// turtle.goto(5.0, 5.0);
+    /// turtle.face_toward(15.0, 15.0);
+    /// assert!((turtle.state().heading.to_degrees() - 45.0).abs() < 1e-4);
+    /// assert_eq!((turtle.state().x, turtle.state().y), (5.0, 5.0));
+    /// turtle.face_toward(5.0, 5.0);
+    /// assert!((turtle.state().heading.to_degrees() - 45.0).abs() < 1e-4);
+    /// ```
+    pub fn face_toward(&mut self, x: f32, y: f32) {
+        let (dx, dy) = (x - self.x, y - self.y);
+        if dx != 0.0 || dy != 0.0 {
+            self.heading = dy.atan2(dx);
+        }
+    }
+
+    // move to (x, y) without drawing, leaving the pen as it was
+    pub fn travel_to(&mut self, x: f32, y: f32) {
+        let pen_down = self.pen_down;
+        self.pen_up();
+        self.goto(x, y);
+        if pen_down {
+            self.pen_down();
+        }
+    }
+
+    // set heading, in degrees
+    pub fn face(&mut self, degrees: f32) {
+        self.heading = degrees.to_radians();
+    }
+
+    // turn counterclockwise, in degrees
+    pub fn turn(&mut self, degrees: f32) {
+        self.heading += degrees.to_radians();
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+        if self.pen_down {
+            self.canvas.set_color(color);
+        }
+    }
+
+    // set a fixed pen width, turning off dynamic width
+    pub fn set_pen_width(&mut self, width: usize) {
+        self.pen_width = width;
+        self.dynamic_width = None;
+        self.canvas.set_pen_width(width);
+    }
+
+    /// Have the pen width follow the length of each stroke, from max for the shortest down to min for strokes
+    /// [`DYNAMIC_WIDTH_LENGTH`] or longer, so fast strokes come out thin like a real pen's:
+    ///
+    /// ```
+    /// use penplot::canvas::PixelCanvas;
+    /// use penplot::color::Color;
+    /// use penplot::turtle::Turtle;
+    ///
+    /// // how many pixels tall the stroke is at column x
+    /// let thickness = |canvas: &PixelCanvas, x: usize| {
+    ///     (0..canvas.height()).filter(|&y| canvas.pixel(x, y) != Some(Color::transparent())).count()
+    /// };
+    /// let mut canvas = PixelCanvas::new(100, 60, 0, 0);
+    /// let mut turtle = Turtle::new(&mut canvas);
+    /// turtle.set_color(Color(255, 255, 255, 255));
+    /// turtle.set_dynamic_width(Some((1, 9)));
+    /// turtle.pen_up();
+    /// turtle.goto(10.0, 15.0);
+    /// turtle.pen_down();
+    /// turtle.goto(14.0, 15.0);
+    /// turtle.pen_up();
+    /// turtle.goto(10.0, 45.0);
+    /// turtle.pen_down();
+    /// turtle.goto(90.0, 45.0);
+    /// // the long stroke is as thin as the pen goes, and the short one is much thicker
+    /// assert_eq!(thickness(&canvas, 50), 1);
+    /// assert!(thickness(&canvas, 12) >= 8);
+    /// ```
+    pub fn set_dynamic_width(&mut self, range: Option<(usize, usize)>) {
+        self.dynamic_width = range;
+    }
+
+    // lifting the pen is done by giving the canvas a transparent pen, so the real color is kept for later
+    pub fn pen_up(&mut self) {
+        self.pen_down = false;
+        self.canvas.set_color(Color::transparent());
+    }
+
+    pub fn pen_down(&mut self) {
+        self.pen_down = true;
+        self.canvas.set_color(self.color);
+    }
+
+    // draw a single dot at the current position
+    pub fn blot(&mut self) {
+        if self.pen_down {
+            self.canvas.blot(self.x, self.y);
+        }
+    }
+
+    // flood fill the empty area around the current position
+    pub fn fill(&mut self) {
+        if self.pen_down {
+            self.canvas.fill(self.x, self.y);
+        }
+    }
+
+    // draw a circle around the current position (or fill it in), without moving
+    pub fn circle(&mut self, radius: f32, filled: bool) {
+        if self.pen_down {
+            self.canvas.draw_circle(self.x, self.y, radius.abs(), filled);
+        }
+    }
+
+    // write text in the built-in font, with its top left corner at the current position
+    pub fn text(&mut self, text: &str) {
+        if self.pen_down {
+            self.canvas.draw_text(self.x, self.y, text, 1);
+        }
+    }
+}
@@ -12,4 +12,67 @@ pub fn replace<T: Clone + Eq + Hash>(input: Vec<T>, rules: &HashMap<T, Vec<T>>)
         }
     }
     result
-}
\ No newline at end of file
+}
+
+// 4x4 ordered (bayer) dither matrix
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// how far through [0, 1) a value's fraction has to be to round up at pixel (x, y), for ordered dithering
+// the thresholds are spread evenly through each 4x4 tile, so a whole tile rounds up as often as its fraction says
+pub fn bayer_threshold(x: usize, y: usize) -> f32 {
+    (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0
+}
+
+// x coordinates where the horizontal line at y crosses the edges of a polygon, sorted left to right
+// the inside of the polygon is between each pair of crossings
+// edges include their lower end but not their upper one, so vertices aren't counted twice
+pub fn scanline_crossings(polygon: &[(f32, f32)], y: f32) -> Vec<f32> {
+    let mut crossings = vec![];
+    for (index, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(index + 1) % polygon.len()];
+        if (y0 <= y && y < y1) || (y1 <= y && y < y0) {
+            crossings.push(x0 + (y - y0) * (x1 - x0) / (y1 - y0));
+        }
+    }
+    crossings.sort_by(|a, b| a.total_cmp(b));
+    crossings
+}
+
+// small xorshift random number generator, so that anything random comes out the same given the same seed
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed so nearby seeds give different sequences (and keep the state nonzero, or xorshift gets stuck)
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    // the generator's internal state, which from_state picks back up exactly where it left off
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_state(state: u64) -> Self {
+        // a zero state would only ever give zeroes
+        Rng(if state == 0 { 1 } else { state })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // between lo and hi, including both ends (the bias from taking a remainder is too small to matter here)
+    pub fn next_in_range(&mut self, lo: isize, hi: isize) -> isize {
+        let (lo, hi) = (lo.min(hi) as i128, lo.max(hi) as i128);
+        (lo + self.next_u64() as i128 % (hi - lo + 1)) as isize
+    }
+}